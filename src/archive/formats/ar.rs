@@ -1,23 +1,71 @@
 //! Implementation of the Unix [ar] file format.
 //!
+//! There is no single standard for the format, and the variants differ in how
+//! they store names longer than 16 bytes. The GNU variant stores them in a
+//! special `//` member, while the BSD variant stores them at the start of the
+//! member data. Both variants can be read and written.
+//!
+//! Static libraries usually begin with a symbol table member, named `/` in the
+//! GNU variant and `__.SYMDEF` in the BSD variant, which indexes the symbols
+//! defined by each object file for linkers. These are not files of their own,
+//! so they are skipped when reading.
+//!
 //! [ar]: https://en.wikipedia.org/wiki/Ar_(Unix)
 
 use crate::{
-    archive::{ArchiveReader, Entry, EntryType, Metadata},
+    archive::{ArchiveReader, ArchiveWriter, Entry, EntryType, Metadata},
+    compress::formats::CompressionOptions,
+    format::Format,
     input::Input,
+    output::Output,
 };
 use chrono::prelude::*;
 use std::{
     borrow::Cow,
+    convert::{TryFrom, TryInto},
     fmt,
-    io::{Read, Result, Seek},
-    path::{Path, PathBuf},
+    io::{self, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write},
+    path::Path,
+    str::FromStr,
 };
+use tempfile::SpooledTempFile;
+
+const GLOBAL_HEADER: &[u8] = b"!<arch>\n";
+const THIN_GLOBAL_HEADER: &[u8] = b"!<thin>\n";
+const HEADER_LEN: usize = 60;
+const HEADER_TERMINATOR: &[u8] = b"`\n";
+
+const GNU_SYMBOL_TABLE_NAME: &[u8] = b"/";
+const GNU_SYMBOL_TABLE_64_NAME: &[u8] = b"/SYM64/";
+const GNU_NAME_TABLE_NAME: &[u8] = b"//";
+const BSD_SYMBOL_TABLE_NAME: &[u8] = b"__.SYMDEF";
+
+/// Length that the BSD symbol table name is padded to when written, so that the
+/// table data is aligned.
+const BSD_SYMBOL_TABLE_NAME_LEN: usize = 12;
+
+/// Prefix of names in the BSD variant that are stored in the member data,
+/// followed by the length of the name.
+const BSD_LONG_NAME_PREFIX: &[u8] = b"#1/";
+
+/// Maximum size of a member name, the same as `PATH_MAX` on Linux.
+const MAX_NAME_SIZE: u64 = 4096;
+
+/// Maximum size of the GNU name table, which is held in memory.
+const MAX_NAME_TABLE_SIZE: u64 = 16 * 1024 * 1024;
+
+const MODE_FILE: u32 = 0o100000;
+const DEFAULT_FILE_MODE: u32 = 0o644;
+
+/// Maximum size of member data to hold in memory before spilling to disk. All
+/// of the data has to be held until the archive is finished, since the symbol
+/// and name tables come before it.
+const SCRATCH_MEMORY_LIMIT: usize = 8 * 1024 * 1024;
 
 /// Format provider for AR.
 pub struct Ar;
 
-impl super::Format for Ar {
+impl Format for Ar {
     fn id(&self) -> &str {
         "ar"
     }
@@ -39,50 +87,1017 @@ impl fmt::Display for Ar {
 
 impl super::ArchiveFormat for Ar {
     fn open<'r>(&self, input: Input<'r>) -> Result<Box<dyn ArchiveReader + 'r>> {
-        Ok(Box::new(ArReader::new(input)))
+        Ok(Box::new(ArReader {
+            reader: input,
+            started: false,
+            position: 0,
+            header: None,
+            remaining: 0,
+            names: Vec::new(),
+        }))
+    }
+
+    fn create(
+        &self,
+        output: Output,
+        options: &super::ArchiveOptions,
+        _compression_options: &CompressionOptions,
+    ) -> Result<Box<dyn ArchiveWriter>> {
+        Ok(Box::new(ArWriter {
+            output,
+            format: options.ar_format,
+            deterministic: options.ar_deterministic,
+            symbol_table: options.ar_symbol_table,
+            members: Vec::new(),
+            data: SpooledTempFile::new(SCRATCH_MEMORY_LIMIT),
+        }))
     }
 }
 
-pub struct ArReader<R: Read + Seek> {
-    archive: ar::Archive<R>,
+/// Variants of the format that archives can be created in.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ArFormat {
+    #[default]
+    Gnu,
+    Bsd,
 }
 
-impl<R: Read + Seek> ArReader<R> {
-    fn new(reader: R) -> Self {
-        Self {
-            archive: ar::Archive::new(reader),
+impl FromStr for ArFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "gnu" => Ok(ArFormat::Gnu),
+            "bsd" => Ok(ArFormat::Bsd),
+            _ => Err(format!("unknown ar format: {}", s)),
         }
     }
 }
 
-impl<R: Read + Seek> ArchiveReader for ArReader<R> {
+fn corrupt(message: impl fmt::Display) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("corrupt ar archive: {}", message))
+}
+
+/// Check whether a member name is that of a symbol table in any variant.
+fn is_symbol_table(name: &[u8]) -> bool {
+    name == GNU_SYMBOL_TABLE_NAME
+        || name == GNU_SYMBOL_TABLE_64_NAME
+        || name.starts_with(BSD_SYMBOL_TABLE_NAME)
+}
+
+/// Parse a numeric header field, which is padded with spaces and may be empty.
+fn parse_field(field: &[u8], radix: u32) -> Result<u64> {
+    let field = trim_end(field, b' ');
+
+    if field.is_empty() {
+        return Ok(0);
+    }
+
+    std::str::from_utf8(field)
+        .ok()
+        .and_then(|s| u64::from_str_radix(s, radix).ok())
+        .ok_or_else(|| corrupt(format_args!("invalid header field {:?}", String::from_utf8_lossy(field))))
+}
+
+fn trim_end(bytes: &[u8], padding: u8) -> &[u8] {
+    let len = bytes.iter().rposition(|&b| b != padding).map_or(0, |index| index + 1);
+
+    &bytes[..len]
+}
+
+/// Fields of a member header.
+struct Header {
+    name: Vec<u8>,
+    mtime: u64,
+    mode: u32,
+    size: u64,
+}
+
+struct ArReader<'r> {
+    reader: Input<'r>,
+    started: bool,
+
+    /// Number of bytes read so far, used to find padding.
+    position: u64,
+
+    /// Header of the member last returned, if any.
+    header: Option<Header>,
+
+    /// Number of bytes of data of the current member not yet read.
+    remaining: u64,
+
+    /// Long names of members, as stored in the GNU variant.
+    names: Vec<u8>,
+}
+
+impl<'r> ArReader<'r> {
+    /// Read enough bytes to fill the buffer, returning false if the end of the
+    /// archive is reached before any bytes are read.
+    fn read_exact_or_end(&mut self, buf: &mut [u8]) -> Result<bool> {
+        let len = crate::io::read_full(&mut self.reader, buf)?;
+        self.position += len as u64;
+
+        match len {
+            0 if !buf.is_empty() => Ok(false),
+            len if len == buf.len() => Ok(true),
+            _ => Err(corrupt("unexpected end of archive")),
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        if buf.is_empty() || self.read_exact_or_end(buf)? {
+            Ok(())
+        } else {
+            Err(corrupt("unexpected end of archive"))
+        }
+    }
+
+    /// Read the given number of bytes, only allocating as much as is actually
+    /// read in case the length is wrong.
+    fn read_data(&mut self, len: u64) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        (&mut self.reader).take(len).read_to_end(&mut data)?;
+        self.position += data.len() as u64;
+
+        if (data.len() as u64) < len {
+            return Err(corrupt("unexpected end of archive"));
+        }
+
+        Ok(data)
+    }
+
+    fn skip(&mut self, len: u64) -> Result<()> {
+        let skipped = io::copy(&mut (&mut self.reader).take(len), &mut io::sink())?;
+        self.position += skipped;
+
+        if skipped < len {
+            return Err(corrupt("unexpected end of archive"));
+        }
+
+        Ok(())
+    }
+
+    fn read_global_header(&mut self) -> Result<()> {
+        let mut magic = [0; 8];
+        self.read_exact(&mut magic)?;
+
+        match &magic[..] {
+            GLOBAL_HEADER => Ok(()),
+            THIN_GLOBAL_HEADER => Err(Error::other("thin ar archives are not supported")),
+            _ => Err(corrupt("invalid global header")),
+        }
+    }
+
+    /// Read the next member header, if there is one, resolving its name.
+    fn read_header(&mut self) -> Result<Option<Header>> {
+        // Member data is padded to an even length, though some writers omit the
+        // padding after the last member.
+        if self.position % 2 == 1 && !self.read_exact_or_end(&mut [0])? {
+            return Ok(None);
+        }
+
+        let mut fields = [0; HEADER_LEN];
+
+        if !self.read_exact_or_end(&mut fields)? {
+            return Ok(None);
+        }
+
+        if &fields[58..] != HEADER_TERMINATOR {
+            return Err(corrupt("invalid member header"));
+        }
+
+        let mut size = parse_field(&fields[48..58], 10)?;
+        let raw_name = trim_end(&fields[..16], b' ');
+
+        let name = if raw_name.starts_with(BSD_LONG_NAME_PREFIX) {
+            let len = parse_field(&raw_name[BSD_LONG_NAME_PREFIX.len()..], 10)?;
+            size = size.checked_sub(len).ok_or_else(|| corrupt("name is longer than member"))?;
+
+            if len > MAX_NAME_SIZE {
+                return Err(corrupt(format_args!("name size of {} bytes is too large", len)));
+            }
+
+            let mut name = self.read_data(len)?;
+            name.truncate(trim_end(&name, 0).len());
+            name
+        } else if raw_name.len() > 1 && raw_name[0] == b'/' && raw_name[1..].iter().all(u8::is_ascii_digit) {
+            let offset = parse_field(&raw_name[1..], 10)? as usize;
+            let name = self.names.get(offset..)
+                .ok_or_else(|| corrupt("name offset is out of range"))?;
+            let end = name.iter().position(|&b| b == b'\n').unwrap_or(name.len());
+
+            trim_end(&name[..end], b'/').to_vec()
+        } else if raw_name == GNU_SYMBOL_TABLE_NAME || raw_name == GNU_SYMBOL_TABLE_64_NAME || raw_name == GNU_NAME_TABLE_NAME {
+            raw_name.to_vec()
+        } else {
+            // GNU names are terminated with a slash, so that they may contain
+            // spaces.
+            raw_name.strip_suffix(b"/").unwrap_or(raw_name).to_vec()
+        };
+
+        Ok(Some(Header {
+            name,
+            mtime: parse_field(&fields[16..28], 10)?,
+            mode: parse_field(&fields[40..48], 8)? as u32,
+            size,
+        }))
+    }
+
+    /// Count the members in the archive that are files, without disturbing the
+    /// current position.
+    fn count_entries(&mut self) -> Result<u64> {
+        let position = self.reader.stream_position()?;
+        let mut offset = GLOBAL_HEADER.len() as u64;
+        let mut count = 0;
+
+        loop {
+            self.reader.seek(SeekFrom::Start(offset))?;
+
+            let mut fields = [0; HEADER_LEN];
+
+            match self.reader.read_exact(&mut fields) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+
+            let size = parse_field(&fields[48..58], 10)?;
+            let raw_name = trim_end(&fields[..16], b' ');
+
+            // Only the start of a long BSD name is needed to tell if it is a
+            // symbol table.
+            let mut name = [0; 16];
+            let name = if raw_name.starts_with(BSD_LONG_NAME_PREFIX) {
+                let len = self.reader.read(&mut name)?;
+                &name[..len]
+            } else {
+                raw_name
+            };
+
+            if !is_symbol_table(name) && name != GNU_NAME_TABLE_NAME {
+                count += 1;
+            }
+
+            offset += HEADER_LEN as u64 + size + size % 2;
+        }
+
+        self.reader.seek(SeekFrom::Start(position))?;
+
+        Ok(count)
+    }
+}
+
+impl<'r> ArchiveReader for ArReader<'r> {
     fn len(&mut self) -> Option<u64> {
-        self.archive.count_entries().ok().map(|l| l as u64)
+        self.count_entries().ok()
     }
 
     fn entry(&mut self) -> Result<Option<Box<dyn Entry + '_>>> {
-        Ok(self.archive.next_entry().transpose()?.map(|e| Box::new(e) as Box<dyn Entry + '_>))
+        if !self.started {
+            self.read_global_header()?;
+            self.started = true;
+        }
+
+        // Skip over whatever is left of the previously returned member.
+        if self.header.take().is_some() {
+            self.skip(self.remaining)?;
+            self.remaining = 0;
+        }
+
+        loop {
+            let header = match self.read_header()? {
+                Some(header) => header,
+                None => return Ok(None),
+            };
+
+            if header.name == GNU_NAME_TABLE_NAME {
+                if header.size > MAX_NAME_TABLE_SIZE {
+                    return Err(corrupt(format_args!("name table size of {} bytes is too large", header.size)));
+                }
+
+                self.names = self.read_data(header.size)?;
+                continue;
+            }
+
+            if is_symbol_table(&header.name) {
+                log::debug!("skipping ar symbol table {}", String::from_utf8_lossy(&header.name));
+                self.skip(header.size)?;
+                continue;
+            }
+
+            self.remaining = header.size;
+            self.header = Some(header);
+
+            return Ok(Some(Box::new(ArEntry {
+                reader: self,
+            })));
+        }
     }
 }
 
-impl<'r, R: Read + Seek> Entry for ar::Entry<'r, R> {
+struct ArEntry<'a, 'r> {
+    reader: &'a mut ArReader<'r>,
+}
+
+impl<'a, 'r> ArEntry<'a, 'r> {
+    fn header(&self) -> &Header {
+        self.reader.header.as_ref().unwrap()
+    }
+}
+
+impl<'a, 'r> Entry for ArEntry<'a, 'r> {
     fn path(&self) -> Cow<'_, Path> {
-        match String::from_utf8_lossy(self.header().identifier()) {
-            Cow::Borrowed(s) => Cow::Borrowed(Path::new(s)),
-            Cow::Owned(s) => Cow::Owned(PathBuf::from(s)),
-        }
+        crate::paths::path_from_unix_path_bytes(Cow::Borrowed(&self.header().name))
     }
 
     fn metadata(&self) -> Metadata {
+        let header = self.header();
+
         Metadata::builder()
             .entry_type(EntryType::File)
-            .size(self.header().size())
-            .modified(if self.header().mtime() > 0 {
-                Some(Local.timestamp(self.header().mtime() as i64, 0))
+            .size(header.size)
+            .modified(if header.mtime > 0 {
+                Local.timestamp_opt(header.mtime as i64, 0).single()
             } else {
                 None
             })
-            .unix_mode(Some(self.header().mode()))
+            .unix_mode(Some(header.mode & 0o7777))
             .build()
     }
 }
+
+impl<'a, 'r> Read for ArEntry<'a, 'r> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let reader = &mut *self.reader;
+
+        if reader.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = buf.len().min(reader.remaining.try_into().unwrap_or(usize::MAX));
+        let read = reader.reader.read(&mut buf[..len])?;
+
+        if read == 0 {
+            return Err(corrupt("unexpected end of archive"));
+        }
+
+        reader.position += read as u64;
+        reader.remaining -= read as u64;
+
+        Ok(read)
+    }
+}
+
+/// A member to be written once the archive is finished.
+struct Member {
+    name: Vec<u8>,
+    mtime: u64,
+    mode: u32,
+    size: u64,
+
+    /// Global symbols defined by the member, if it is an object file.
+    symbols: Vec<Vec<u8>>,
+}
+
+pub struct ArWriter {
+    output: Output,
+    format: ArFormat,
+
+    /// Record zero for all timestamps and fixed permissions, so that archives
+    /// of the same files are identical.
+    deterministic: bool,
+
+    symbol_table: bool,
+    members: Vec<Member>,
+
+    /// Data of all members added so far, one after another.
+    data: SpooledTempFile,
+}
+
+/// The name of a member as it is written.
+struct EncodedName {
+    /// Name as stored in the header.
+    header: Vec<u8>,
+
+    /// Anything stored before the member data, counted in its size.
+    prefix: Vec<u8>,
+}
+
+impl ArWriter {
+    /// Encode the name of each member, along with the contents of the GNU name
+    /// table.
+    fn encode_names(&self) -> (Vec<EncodedName>, Vec<u8>) {
+        let mut table = Vec::new();
+
+        let names = self.members.iter()
+            .map(|member| match self.format {
+                ArFormat::Gnu if member.name.len() < 16 => EncodedName {
+                    header: [&member.name[..], b"/"].concat(),
+                    prefix: Vec::new(),
+                },
+                ArFormat::Gnu => {
+                    let header = format!("/{}", table.len()).into_bytes();
+                    table.extend_from_slice(&member.name);
+                    table.extend_from_slice(b"/\n");
+
+                    EncodedName {
+                        header,
+                        prefix: Vec::new(),
+                    }
+                }
+                ArFormat::Bsd if member.name.len() <= 16 && !member.name.contains(&b' ') => EncodedName {
+                    header: member.name.clone(),
+                    prefix: Vec::new(),
+                },
+                ArFormat::Bsd => {
+                    // The name is padded with null bytes so that the member
+                    // data is aligned.
+                    let mut prefix = member.name.clone();
+                    prefix.resize((HEADER_LEN + prefix.len()).div_ceil(8) * 8 - HEADER_LEN, 0);
+
+                    EncodedName {
+                        header: [BSD_LONG_NAME_PREFIX, prefix.len().to_string().as_bytes()].concat(),
+                        prefix,
+                    }
+                }
+            })
+            .collect();
+
+        (names, table)
+    }
+
+    /// Encode the symbol table, given the offsets of the headers of each
+    /// member. Returns the name of the table member and its data.
+    fn encode_symbol_table(&self, offsets: &[u64], wide: bool) -> Result<(Vec<u8>, Vec<u8>)> {
+        let symbols = self.members.iter()
+            .zip(offsets)
+            .flat_map(|(member, &offset)| member.symbols.iter().map(move |symbol| (symbol, offset)))
+            .collect::<Vec<_>>();
+
+        let too_large = || Error::new(ErrorKind::InvalidInput, "archive is too large for a symbol table");
+        let mut data = Vec::new();
+
+        match self.format {
+            // A count of symbols, followed by the offset of the member defining
+            // each one, followed by their names, with all numbers big-endian.
+            ArFormat::Gnu if wide => {
+                data.extend_from_slice(&(symbols.len() as u64).to_be_bytes());
+
+                for (_, offset) in &symbols {
+                    data.extend_from_slice(&offset.to_be_bytes());
+                }
+            }
+            ArFormat::Gnu => {
+                data.extend_from_slice(&u32::try_from(symbols.len()).map_err(|_| too_large())?.to_be_bytes());
+
+                for (_, offset) in &symbols {
+                    data.extend_from_slice(&u32::try_from(*offset).map_err(|_| too_large())?.to_be_bytes());
+                }
+            }
+
+            // The size of an array of pairs of name and member offsets,
+            // followed by the array, followed by the size of the names and the
+            // names, with all numbers little-endian.
+            ArFormat::Bsd => {
+                let names_len = symbols.iter().map(|(symbol, _)| symbol.len() + 1).sum::<usize>();
+                let mut name_offset = 0;
+
+                data.extend_from_slice(&u32::try_from(symbols.len() * 8).map_err(|_| too_large())?.to_le_bytes());
+
+                for (symbol, offset) in &symbols {
+                    data.extend_from_slice(&u32::try_from(name_offset).map_err(|_| too_large())?.to_le_bytes());
+                    data.extend_from_slice(&u32::try_from(*offset).map_err(|_| too_large())?.to_le_bytes());
+                    name_offset += symbol.len() + 1;
+                }
+
+                data.extend_from_slice(&u32::try_from(names_len).map_err(|_| too_large())?.to_le_bytes());
+            }
+        }
+
+        for (symbol, _) in &symbols {
+            data.extend_from_slice(symbol);
+            data.push(0);
+        }
+
+        // The table itself is padded, to an even length for GNU and to a
+        // multiple of four bytes for BSD.
+        let alignment = if self.format == ArFormat::Gnu { 2 } else { 4 };
+        data.resize(data.len().div_ceil(alignment) * alignment, 0);
+
+        let name = match self.format {
+            ArFormat::Gnu if wide => GNU_SYMBOL_TABLE_64_NAME,
+            ArFormat::Gnu => GNU_SYMBOL_TABLE_NAME,
+            ArFormat::Bsd => BSD_SYMBOL_TABLE_NAME,
+        };
+
+        Ok((name.to_vec(), data))
+    }
+
+    /// Get the offset of the header of each member, given the sizes of the
+    /// members that come before them.
+    fn member_offsets(&self, names: &[EncodedName], mut offset: u64) -> Vec<u64> {
+        self.members.iter()
+            .zip(names)
+            .map(|(member, name)| {
+                let member_offset = offset;
+                let size = name.prefix.len() as u64 + member.size;
+                offset += HEADER_LEN as u64 + size + size % 2;
+                member_offset
+            })
+            .collect()
+    }
+
+    /// Write a member header and any data stored before the member data. The
+    /// size given is that of the member data alone.
+    fn write_header(&mut self, name: &[u8], prefix: &[u8], mtime: u64, mode: u32, size: u64) -> Result<()> {
+        let size = prefix.len() as u64 + size;
+        let field = |value: u64, width: usize, radix: u32, what: &str| {
+            let value = if radix == 8 {
+                format!("{:o}", value)
+            } else {
+                value.to_string()
+            };
+
+            if value.len() <= width {
+                Ok(format!("{:<width$}", value, width = width))
+            } else {
+                Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} of {} is too large for an ar archive", what, String::from_utf8_lossy(name)),
+                ))
+            }
+        };
+
+        let fields = vec![
+            field(mtime, 12, 10, "modification time"),
+            field(0, 6, 10, "user ID"),
+            field(0, 6, 10, "group ID"),
+            field(mode.into(), 8, 8, "mode"),
+            field(size, 10, 10, "size"),
+        ].into_iter().collect::<Result<String>>()?;
+
+        self.output.write_all(name)?;
+        self.output.write_all(&[b' '; 16][name.len()..])?;
+        self.output.write_all(fields.as_bytes())?;
+        self.output.write_all(HEADER_TERMINATOR)?;
+        self.output.write_all(prefix)?;
+
+        Ok(())
+    }
+
+    fn pad(&mut self, size: u64) -> Result<()> {
+        if size % 2 == 1 {
+            self.output.write_all(b"\n")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ArchiveWriter for ArWriter {
+    fn add_directory(&mut self, path: &Path, _metadata: Metadata) -> Result<()> {
+        log::debug!("skipping directory {}, ar archives cannot contain directories", path.display());
+
+        Ok(())
+    }
+
+    fn add_file(&mut self, path: &Path, metadata: Metadata, file: &mut dyn Read) -> Result<()> {
+        if metadata.entry_type != EntryType::File {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not a regular file, which ar archives cannot contain", path.display()),
+            ));
+        }
+
+        // Members are only identified by their file name.
+        let name = path.file_name()
+            .map(|name| crate::paths::path_to_unix_path_bytes(Path::new(name)).into_owned())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("{} has no file name", path.display())))?;
+
+        if name.contains(&b'\n') {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} cannot be stored in an ar archive", path.display()),
+            ));
+        }
+
+        let start = self.data.stream_position()?;
+        let size = io::copy(file, &mut self.data)?;
+
+        // Object files are small, so just read them back in full to find their
+        // symbols.
+        let symbols = if self.symbol_table {
+            let mut data = Vec::new();
+            self.data.seek(SeekFrom::Start(start))?;
+            self.data.read_to_end(&mut data)?;
+
+            defined_symbols(&data)
+        } else {
+            Vec::new()
+        };
+
+        let (mtime, mode) = if self.deterministic {
+            (0, DEFAULT_FILE_MODE)
+        } else {
+            (
+                // Timestamps before the UNIX epoch can't be represented, so
+                // clamp them.
+                metadata.modified.map(|dt| dt.timestamp().max(0) as u64).unwrap_or(0),
+                MODE_FILE | metadata.unix_mode.map(|mode| mode & 0o7777).unwrap_or(DEFAULT_FILE_MODE),
+            )
+        };
+
+        self.members.push(Member {
+            name,
+            mtime,
+            mode,
+            size,
+            symbols,
+        });
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let (names, name_table) = self.encode_names();
+
+        // Offsets in the GNU variant only fit in 32 bits for archives up to
+        // 4 GiB, after which a wider symbol table is needed.
+        let mut wide = false;
+
+        let offsets = loop {
+            // The symbol table comes first, then the name table, then the
+            // members.
+            let mut offset = GLOBAL_HEADER.len() as u64;
+
+            if self.symbol_table {
+                // The size of the symbol table doesn't depend on the offsets it
+                // contains, so it can be used to find them.
+                let (_, data) = self.encode_symbol_table(&vec![0; self.members.len()], wide)?;
+                let name_len = if self.format == ArFormat::Bsd { BSD_SYMBOL_TABLE_NAME_LEN } else { 0 };
+                let size = (name_len + data.len()) as u64;
+                offset += HEADER_LEN as u64 + size + size % 2;
+            }
+
+            if !name_table.is_empty() {
+                let size = name_table.len() as u64;
+                offset += HEADER_LEN as u64 + size + size % 2;
+            }
+
+            let offsets = self.member_offsets(&names, offset);
+
+            if self.symbol_table && self.format == ArFormat::Gnu && !wide && offsets.last().is_some_and(|&last| last > u32::MAX.into()) {
+                wide = true;
+                continue;
+            }
+
+            break offsets;
+        };
+
+        self.output.write_all(GLOBAL_HEADER)?;
+
+        if self.symbol_table {
+            let (name, data) = self.encode_symbol_table(&offsets, wide)?;
+
+            match self.format {
+                ArFormat::Gnu => self.write_header(&name, &[], 0, 0, data.len() as u64)?,
+                ArFormat::Bsd => {
+                    // The name is stored before the data.
+                    let mut prefix = name;
+                    prefix.resize(BSD_SYMBOL_TABLE_NAME_LEN, 0);
+                    let name = [BSD_LONG_NAME_PREFIX, BSD_SYMBOL_TABLE_NAME_LEN.to_string().as_bytes()].concat();
+
+                    self.write_header(&name, &prefix, 0, 0, data.len() as u64)?;
+                }
+            }
+
+            self.output.write_all(&data)?;
+            self.pad(data.len() as u64)?;
+        }
+
+        if !name_table.is_empty() {
+            // Only the size is given for the name table.
+            self.output.write_all(GNU_NAME_TABLE_NAME)?;
+            self.output.write_all(&[b' '; 48][GNU_NAME_TABLE_NAME.len()..])?;
+            self.output.write_all(format!("{:<10}", name_table.len()).as_bytes())?;
+            self.output.write_all(HEADER_TERMINATOR)?;
+            self.output.write_all(&name_table)?;
+            self.pad(name_table.len() as u64)?;
+        }
+
+        self.data.seek(SeekFrom::Start(0))?;
+
+        for (member, name) in std::mem::take(&mut self.members).into_iter().zip(names) {
+            self.write_header(&name.header, &name.prefix, member.mtime, member.mode, member.size)?;
+
+            let copied = io::copy(&mut (&mut self.data).take(member.size), &mut self.output)?;
+
+            if copied < member.size {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "member data is shorter than expected"));
+            }
+
+            self.pad(name.prefix.len() as u64 + member.size)?;
+        }
+
+        self.output.finish()
+    }
+}
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const MACH_O_MAGIC: &[u8] = &[0xce, 0xfa, 0xed, 0xfe];
+const MACH_O_64_MAGIC: &[u8] = &[0xcf, 0xfa, 0xed, 0xfe];
+
+const SHT_SYMTAB: u32 = 2;
+const SHN_UNDEF: u16 = 0;
+const STB_GLOBAL: u8 = 1;
+const STB_WEAK: u8 = 2;
+const STB_GNU_UNIQUE: u8 = 10;
+
+const LC_SYMTAB: u32 = 2;
+const N_STAB: u8 = 0xe0;
+const N_TYPE: u8 = 0x0e;
+const N_EXT: u8 = 0x01;
+const N_UNDF: u8 = 0x00;
+
+/// Get the names of the global symbols defined by an ELF or Mach-O object file.
+/// Anything else, including malformed object files, defines no symbols.
+fn defined_symbols(data: &[u8]) -> Vec<Vec<u8>> {
+    let symbols = if data.starts_with(ELF_MAGIC) {
+        elf_symbols(data)
+    } else if data.starts_with(MACH_O_MAGIC) || data.starts_with(MACH_O_64_MAGIC) {
+        mach_o_symbols(data)
+    } else {
+        None
+    };
+
+    symbols.unwrap_or_default()
+}
+
+fn elf_symbols(data: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let is_64 = match *data.get(4)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+
+    let bytes = Bytes {
+        data,
+        big_endian: *data.get(5)? == 2,
+    };
+
+    let (section_headers, section_header_len, section_count) = if is_64 {
+        (bytes.u64(0x28)?, bytes.u16(0x3a)?, bytes.u16(0x3c)?)
+    } else {
+        (bytes.u32(0x20)?.into(), bytes.u16(0x2e)?, bytes.u16(0x30)?)
+    };
+
+    let section = |index: u32| section_headers.checked_add(u64::from(index) * u64::from(section_header_len));
+    let mut symbols = Vec::new();
+
+    for index in 0..section_count {
+        let header = section(index.into())?;
+
+        if bytes.u32(header + 4)? != SHT_SYMTAB {
+            continue;
+        }
+
+        let (offset, size, link, entry_len) = if is_64 {
+            (bytes.u64(header + 0x18)?, bytes.u64(header + 0x20)?, bytes.u32(header + 0x28)?, bytes.u64(header + 0x38)?)
+        } else {
+            (bytes.u32(header + 0x10)?.into(), bytes.u32(header + 0x14)?.into(), bytes.u32(header + 0x18)?, bytes.u32(header + 0x24)?.into())
+        };
+
+        let strings = if is_64 {
+            bytes.u64(section(link)? + 0x18)?
+        } else {
+            bytes.u32(section(link)? + 0x10)?.into()
+        };
+
+        if entry_len == 0 {
+            return None;
+        }
+
+        // The first symbol is always a null symbol.
+        for symbol in (offset..offset.checked_add(size)?).step_by(entry_len as usize).skip(1) {
+            let (name, info, section_index) = if is_64 {
+                (bytes.u32(symbol)?, bytes.u8(symbol + 4)?, bytes.u16(symbol + 6)?)
+            } else {
+                (bytes.u32(symbol)?, bytes.u8(symbol + 12)?, bytes.u16(symbol + 14)?)
+            };
+
+            if section_index != SHN_UNDEF && matches!(info >> 4, STB_GLOBAL | STB_WEAK | STB_GNU_UNIQUE) {
+                symbols.push(bytes.c_str(strings + u64::from(name))?.to_vec());
+            }
+        }
+    }
+
+    Some(symbols)
+}
+
+fn mach_o_symbols(data: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let is_64 = data.starts_with(MACH_O_64_MAGIC);
+    let bytes = Bytes {
+        data,
+        big_endian: false,
+    };
+
+    let mut command = if is_64 { 32 } else { 28 };
+    let mut symbols = Vec::new();
+
+    for _ in 0..bytes.u32(16)? {
+        let command_len = bytes.u32(command + 4)?;
+
+        if bytes.u32(command)? == LC_SYMTAB {
+            let offset = u64::from(bytes.u32(command + 8)?);
+            let count = u64::from(bytes.u32(command + 12)?);
+            let strings = u64::from(bytes.u32(command + 16)?);
+            let entry_len = if is_64 { 16 } else { 12 };
+
+            for index in 0..count {
+                let symbol = offset + index * entry_len;
+                let symbol_type = bytes.u8(symbol + 4)?;
+
+                if symbol_type & N_STAB == 0 && symbol_type & N_EXT != 0 && symbol_type & N_TYPE != N_UNDF {
+                    symbols.push(bytes.c_str(strings + u64::from(bytes.u32(symbol)?))?.to_vec());
+                }
+            }
+        }
+
+        if command_len == 0 {
+            return None;
+        }
+
+        command += u64::from(command_len);
+    }
+
+    Some(symbols)
+}
+
+/// Bounds-checked access to the fields of an object file.
+struct Bytes<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Bytes<'a> {
+    fn get<const N: usize>(&self, offset: u64) -> Option<[u8; N]> {
+        let offset = usize::try_from(offset).ok()?;
+
+        self.data.get(offset..offset.checked_add(N)?)?.try_into().ok()
+    }
+
+    fn u8(&self, offset: u64) -> Option<u8> {
+        self.get::<1>(offset).map(|[b]| b)
+    }
+
+    fn u16(&self, offset: u64) -> Option<u16> {
+        let bytes = self.get(offset)?;
+
+        Some(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(&self, offset: u64) -> Option<u32> {
+        let bytes = self.get(offset)?;
+
+        Some(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn u64(&self, offset: u64) -> Option<u64> {
+        let bytes = self.get(offset)?;
+
+        Some(if self.big_endian { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) })
+    }
+
+    /// Get a null-terminated string.
+    fn c_str(&self, offset: u64) -> Option<&'a [u8]> {
+        let bytes = self.data.get(usize::try_from(offset).ok()?..)?;
+
+        Some(&bytes[..bytes.iter().position(|&b| b == 0)?])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{formats::ArchiveOptions, ArchiveFormat};
+    use std::fs;
+    use structopt::StructOpt;
+
+    // The fixtures were created deterministically by GNU ar (`ar rcSD` and
+    // `ar rcsD`) and by `llvm-ar --format=bsd`.
+    const GNU: &[u8] = include_bytes!("../../../tests/fixtures/sample-gnu.a");
+    const BSD: &[u8] = include_bytes!("../../../tests/fixtures/sample-bsd.a");
+    const SYMBOLS: &[u8] = include_bytes!("../../../tests/fixtures/sample-symbols.a");
+
+    const HELLO: &[u8] = b"hello from a fixture\n";
+    const LONG_NAME: &str = "a_long_member_name.txt";
+    const LONG_CONTENTS: &[u8] = b"a member with a name longer than fifteen bytes\n";
+
+    fn read_all(bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut reader = Ar.open(Input::from_reader(bytes)?)?;
+        let mut members = Vec::new();
+
+        while let Some(mut entry) = reader.entry()? {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            members.push((entry.path().to_string_lossy().into_owned(), data));
+        }
+
+        Ok(members)
+    }
+
+    fn write_all(args: &[&str], members: &[(String, Vec<u8>)]) -> Vec<u8> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.a");
+        let options = ArchiveOptions::from_iter(args);
+        let compression_options = CompressionOptions::from_iter(&["test"]);
+        let mut writer = Ar.create(Output::create(&path).unwrap(), &options, &compression_options).unwrap();
+
+        for (name, data) in members {
+            let metadata = Metadata::builder()
+                .entry_type(EntryType::File)
+                .size(data.len() as u64)
+                .modified(Local.timestamp_opt(1_622_548_800, 0).single())
+                .unix_mode(Some(0o100600))
+                .build();
+
+            writer.add_file(Path::new(name), metadata, &mut &data[..]).unwrap();
+        }
+
+        writer.finish().unwrap();
+
+        fs::read(path).unwrap()
+    }
+
+    #[test]
+    fn read_fixtures() {
+        let expected = vec![
+            ("hello.txt".to_owned(), HELLO.to_vec()),
+            (LONG_NAME.to_owned(), LONG_CONTENTS.to_vec()),
+        ];
+
+        assert_eq!(read_all(GNU).unwrap(), expected);
+        assert_eq!(read_all(BSD).unwrap(), expected);
+
+        // The symbol table is not returned as a member of its own.
+        let members = read_all(SYMBOLS).unwrap();
+        assert_eq!(members.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["symbol.o", "hello.txt"]);
+        assert_eq!(members[1].1, HELLO);
+    }
+
+    #[test]
+    fn deterministic_output_matches_gnu_ar() {
+        assert_eq!(write_all(&["test", "--ar-deterministic"], &read_all(GNU).unwrap()), GNU);
+        assert_eq!(write_all(&["test", "--ar-deterministic", "--ar-symbol-table"], &read_all(SYMBOLS).unwrap()), SYMBOLS);
+    }
+
+    #[test]
+    fn round_trip() {
+        let members = vec![
+            ("odd".to_owned(), b"odd length".to_vec()),
+            ("name with spaces".to_owned(), Vec::new()),
+            (LONG_NAME.to_owned(), LONG_CONTENTS.to_vec()),
+            ("sixteen_bytes.oo".to_owned(), HELLO.to_vec()),
+        ];
+
+        for format in &["gnu", "bsd"] {
+            let bytes = write_all(&["test", "--ar-format", format], &members);
+            assert_eq!(read_all(&bytes).unwrap(), members, "{} variant", format);
+
+            let mut reader = Ar.open(Input::from_reader(&bytes[..]).unwrap()).unwrap();
+            let metadata = reader.entry().unwrap().unwrap().metadata();
+            assert_eq!(metadata.modified.map(|dt| dt.timestamp()), Some(1_622_548_800));
+            assert_eq!(metadata.unix_mode, Some(0o600));
+        }
+    }
+
+    #[test]
+    fn truncated_archive_is_an_error() {
+        // Cutting the archive off anywhere but between members must be
+        // reported. The padding after the data of the last member is optional.
+        let hello_end = GLOBAL_HEADER.len() + HEADER_LEN + 24 + HEADER_LEN + HELLO.len();
+        let complete = (0..=GNU.len())
+            .filter(|len| read_all(&GNU[..*len]).is_ok())
+            .collect::<Vec<_>>();
+
+        assert_eq!(complete, [
+            GLOBAL_HEADER.len(),
+            GLOBAL_HEADER.len() + HEADER_LEN + 24,
+            hello_end,
+            hello_end + 1,
+            GNU.len() - 1,
+            GNU.len(),
+        ]);
+        assert!(read_all(b"!<thin>\n").is_err());
+    }
+
+    #[test]
+    fn oversized_names_are_rejected() {
+        for (name, size) in &[("#1/99999999", 99_999_999u64), ("//", 9_999_999_999)] {
+            let header = format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", name, 0, 0, 0, 644, size);
+            let bytes = [GLOBAL_HEADER, header.as_bytes(), b"short"].concat();
+
+            assert!(read_all(&bytes).is_err(), "{} member", name);
+        }
+    }
+}
@@ -1,27 +1,70 @@
 //! The CPIO family of file formats.
 //!
 //! According to the [man page][cpio(5)], there are a few different variants of
-//! the CPIO format. The `cpio` crate used here only implements the "New ASCII"
-//! format (newc/SVR4).
+//! the CPIO format. All of them can be read: the old binary format, the
+//! portable ASCII format (odc), and the "New ASCII" format (newc/SVR4) with or
+//! without checksums (crc). Archives can be written in any of the ASCII
+//! variants.
+//!
+//! Linux initramfs images consist of multiple archives concatenated together,
+//! possibly with compression starting part of the way through, so reading
+//! continues past the end of an archive and presents them all as one.
 //!
 //! [cpio(5)]: https://www.freebsd.org/cgi/man.cgi?query=cpio&sektion=5
 
 use crate::{
-    archive::{ArchiveReader, Entry, EntryType, Metadata},
+    archive::{ArchiveReader, ArchiveWriter, Entry, EntryType, Metadata},
+    compress::{self, formats::CompressionOptions},
+    format::Format,
     input::Input,
+    output::Output,
 };
 use chrono::prelude::*;
 use std::{
     borrow::Cow,
+    collections::{hash_map, HashMap},
+    convert::{TryFrom, TryInto},
     fmt,
-    io::{Read, Result, Seek},
+    io::{self, BufRead, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write},
+    mem,
     path::{Path, PathBuf},
+    str::FromStr,
 };
+use tempfile::SpooledTempFile;
+
+const NEWC_MAGIC: &[u8] = b"070701";
+const CRC_MAGIC: &[u8] = b"070702";
+const ODC_MAGIC: &[u8] = b"070707";
+
+/// Magic number of the old binary format, which is stored in the byte order of
+/// the machine that created the archive.
+const BINARY_MAGIC: u16 = 0o070707;
+
+/// Name of the special entry marking the end of an archive.
+const TRAILER: &[u8] = b"TRAILER!!!";
+
+const MODE_FILE: u32 = 0o100000;
+const MODE_DIRECTORY: u32 = 0o040000;
+const MODE_SYMLINK: u32 = 0o120000;
+const MODE_CHARACTER_DEVICE: u32 = 0o020000;
+const MODE_BLOCK_DEVICE: u32 = 0o060000;
+const MODE_FIFO: u32 = 0o010000;
+
+const DEFAULT_FILE_MODE: u32 = 0o644;
+const DEFAULT_DIRECTORY_MODE: u32 = 0o755;
+
+/// Maximum size of an entry name including the terminating null byte, the
+/// same as `PATH_MAX` on Linux.
+const MAX_NAME_SIZE: u64 = 4096;
+
+/// Maximum size of file data to hold in memory when computing checksums for
+/// the crc variant, before spilling to disk.
+const SCRATCH_MEMORY_LIMIT: usize = 8 * 1024 * 1024;
 
 /// Format provider for CPIO archives.
 pub struct Cpio;
 
-impl super::Format for Cpio {
+impl Format for Cpio {
     fn id(&self) -> &str {
         "cpio"
     }
@@ -31,73 +74,914 @@ impl super::Format for Cpio {
     }
 
     fn match_bytes(&self, bytes: &[u8]) -> bool {
-        matches!(bytes, [0x30, 0x37, 0x30, 0x37, 0x30, 0x31, ..])
+        bytes.starts_with(NEWC_MAGIC)
+            || bytes.starts_with(CRC_MAGIC)
+            || bytes.starts_with(ODC_MAGIC)
+            || bytes.starts_with(&BINARY_MAGIC.to_le_bytes())
+            || bytes.starts_with(&BINARY_MAGIC.to_be_bytes())
     }
 }
 
 impl fmt::Display for Cpio {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str("cpio (newc)")
+        f.write_str("cpio")
     }
 }
 
 impl super::ArchiveFormat for Cpio {
     fn open<'r>(&self, input: Input<'r>) -> Result<Box<dyn ArchiveReader + 'r>> {
         Ok(Box::new(CpioReader {
-            init: Some(input),
-            entry: None,
+            reader: input,
+            position: 0,
+            header: None,
+            remaining: 0,
+            checksum: 0,
+            done: false,
+            links: HashMap::new(),
+        }))
+    }
+
+    fn create(
+        &self,
+        output: Output,
+        options: &super::ArchiveOptions,
+        _compression_options: &CompressionOptions,
+    ) -> Result<Box<dyn ArchiveWriter>> {
+        Ok(Box::new(CpioWriter {
+            output,
+            format: options.cpio_format,
+            position: 0,
+            next_ino: 1,
+            files: HashMap::new(),
         }))
     }
 }
 
-struct CpioReader<R: Read> {
-    init: Option<R>,
-    entry: Option<cpio::newc::Reader<R>>,
+/// Variants of the format that archives can be created in.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CpioFormat {
+    #[default]
+    Newc,
+    Crc,
+    Odc,
 }
 
-impl<R: Read> ArchiveReader for CpioReader<R> {
-    fn entry(&mut self) -> Result<Option<Box<dyn Entry + '_>>> {
-        // Finish previously returned entry, if any.
-        let reader = if let Some(previous) = self.entry.take() {
-            previous.finish()?
-        } else if let Some(reader) = self.init.take() {
-            reader
+impl FromStr for CpioFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "newc" => Ok(CpioFormat::Newc),
+            "crc" => Ok(CpioFormat::Crc),
+            "odc" => Ok(CpioFormat::Odc),
+            _ => Err(format!("unknown cpio format: {}", s)),
+        }
+    }
+}
+
+fn corrupt(message: impl fmt::Display) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("corrupt cpio archive: {}", message))
+}
+
+/// Variants of the format that archives can be read in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Variant {
+    Binary { big_endian: bool },
+    Odc,
+    Newc,
+    Crc,
+}
+
+impl Variant {
+    /// Get the alignment in bytes of headers and file data.
+    fn alignment(self) -> u64 {
+        match self {
+            Variant::Binary { .. } => 2,
+            Variant::Odc => 1,
+            Variant::Newc | Variant::Crc => 4,
+        }
+    }
+}
+
+/// Fields of an entry header common to all variants.
+struct Header {
+    variant: Variant,
+    ino: u32,
+    mode: u32,
+    nlink: u32,
+    mtime: u64,
+    file_size: u64,
+    dev: (u32, u32),
+    rdev: (u32, u32),
+
+    /// For the crc variant, the sum of all bytes of the file data.
+    check: u32,
+
+    name: Vec<u8>,
+}
+
+impl Header {
+    fn path(&self) -> Cow<'_, Path> {
+        crate::paths::path_from_unix_path_bytes(Cow::Borrowed(&self.name))
+    }
+}
+
+/// Split a device number in the format used by odc and binary headers, which
+/// only have room for 8-bit minor numbers.
+fn split_device(dev: u64) -> (u32, u32) {
+    ((dev >> 8) as u32, (dev & 0xff) as u32)
+}
+
+fn parse_number(field: &[u8], radix: u32) -> Result<u64> {
+    std::str::from_utf8(field)
+        .ok()
+        .and_then(|s| u64::from_str_radix(s, radix).ok())
+        .ok_or_else(|| corrupt(format_args!("invalid header field {:?}", String::from_utf8_lossy(field))))
+}
+
+struct CpioReader<'r> {
+    reader: Input<'r>,
+
+    /// Number of bytes read so far, used to find padding.
+    position: u64,
+
+    /// Header of the entry last returned, if any.
+    header: Option<Header>,
+
+    /// Number of bytes of file data of the current entry not yet read.
+    remaining: u64,
+
+    /// Sum of the bytes of file data read so far, for the crc variant.
+    checksum: u32,
+
+    done: bool,
+
+    /// Paths of entries seen so far that have multiple links, keyed by their
+    /// device and inode numbers.
+    ///
+    /// Hard links in CPIO are represented by multiple entries sharing the same
+    /// inode. The first entry seen is treated as the original file and any
+    /// later entries as links to it.
+    links: HashMap<(u32, u32, u32), Vec<u8>>,
+}
+
+impl<'r> CpioReader<'r> {
+    /// Read enough bytes to fill the buffer, returning false if the end of the
+    /// archive is reached before any bytes are read.
+    fn read_exact_or_end(&mut self, buf: &mut [u8]) -> Result<bool> {
+        let len = crate::io::read_full(&mut self.reader, buf)?;
+        self.position += len as u64;
+
+        match len {
+            0 if !buf.is_empty() => Ok(false),
+            len if len == buf.len() => Ok(true),
+            _ => Err(corrupt("unexpected end of archive")),
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        if buf.is_empty() || self.read_exact_or_end(buf)? {
+            Ok(())
         } else {
+            Err(corrupt("unexpected end of archive"))
+        }
+    }
+
+    fn skip(&mut self, len: u64) -> Result<()> {
+        let skipped = io::copy(&mut (&mut self.reader).take(len), &mut io::sink())?;
+        self.position += skipped;
+
+        if skipped < len {
+            return Err(corrupt("unexpected end of archive"));
+        }
+
+        Ok(())
+    }
+
+    /// Skip any padding up to the given alignment.
+    fn align(&mut self, alignment: u64) -> Result<()> {
+        self.skip((alignment - self.position % alignment) % alignment)
+    }
+
+    /// Advance to the next archive following a trailer, returning false if
+    /// there isn't one.
+    ///
+    /// Archives may be separated by null padding. Once a compressed archive is
+    /// found, everything after it is read through the decoder, so nothing
+    /// following the end of the compressed stream will be seen.
+    fn next_segment(&mut self) -> Result<bool> {
+        loop {
+            let buf = self.reader.fill_buf()?;
+
+            if buf.is_empty() {
+                return Ok(false);
+            }
+
+            let zeros = buf.iter().take_while(|&&b| b == 0).count();
+
+            if zeros == 0 {
+                break;
+            }
+
+            self.reader.consume(zeros);
+        }
+
+        if !Cpio.match_bytes(self.reader.fill_buf()?) {
+            let input = mem::replace(&mut self.reader, Input::from_reader(io::empty())?);
+            let (input, detected) = compress::detect_decode(input)?;
+            self.reader = input;
+
+            if detected.is_empty() || !Cpio.match_bytes(self.reader.fill_buf()?) {
+                log::warn!("ignoring unrecognized data after cpio archive trailer");
+                return Ok(false);
+            }
+        }
+
+        // Each archive is aligned relative to its own start, and inode numbers
+        // are only unique within a single archive.
+        self.position = 0;
+        self.links.clear();
+
+        Ok(true)
+    }
+
+    /// Read the next entry header, if there is one.
+    fn read_header(&mut self) -> Result<Option<Header>> {
+        let mut magic = [0; 6];
+
+        // Tolerate archives missing a trailer.
+        if !self.read_exact_or_end(&mut magic[..2])? {
+            log::debug!("cpio archive ended without a trailer");
             return Ok(None);
+        }
+
+        let variant = if magic[..2] == BINARY_MAGIC.to_le_bytes() {
+            Variant::Binary { big_endian: false }
+        } else if magic[..2] == BINARY_MAGIC.to_be_bytes() {
+            Variant::Binary { big_endian: true }
+        } else {
+            self.read_exact(&mut magic[2..])?;
+
+            match &magic[..] {
+                NEWC_MAGIC => Variant::Newc,
+                CRC_MAGIC => Variant::Crc,
+                ODC_MAGIC => Variant::Odc,
+                _ => return Err(corrupt("invalid header magic")),
+            }
+        };
+
+        let mut header = Header {
+            variant,
+            ino: 0,
+            mode: 0,
+            nlink: 0,
+            mtime: 0,
+            file_size: 0,
+            dev: (0, 0),
+            rdev: (0, 0),
+            check: 0,
+            name: Vec::new(),
+        };
+
+        let name_size = match variant {
+            Variant::Binary { big_endian } => {
+                let mut fields = [0; 24];
+                self.read_exact(&mut fields)?;
+
+                let words = fields.chunks_exact(2)
+                    .map(|word| if big_endian {
+                        u16::from_be_bytes([word[0], word[1]])
+                    } else {
+                        u16::from_le_bytes([word[0], word[1]])
+                    })
+                    .collect::<Vec<_>>();
+
+                // 32-bit values are stored as two words, most significant first.
+                let long = |index: usize| (u32::from(words[index]) << 16 | u32::from(words[index + 1])) as u64;
+
+                header.dev = split_device(words[0].into());
+                header.ino = words[1].into();
+                header.mode = words[2].into();
+                header.nlink = words[5].into();
+                header.rdev = split_device(words[6].into());
+                header.mtime = long(7);
+                header.file_size = long(10);
+
+                words[9] as u64
+            }
+
+            Variant::Odc => {
+                let mut fields = [0; 70];
+                self.read_exact(&mut fields)?;
+
+                let field = |offset: usize, len: usize| parse_number(&fields[offset..offset + len], 8);
+
+                header.dev = split_device(field(0, 6)?);
+                header.ino = field(6, 6)? as u32;
+                header.mode = field(12, 6)? as u32;
+                header.nlink = field(30, 6)? as u32;
+                header.rdev = split_device(field(36, 6)?);
+                header.mtime = field(42, 11)?;
+                header.file_size = field(59, 11)?;
+
+                field(53, 6)?
+            }
+
+            Variant::Newc | Variant::Crc => {
+                let mut fields = [0; 104];
+                self.read_exact(&mut fields)?;
+
+                let field = |index: usize| parse_number(&fields[index * 8..index * 8 + 8], 16).map(|n| n as u32);
+
+                header.ino = field(0)?;
+                header.mode = field(1)?;
+                header.nlink = field(4)?;
+                header.mtime = field(5)?.into();
+                header.file_size = field(6)?.into();
+                header.dev = (field(7)?, field(8)?);
+                header.rdev = (field(9)?, field(10)?);
+                header.check = field(12)?;
+
+                field(11)?.into()
+            }
         };
 
-        let reader = cpio::newc::Reader::new(reader)?;
+        if name_size > MAX_NAME_SIZE {
+            return Err(corrupt(format_args!("name size of {} bytes is too large", name_size)));
+        }
+
+        // The name includes a terminating null byte.
+        let mut name = vec![0; name_size as usize];
+        self.read_exact(&mut name)?;
+        self.align(variant.alignment())?;
+
+        if let Some(end) = name.iter().position(|&b| b == 0) {
+            name.truncate(end);
+        }
 
-        // If we reach a special entry named `TRAILER!!!`, then we've reached
-        // the end of the archive.
-        if reader.entry().name() == "TRAILER!!!" {
+        header.name = name;
+
+        Ok(Some(header))
+    }
+}
+
+impl<'r> ArchiveReader for CpioReader<'r> {
+    fn entry(&mut self) -> Result<Option<Box<dyn Entry + '_>>> {
+        if self.done {
             return Ok(None);
         }
 
-        self.entry = Some(reader);
-        Ok(self.entry.as_mut().map(|r| Box::new(r) as Box<dyn Entry>))
+        // Skip over whatever is left of the previously returned entry.
+        if let Some(previous) = self.header.take() {
+            self.skip(self.remaining)?;
+            self.remaining = 0;
+            self.align(previous.variant.alignment())?;
+        }
+
+        let header = loop {
+            let header = match self.read_header()? {
+                Some(header) => header,
+                None => {
+                    self.done = true;
+                    return Ok(None);
+                }
+            };
+
+            // If we reach a special entry named `TRAILER!!!`, then we've
+            // reached the end of the archive, though another one might follow.
+            if header.name != TRAILER {
+                break header;
+            }
+
+            if !self.next_segment()? {
+                self.done = true;
+                return Ok(None);
+            }
+        };
+
+        let mut link_target = None;
+
+        if header.nlink > 1 && EntryType::from_unix_mode(header.mode) != EntryType::Directory {
+            match self.links.entry((header.dev.0, header.dev.1, header.ino)) {
+                hash_map::Entry::Occupied(entry) => link_target = Some(entry.get().clone()),
+                hash_map::Entry::Vacant(entry) => {
+                    entry.insert(header.name.clone());
+                }
+            }
+        }
+
+        self.remaining = header.file_size;
+        self.checksum = 0;
+        self.header = Some(header);
+
+        Ok(Some(Box::new(CpioEntry {
+            reader: self,
+            link_target,
+        })))
     }
 }
 
-impl<'r, R: Read> Entry for &'r mut cpio::newc::Reader<R> {
+struct CpioEntry<'a, 'r> {
+    reader: &'a mut CpioReader<'r>,
+
+    /// If this entry is a hard link, the path of the entry it links to.
+    link_target: Option<Vec<u8>>,
+}
+
+impl<'a, 'r> CpioEntry<'a, 'r> {
+    fn header(&self) -> &Header {
+        self.reader.header.as_ref().unwrap()
+    }
+}
+
+impl<'a, 'r> Entry for CpioEntry<'a, 'r> {
     fn path(&self) -> Cow<'_, Path> {
-        Cow::Borrowed(Path::new(self.entry().name()))
+        self.header().path()
     }
 
     fn metadata(&self) -> Metadata {
+        let header = self.header();
+        let entry_type = if self.link_target.is_some() {
+            EntryType::HardLink
+        } else {
+            EntryType::from_unix_mode(header.mode)
+        };
+
         Metadata::builder()
-            .entry_type(match self.entry().mode() & 0o0170000 {
-                0o0100000 => EntryType::File,
-                0o0040000 => EntryType::Directory,
-                _ => todo!("account for 'unknown' entry types"),
+            .entry_type(entry_type)
+            .size(header.file_size)
+            .modified(if header.mtime > 0 {
+                Local.timestamp_opt(header.mtime as i64, 0).single()
+            } else {
+                None
             })
-            .size(self.entry().file_size().into())
-            .modified(if self.entry().mtime() > 0 {
-                Some(Local.timestamp(self.entry().mtime() as i64, 0))
+            .unix_mode(Some(header.mode & 0o0007777))
+            .device(if entry_type.is_device() {
+                Some(header.rdev)
             } else {
                 None
             })
-            .unix_mode(Some(self.entry().mode() & 0o0000777))
+            .links(Some(header.nlink))
             .build()
     }
+
+    fn read_link(&mut self) -> Result<Option<Cow<'_, Path>>> {
+        if self.link_target.is_some() {
+            return Ok(self.link_target.as_deref()
+                .map(|target| crate::paths::path_from_unix_path_bytes(Cow::Borrowed(target))));
+        }
+
+        if EntryType::from_unix_mode(self.header().mode) != EntryType::SymbolicLink {
+            return Ok(None);
+        }
+
+        // Symbolic link targets are stored as the entry's data.
+        let mut target = Vec::new();
+        self.read_to_end(&mut target)?;
+
+        Ok(Some(crate::paths::path_from_unix_path_bytes(Cow::Owned(target))))
+    }
+}
+
+impl<'a, 'r> Read for CpioEntry<'a, 'r> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let reader = &mut *self.reader;
+
+        if reader.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = buf.len().min(reader.remaining.try_into().unwrap_or(usize::MAX));
+        let read = reader.reader.read(&mut buf[..len])?;
+
+        if read == 0 {
+            return Err(corrupt("unexpected end of archive"));
+        }
+
+        reader.position += read as u64;
+        reader.remaining -= read as u64;
+
+        let header = reader.header.as_ref().unwrap();
+
+        if header.variant == Variant::Crc {
+            reader.checksum = checksum(reader.checksum, &buf[..read]);
+
+            if reader.remaining == 0 && reader.checksum != header.check {
+                return Err(corrupt(format_args!("checksum mismatch for {}", header.path().display())));
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+/// Add bytes to a checksum as used by the crc variant, which is a simple sum of
+/// all bytes.
+fn checksum(sum: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(sum, |sum, &byte| sum.wrapping_add(byte.into()))
+}
+
+/// Fields of an entry header to be written.
+struct NewHeader {
+    ino: u32,
+    mode: u32,
+    nlink: u32,
+    mtime: u64,
+    rdev: (u32, u32),
+    file_size: u64,
+    check: u32,
+}
+
+impl NewHeader {
+    fn new(mode: u32, metadata: &Metadata) -> Self {
+        Self {
+            ino: 0,
+            mode,
+            nlink: 1,
+
+            // Timestamps before the UNIX epoch can't be represented, so clamp
+            // them.
+            mtime: metadata.modified
+                .map(|dt| dt.timestamp().max(0) as u64)
+                .unwrap_or(0),
+
+            rdev: (0, 0),
+            file_size: 0,
+            check: 0,
+        }
+    }
+}
+
+pub struct CpioWriter {
+    output: Output,
+    format: CpioFormat,
+
+    /// Number of bytes written so far, used to add padding.
+    position: u64,
+
+    next_ino: u32,
+
+    /// Inode numbers and link counts of files added so far, which hard links
+    /// added later share.
+    files: HashMap<PathBuf, (u32, u32)>,
+}
+
+impl CpioWriter {
+    fn allocate_ino(&mut self) -> u32 {
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        ino
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.output.write_all(bytes)?;
+        self.position += bytes.len() as u64;
+
+        Ok(())
+    }
+
+    /// Write zeroes up to the given alignment.
+    fn pad(&mut self, alignment: u64) -> Result<()> {
+        let len = (alignment - self.position % alignment) % alignment;
+
+        self.write(&[0; 4][..len as usize])
+    }
+
+    /// Write an entry, copying exactly as much file data as the header says.
+    fn write_entry(&mut self, name: &[u8], header: NewHeader, data: &mut dyn Read) -> Result<()> {
+        let name_size = name.len() as u64 + 1;
+
+        let encoded = match self.format {
+            CpioFormat::Newc | CpioFormat::Crc => {
+                let file_size = u32::try_from(header.file_size).map_err(|_| Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} is too large for a newc cpio archive", String::from_utf8_lossy(name)),
+                ))?;
+
+                format!(
+                    "{}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+                    String::from_utf8_lossy(if self.format == CpioFormat::Crc { CRC_MAGIC } else { NEWC_MAGIC }),
+                    header.ino,
+                    header.mode,
+                    0,
+                    0,
+                    header.nlink,
+                    header.mtime.min(u32::MAX.into()),
+                    file_size,
+                    0,
+                    0,
+                    header.rdev.0,
+                    header.rdev.1,
+                    name_size,
+                    header.check,
+                )
+            }
+
+            CpioFormat::Odc => {
+                let field = |value: u64, digits: u32, what: &str| if value < 8u64.pow(digits) {
+                    Ok(format!("{:0width$o}", value, width = digits as usize))
+                } else {
+                    Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{} of {} is too large for an odc cpio archive", what, String::from_utf8_lossy(name)),
+                    ))
+                };
+
+                // Device numbers are combined, leaving 8 bits for the minor
+                // number.
+                let rdev = if header.rdev.1 < 0x100 {
+                    u64::from(header.rdev.0) << 8 | u64::from(header.rdev.1)
+                } else {
+                    u64::MAX
+                };
+
+                vec![
+                    Ok(String::from_utf8_lossy(ODC_MAGIC).into_owned()),
+                    field(0, 6, "device number"),
+                    field(header.ino.into(), 6, "inode number"),
+                    field(header.mode.into(), 6, "mode"),
+                    field(0, 6, "user ID"),
+                    field(0, 6, "group ID"),
+                    field(header.nlink.into(), 6, "link count"),
+                    field(rdev, 6, "device number"),
+                    field(header.mtime.min(8u64.pow(11) - 1), 11, "modification time"),
+                    field(name_size, 6, "name"),
+                    field(header.file_size, 11, "size"),
+                ].into_iter().collect::<Result<String>>()?
+            }
+        };
+
+        self.write(encoded.as_bytes())?;
+        self.write(name)?;
+        self.write(&[0])?;
+        self.pad(self.alignment())?;
+
+        let copied = io::copy(&mut data.take(header.file_size), &mut self.output)?;
+        self.position += copied;
+
+        if copied < header.file_size {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("{} is shorter than expected", String::from_utf8_lossy(name)),
+            ));
+        }
+
+        self.pad(self.alignment())
+    }
+
+    fn alignment(&self) -> u64 {
+        match self.format {
+            CpioFormat::Newc | CpioFormat::Crc => 4,
+            CpioFormat::Odc => 1,
+        }
+    }
+}
+
+impl ArchiveWriter for CpioWriter {
+    fn add_directory(&mut self, path: &Path, metadata: Metadata) -> Result<()> {
+        let mode = MODE_DIRECTORY | metadata.unix_mode.map(|mode| mode & 0o7777).unwrap_or(DEFAULT_DIRECTORY_MODE);
+        let mut header = NewHeader::new(mode, &metadata);
+        header.ino = self.allocate_ino();
+        header.nlink = 2;
+
+        self.write_entry(&crate::paths::path_to_unix_path_bytes(path), header, &mut io::empty())
+    }
+
+    fn add_file(&mut self, path: &Path, metadata: Metadata, file: &mut dyn Read) -> Result<()> {
+        let permissions = metadata.unix_mode.map(|mode| mode & 0o7777).unwrap_or(DEFAULT_FILE_MODE);
+        let name = crate::paths::path_to_unix_path_bytes(path);
+
+        let file_type = match metadata.entry_type {
+            EntryType::CharacterDevice => MODE_CHARACTER_DEVICE,
+            EntryType::BlockDevice => MODE_BLOCK_DEVICE,
+            EntryType::Fifo => MODE_FIFO,
+            _ => MODE_FILE,
+        };
+
+        let mut header = NewHeader::new(file_type | permissions, &metadata);
+        header.ino = self.allocate_ino();
+
+        if file_type != MODE_FILE {
+            header.rdev = metadata.device.unwrap_or_default();
+
+            return self.write_entry(&name, header, &mut io::empty());
+        }
+
+        header.nlink = metadata.links.unwrap_or(1).max(1);
+        self.files.insert(path.to_owned(), (header.ino, header.nlink));
+
+        if self.format != CpioFormat::Crc {
+            header.file_size = metadata.size;
+
+            return self.write_entry(&name, header, file);
+        }
+
+        // The checksum comes before the data, so the data has to be read in
+        // full first.
+        let mut scratch = SpooledTempFile::new(SCRATCH_MEMORY_LIMIT);
+        let mut buf = [0; 8192];
+
+        loop {
+            let len = match file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            header.check = checksum(header.check, &buf[..len]);
+            header.file_size += len as u64;
+            scratch.write_all(&buf[..len])?;
+        }
+
+        scratch.seek(SeekFrom::Start(0))?;
+
+        self.write_entry(&name, header, &mut scratch)
+    }
+
+    fn add_symlink(&mut self, path: &Path, metadata: Metadata, target: &Path) -> Result<()> {
+        let target = crate::paths::path_to_unix_path_bytes(target);
+
+        // Symbolic link targets are stored as the entry's data.
+        let mut header = NewHeader::new(MODE_SYMLINK | 0o777, &metadata);
+        header.ino = self.allocate_ino();
+        header.file_size = target.len() as u64;
+
+        if self.format == CpioFormat::Crc {
+            header.check = checksum(0, &target);
+        }
+
+        self.write_entry(&crate::paths::path_to_unix_path_bytes(path), header, &mut &*target)
+    }
+
+    fn add_hard_link(&mut self, path: &Path, metadata: Metadata, target: &Path) -> Result<()> {
+        let (ino, nlink) = *self.files.get(target).ok_or_else(|| Error::new(
+            ErrorKind::InvalidInput,
+            format!("cannot link {} to {}, which has not been added as a file", path.display(), target.display()),
+        ))?;
+
+        // Links share the inode of the file they link to, and only the first
+        // entry for the inode contains the file data.
+        let permissions = metadata.unix_mode.map(|mode| mode & 0o7777).unwrap_or(DEFAULT_FILE_MODE);
+        let mut header = NewHeader::new(MODE_FILE | permissions, &metadata);
+        header.ino = ino;
+        header.nlink = nlink;
+
+        self.write_entry(&crate::paths::path_to_unix_path_bytes(path), header, &mut io::empty())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let header = NewHeader {
+            ino: 0,
+            mode: 0,
+            nlink: 1,
+            mtime: 0,
+            rdev: (0, 0),
+            file_size: 0,
+            check: 0,
+        };
+
+        self.write_entry(TRAILER, header, &mut io::empty())?;
+        self.output.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{formats::ArchiveOptions, ArchiveFormat};
+    use std::fs;
+    use structopt::StructOpt;
+
+    // The fixtures were created by `bsdtar`, which stores the data of hard
+    // linked files with the last link in the newc variant and with every link
+    // in the others.
+    const NEWC: &[u8] = include_bytes!("../../../tests/fixtures/sample-newc.cpio");
+    const ODC: &[u8] = include_bytes!("../../../tests/fixtures/sample-odc.cpio");
+    const BINARY: &[u8] = include_bytes!("../../../tests/fixtures/sample-bin.cpio");
+
+    const HELLO: &str = "hello from a fixture\n";
+    const MTIME: i64 = 1_622_548_800;
+
+    /// Path, type, link target, and contents of an entry.
+    type Summary = (String, EntryType, Option<String>, String);
+
+    fn read_all(bytes: &[u8]) -> Result<Vec<Summary>> {
+        let mut reader = Cpio.open(Input::from_reader(bytes)?)?;
+        let mut entries = Vec::new();
+
+        while let Some(mut entry) = reader.entry()? {
+            let metadata = entry.metadata();
+            let target = entry.read_link()?.map(|target| target.to_string_lossy().into_owned());
+            let mut contents = String::new();
+
+            if metadata.entry_type != EntryType::SymbolicLink {
+                entry.read_to_string(&mut contents)?;
+            }
+
+            entries.push((entry.path().to_string_lossy().into_owned(), metadata.entry_type, target, contents));
+        }
+
+        Ok(entries)
+    }
+
+    fn entry(path: &str, entry_type: EntryType, target: Option<&str>, contents: &str) -> Summary {
+        (path.to_owned(), entry_type, target.map(str::to_owned), contents.to_owned())
+    }
+
+    #[test]
+    fn read_fixtures() {
+        for (bytes, first_contents) in &[(NEWC, ""), (ODC, HELLO), (BINARY, HELLO)] {
+            assert_eq!(read_all(bytes).unwrap(), vec![
+                entry("dir", EntryType::Directory, None, ""),
+                entry("hello.txt", EntryType::File, None, first_contents),
+                entry("dir/hardlink.txt", EntryType::HardLink, Some("hello.txt"), HELLO),
+                entry("link", EntryType::SymbolicLink, Some("hello.txt"), ""),
+            ]);
+
+            let mut reader = Cpio.open(Input::from_reader(*bytes).unwrap()).unwrap();
+            reader.entry().unwrap();
+            let metadata = reader.entry().unwrap().unwrap().metadata();
+            assert_eq!(metadata.modified.map(|dt| dt.timestamp()), Some(MTIME));
+            assert_eq!(metadata.unix_mode, Some(0o640));
+        }
+    }
+
+    #[test]
+    fn concatenated_archives() {
+        let entries = read_all(&[ODC, NEWC].concat()).unwrap();
+
+        assert_eq!(entries.len(), 8);
+        assert_eq!(entries[..4], read_all(ODC).unwrap()[..]);
+        assert_eq!(entries[4..], read_all(NEWC).unwrap()[..]);
+    }
+
+    #[test]
+    fn round_trip() {
+        for format in &["newc", "crc", "odc"] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("test.cpio");
+            let options = ArchiveOptions::from_iter(&["test", "--cpio-format", format]);
+            let compression_options = CompressionOptions::from_iter(&["test"]);
+            let mut writer = Cpio.create(Output::create(&path).unwrap(), &options, &compression_options).unwrap();
+
+            let metadata = |entry_type| Metadata::builder()
+                .entry_type(entry_type)
+                .modified(Local.timestamp_opt(MTIME, 0).single())
+                .unix_mode(Some(0o600))
+                .build();
+
+            writer.add_directory(Path::new("dir"), metadata(EntryType::Directory)).unwrap();
+            writer.add_file(Path::new("dir/hello.txt"), Metadata {
+                size: HELLO.len() as u64,
+                links: Some(2),
+                ..metadata(EntryType::File)
+            }, &mut HELLO.as_bytes()).unwrap();
+            writer.add_hard_link(Path::new("hardlink.txt"), metadata(EntryType::File), Path::new("dir/hello.txt")).unwrap();
+            writer.add_symlink(Path::new("link"), metadata(EntryType::SymbolicLink), Path::new("dir/hello.txt")).unwrap();
+            writer.add_file(Path::new("null"), Metadata {
+                device: Some((1, 3)),
+                ..metadata(EntryType::CharacterDevice)
+            }, &mut io::empty()).unwrap();
+            writer.add_file(Path::new("pipe"), metadata(EntryType::Fifo), &mut io::empty()).unwrap();
+            writer.finish().unwrap();
+
+            let bytes = fs::read(&path).unwrap();
+            let entries = read_all(&bytes).unwrap();
+
+            assert_eq!(entries[..4], [
+                entry("dir", EntryType::Directory, None, ""),
+                entry("dir/hello.txt", EntryType::File, None, HELLO),
+                entry("hardlink.txt", EntryType::HardLink, Some("dir/hello.txt"), ""),
+                entry("link", EntryType::SymbolicLink, Some("dir/hello.txt"), ""),
+            ], "{} variant", format);
+
+            let mut reader = Cpio.open(Input::from_reader(&bytes[..]).unwrap()).unwrap();
+            let metadata = (0..6)
+                .map(|_| reader.entry().unwrap().unwrap().metadata())
+                .collect::<Vec<_>>();
+
+            assert_eq!(metadata[1].modified.map(|dt| dt.timestamp()), Some(MTIME));
+            assert_eq!(metadata[1].unix_mode, Some(0o600));
+            assert_eq!((metadata[4].entry_type, metadata[4].device), (EntryType::CharacterDevice, Some((1, 3))));
+            assert_eq!(metadata[5].entry_type, EntryType::Fifo);
+            assert!(reader.entry().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn corrupt_archives_are_rejected() {
+        // Cutting the archive off anywhere but between entries must be
+        // reported. A missing trailer is tolerated.
+        let complete = (0..=NEWC.len())
+            .filter(|len| read_all(&NEWC[..*len]).is_ok())
+            .collect::<Vec<_>>();
+
+        assert_eq!(complete, [0, 116, 236, 388, 516, NEWC.len()]);
+
+        // A huge name size must not be allocated up front.
+        let mut bytes = NEWC.to_vec();
+        bytes[94..102].copy_from_slice(b"FFFFFFF0");
+        assert!(read_all(&bytes).is_err());
+    }
 }
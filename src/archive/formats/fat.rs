@@ -1,18 +1,32 @@
 //! https://www.win.tue.nl/~aeb/linux/fs/fat/fat-1.html
+//!
+//! FAT images are detected by checking that the boot sector holds a plausible
+//! BIOS parameter block, since the jump instruction at its start varies
+//! between FAT variants and the tools used to format them.
 
 use crate::{
-    archive::{ArchiveReader, Entry, EntryType, Metadata},
+    archive::{ArchiveReader, ArchiveWriter, Entry, EntryType, Metadata},
+    compress::formats::CompressionOptions,
     input::Input,
+    output::Output,
 };
+use chrono::prelude::*;
 use owning_ref::OwningHandle;
 use std::{
     convert::TryInto,
     fmt,
     fs::File,
-    io::{Read, Result, Seek, SeekFrom},
-    path::{Path, PathBuf},
+    io::{self, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
 };
 
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// Images are formatted with 512-byte sectors, so their size is rounded down
+/// to a multiple of this.
+const SECTOR_SIZE: u64 = 512;
+
 pub struct Fat;
 
 impl super::Format for Fat {
@@ -20,8 +34,40 @@ impl super::Format for Fat {
         "fat"
     }
 
+    fn file_extensions(&self) -> &[&str] {
+        &["img"]
+    }
+
     fn match_bytes(&self, bytes: &[u8]) -> bool {
-        matches!(bytes, [0xEB, 0x3C, 0x90, ..])
+        let boot_sector = match bytes.get(..512) {
+            Some(boot_sector) => boot_sector,
+            None => return false,
+        };
+
+        let u16_at = |offset: usize| u16::from_le_bytes(boot_sector[offset..offset + 2].try_into().unwrap());
+        let u32_at = |offset: usize| u32::from_le_bytes(boot_sector[offset..offset + 4].try_into().unwrap());
+
+        // A short jump (EB xx 90) is used by FAT12/16 and FAT32 alike, only
+        // differing in the offset jumped to, and some formatters use a near
+        // jump (E9 xx xx) instead.
+        let jump = matches!(boot_sector, [0xEB, _, 0x90, ..] | [0xE9, ..]);
+
+        let bytes_per_sector = u16_at(11);
+        let sectors_per_cluster = boot_sector[13];
+        let reserved_sectors = u16_at(14);
+        let fats = boot_sector[16];
+        let total_sectors = u16_at(19) as u32 | u32_at(32);
+        let media = boot_sector[21];
+
+        jump
+            && boot_sector[510..] == BOOT_SIGNATURE
+            && bytes_per_sector.is_power_of_two()
+            && (512..=4096).contains(&bytes_per_sector)
+            && sectors_per_cluster.is_power_of_two()
+            && reserved_sectors > 0
+            && (1..=2).contains(&fats)
+            && total_sectors > 0
+            && (media == 0xF0 || media >= 0xF8)
     }
 }
 
@@ -33,11 +79,51 @@ impl fmt::Display for Fat {
 
 impl super::ArchiveFormat for Fat {
     fn open(&self, input: Input) -> Result<Box<dyn ArchiveReader>> {
-        let input = input.into_file()
-            .map_err(|_| ())
-            .expect("only files are supported with fat");
+        Ok(Box::new(FatReader::new(input.into_file()?)?))
+    }
+
+    fn create(
+        &self,
+        output: Output,
+        options: &super::ArchiveOptions,
+        _compression_options: &CompressionOptions,
+    ) -> Result<Box<dyn ArchiveWriter>> {
+        let size = options.fat_size.ok_or_else(|| Error::other(
+            "FAT images have a fixed size, specify one with --fat-size",
+        ))?;
+
+        Ok(Box::new(FatWriter::new(output, size, options.fat_type)?))
+    }
+}
 
-        Ok(Box::new(FatReader::new(input)?))
+/// FAT variants that can be chosen when creating images.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FromStr for FatType {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "12" | "fat12" => Ok(FatType::Fat12),
+            "16" | "fat16" => Ok(FatType::Fat16),
+            "32" | "fat32" => Ok(FatType::Fat32),
+            _ => Err(format!("unknown FAT type: {}", s)),
+        }
+    }
+}
+
+impl From<FatType> for fatfs::FatType {
+    fn from(fat_type: FatType) -> Self {
+        match fat_type {
+            FatType::Fat12 => fatfs::FatType::Fat12,
+            FatType::Fat16 => fatfs::FatType::Fat16,
+            FatType::Fat32 => fatfs::FatType::Fat32,
+        }
     }
 }
 
@@ -55,7 +141,7 @@ impl FatReader {
                 |fs| unsafe {
                     let fs = &mut *(fs as *mut fatfs::FileSystem<File>);
                     Box::new(FatIterator {
-                        stack: vec![fs.root_dir().iter()],
+                        stack: vec![(PathBuf::new(), fs.root_dir().iter())],
                     })
                 },
             ),
@@ -66,63 +152,342 @@ impl FatReader {
 impl ArchiveReader for FatReader {
     fn entry(&mut self) -> Result<Option<Box<dyn Entry + '_>>> {
         match self.iter.next() {
-            Some(Ok(entry)) => Ok(Some(Box::new(FatEntry(entry)))),
+            Some(Ok((path, entry))) => Ok(Some(Box::new(FatEntry {
+                path,
+                entry,
+                file: None,
+            }))),
             Some(Err(e)) => Err(e),
             None => Ok(None),
         }
     }
 }
 
-struct FatEntry<'a>(fatfs::DirEntry<'a, File>);
+struct FatEntry<'a> {
+    path: PathBuf,
+    entry: fatfs::DirEntry<'a, File>,
+
+    /// Handle for reading the file contents, opened on first read.
+    file: Option<fatfs::File<'a, File>>,
+}
 
 impl<'a> Entry for FatEntry<'a> {
     fn path(&self) -> std::borrow::Cow<'_, Path> {
-        PathBuf::from(self.0.file_name()).into()
+        self.path.as_path().into()
     }
 
     fn metadata(&self) -> Metadata {
         Metadata::builder()
-            .entry_type(if self.0.is_dir() {
+            .entry_type(if self.entry.is_dir() {
                 EntryType::Directory
             } else {
                 EntryType::File
             })
-            .size(self.0.len())
-            .read_only(self.0.attributes().contains(fatfs::FileAttributes::READ_ONLY))
-            .hidden(self.0.attributes().contains(fatfs::FileAttributes::HIDDEN))
+            .size(self.entry.len())
+            .modified(from_dos_date_time(self.entry.modified()))
+            .created(from_dos_date_time(self.entry.created()))
+            .accessed(from_dos_date(self.entry.accessed())
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .and_then(|datetime| Local.from_local_datetime(&datetime).earliest()))
+            .read_only(self.entry.attributes().contains(fatfs::FileAttributes::READ_ONLY))
+            .hidden(self.entry.attributes().contains(fatfs::FileAttributes::HIDDEN))
             .build()
     }
 }
 
 impl<'a> Read for FatEntry<'a> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        if self.0.is_file() {
-            self.0.to_file().read(buf)
-        } else {
-            Ok(0)
+        if !self.entry.is_file() {
+            return Ok(0);
         }
+
+        let entry = &self.entry;
+
+        self.file.get_or_insert_with(|| entry.to_file()).read(buf)
     }
 }
 
+/// Walks the directory tree of a file system depth-first, yielding each entry
+/// along with its path relative to the root.
 struct FatIterator<'a> {
-    stack: Vec<fatfs::DirIter<'a, File>>,
+    /// Iterators of the directories currently being walked, along with their
+    /// paths.
+    stack: Vec<(PathBuf, fatfs::DirIter<'a, File>)>,
 }
 
 impl<'a> Iterator for FatIterator<'a> {
-    type Item = Result<fatfs::DirEntry<'a, File>>;
+    type Item = Result<(PathBuf, fatfs::DirEntry<'a, File>)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let iter = self.stack.last_mut()?;
+        loop {
+            let (dir_path, iter) = self.stack.last_mut()?;
+
+            match iter.next() {
+                Some(Ok(entry)) => {
+                    let name = entry.file_name();
 
-        match iter.next() {
-            Some(Ok(entry)) => {
-                if entry.is_dir() {
-                    self.stack.push(entry.to_dir().iter());
+                    if name == "." || name == ".." {
+                        continue;
+                    }
+
+                    let path = dir_path.join(name);
+
+                    if entry.is_dir() {
+                        self.stack.push((path.clone(), entry.to_dir().iter()));
+                    }
+
+                    return Some(Ok((path, entry)));
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    self.stack.pop();
                 }
+            }
+        }
+    }
+}
+
+/// Writes entries into a FAT image formatted in a temporary file, which is
+/// copied to the output once finished.
+pub struct FatWriter {
+    output: Output,
+    image: File,
+    fs: fatfs::FileSystem<File>,
+}
+
+impl FatWriter {
+    fn new(output: Output, size: u64, fat_type: Option<FatType>) -> Result<Self> {
+        let mut image = tempfile::tempfile()?;
+        image.set_len(size - size % SECTOR_SIZE)?;
+
+        let mut options = fatfs::FormatVolumeOptions::new();
+
+        if let Some(fat_type) = fat_type {
+            options = options.fat_type(fat_type.into());
+        }
+
+        fatfs::format_volume(&mut image, options).map_err(|e| Error::new(
+            e.kind(),
+            format!("cannot format a {}-byte FAT image: {}", size, e),
+        ))?;
+
+        image.seek(SeekFrom::Start(0))?;
+        let fs = fatfs::FileSystem::new(image.try_clone()?, fatfs::FsOptions::new())?;
+
+        log::debug!("formatted {:?} image of {} bytes", fs.fat_type(), size);
+
+        Ok(Self {
+            output,
+            image,
+            fs,
+        })
+    }
+
+    /// Open the directory the given path is in, creating it and any of its
+    /// parents as needed, and return it along with the final path component.
+    fn parent_dir(&self, path: &Path) -> Result<(fatfs::Dir<'_, File>, Option<String>)> {
+        let mut names = Vec::new();
+
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => names.push(name.to_string_lossy().into_owned()),
+                Component::ParentDir => return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("cannot add {} to a FAT image, paths may not contain ..", path.display()),
+                )),
+                _ => {}
+            }
+        }
+
+        let name = names.pop();
+        let mut dir = self.fs.root_dir();
 
-                Some(Ok(entry))
+        for name in names {
+            dir = dir.create_dir(&name)?;
+        }
+
+        Ok((dir, name))
+    }
+}
+
+impl ArchiveWriter for FatWriter {
+    fn add_directory(&mut self, path: &Path, _metadata: Metadata) -> Result<()> {
+        if let (dir, Some(name)) = self.parent_dir(path)? {
+            dir.create_dir(&name)?;
+        }
+
+        Ok(())
+    }
+
+    fn add_file(&mut self, path: &Path, metadata: Metadata, file: &mut dyn Read) -> Result<()> {
+        if metadata.entry_type != EntryType::File {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not a regular file, which FAT images cannot contain", path.display()),
+            ));
+        }
+
+        let (dir, name) = self.parent_dir(path)?;
+        let name = name.ok_or_else(|| Error::new(ErrorKind::InvalidInput, "cannot add a file without a name"))?;
+
+        let mut fat_file = dir.create_file(&name)?;
+        fat_file.truncate()?;
+        io::copy(file, &mut fat_file)?;
+
+        // Deprecated in favor of a time provider, which can only give the same
+        // time for every file.
+        if let Some(modified) = metadata.modified.and_then(dos_date_time) {
+            #[allow(deprecated)]
+            fat_file.set_modified(modified);
+        }
+
+        fat_file.flush()
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        let Self { mut output, mut image, fs } = *self;
+
+        fs.unmount()?;
+
+        image.seek(SeekFrom::Start(0))?;
+        io::copy(&mut image, &mut output)?;
+
+        output.finish()
+    }
+}
+
+/// Convert a timestamp to a DOS timestamp, if it is within the range DOS
+/// timestamps can represent.
+fn dos_date_time(datetime: DateTime<Local>) -> Option<fatfs::DateTime> {
+    if !(1980..=2107).contains(&datetime.year()) {
+        return None;
+    }
+
+    Some(fatfs::DateTime {
+        date: fatfs::Date {
+            year: datetime.year() as u16,
+            month: datetime.month() as u16,
+            day: datetime.day() as u16,
+        },
+        time: fatfs::Time {
+            hour: datetime.hour() as u16,
+            min: datetime.minute() as u16,
+            sec: datetime.second() as u16,
+            millis: (datetime.nanosecond() / 1_000_000).min(999) as u16,
+        },
+    })
+}
+
+/// Convert a DOS date to a calendar date, if it is valid.
+///
+/// Unset timestamps are stored as zeroes, which do not form a valid date.
+fn from_dos_date(date: fatfs::Date) -> Option<NaiveDate> {
+    NaiveDate::from_ymd_opt(date.year.into(), date.month.into(), date.day.into())
+}
+
+/// Convert a DOS timestamp, which is in local time, if it is valid.
+fn from_dos_date_time(datetime: fatfs::DateTime) -> Option<DateTime<Local>> {
+    let time = datetime.time;
+    let datetime = from_dos_date(datetime.date)?.and_hms_milli_opt(
+        time.hour.into(),
+        time.min.into(),
+        time.sec.into(),
+        time.millis.into(),
+    )?;
+
+    Local.from_local_datetime(&datetime).earliest()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{archive::{formats::ArchiveOptions, ArchiveFormat}, format::Format};
+    use structopt::StructOpt;
+
+    type Summary = (PathBuf, EntryType, Option<DateTime<Local>>, Vec<u8>);
+
+    fn lines() -> Vec<u8> {
+        (0..2000)
+            .map(|i| format!("line {}: the quick brown fox jumps over {} lazy dogs\n", i, i * i % 97))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    fn read_all(path: &Path) -> Vec<Summary> {
+        let mut reader = Fat.open(Input::open(path).unwrap()).unwrap();
+        let mut entries = Vec::new();
+
+        while let Some(mut entry) = reader.entry().unwrap() {
+            let metadata = entry.metadata();
+            let mut data = Vec::new();
+
+            // Read in small chunks, so that reads have to carry on from where
+            // the previous one stopped.
+            let mut buf = [0; 100];
+            loop {
+                match entry.read(&mut buf).unwrap() {
+                    0 => break,
+                    n => data.extend_from_slice(&buf[..n]),
+                }
             }
-            _ => None,
+
+            entries.push((entry.path().into_owned(), metadata.entry_type, metadata.modified, data));
         }
+
+        entries
+    }
+
+    #[test]
+    fn fat_types_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let modified = Local.with_ymd_and_hms(2021, 6, 1, 12, 30, 10).unwrap();
+
+        for (fat_type, size) in &[(FatType::Fat12, "1MiB"), (FatType::Fat16, "32MiB"), (FatType::Fat32, "64MiB")] {
+            let path = dir.path().join(format!("{:?}.img", fat_type));
+            let fat_type_arg = format!("{:?}", fat_type).to_lowercase();
+            let options = ArchiveOptions::from_iter(&["test", "--fat-size", size, "--fat-type", &fat_type_arg]);
+            let compression_options = CompressionOptions::from_iter(&["test"]);
+
+            let mut writer = Fat.create(Output::create(&path).unwrap(), &options, &compression_options).unwrap();
+            let metadata = |entry_type| Metadata::builder().entry_type(entry_type).modified(Some(modified)).build();
+
+            writer.add_directory(Path::new("dir"), metadata(EntryType::Directory)).unwrap();
+            writer.add_file(Path::new("dir/sub/lines.txt"), metadata(EntryType::File), &mut &lines()[..]).unwrap();
+            writer.add_file(Path::new("hello.txt"), metadata(EntryType::File), &mut &b"hello\n"[..]).unwrap();
+            writer.finish().unwrap();
+
+            let mut image = File::open(&path).unwrap();
+            let mut boot_sector = [0; 512];
+            image.read_exact(&mut boot_sector).unwrap();
+            assert!(Fat.match_bytes(&boot_sector), "{:?}", fat_type);
+            image.seek(SeekFrom::Start(0)).unwrap();
+            assert_eq!(fatfs::FileSystem::new(image, fatfs::FsOptions::new()).unwrap().fat_type(), (*fat_type).into());
+
+            let entries = read_all(&path);
+            let files = entries.iter()
+                .filter(|(_, entry_type, _, _)| *entry_type == EntryType::File)
+                .cloned()
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                entries.iter().map(|(path, entry_type, _, _)| (path.clone(), *entry_type)).collect::<Vec<_>>(),
+                vec![
+                    (PathBuf::from("dir"), EntryType::Directory),
+                    (PathBuf::from("dir/sub"), EntryType::Directory),
+                    (PathBuf::from("dir/sub/lines.txt"), EntryType::File),
+                    (PathBuf::from("hello.txt"), EntryType::File),
+                ],
+            );
+            assert_eq!(files, vec![
+                (PathBuf::from("dir/sub/lines.txt"), EntryType::File, Some(modified), lines()),
+                (PathBuf::from("hello.txt"), EntryType::File, Some(modified), b"hello\n".to_vec()),
+            ]);
+        }
+    }
+
+    #[test]
+    fn non_fat_data_is_not_detected() {
+        assert!(!Fat.match_bytes(&[0; 512]));
+        assert!(!Fat.match_bytes(&[0xEB, 0x3C, 0x90]));
     }
 }
@@ -0,0 +1,857 @@
+//! https://wiki.osdev.org/ISO_9660
+//! https://web.archive.org/web/20170404132301/http://aminet.net/package/docs/misc/RRIP
+//!
+//! Plain ISO 9660 only allows short upper-case names, so images usually carry
+//! extensions on top. Rock Ridge adds POSIX names, permissions, symbolic links
+//! and timestamps through the system use area of each directory record, while
+//! Joliet adds a second directory tree with UCS-2 names. Rock Ridge is
+//! preferred when present, as it describes the most about each file.
+
+use crate::{
+    archive::{ArchiveReader, Entry, EntryType, Metadata},
+    input::Input,
+};
+use chrono::prelude::*;
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    convert::TryInto,
+    fmt,
+    fs::File,
+    io::{Error, ErrorKind, Read, Result, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+const SECTOR_SIZE: u64 = 2048;
+
+/// Volume descriptors start at sector 16, after the system area.
+const VOLUME_DESCRIPTOR_OFFSET: u64 = 16 * SECTOR_SIZE;
+
+const STANDARD_IDENTIFIER: &[u8] = b"CD001";
+
+const PRIMARY_VOLUME_DESCRIPTOR: u8 = 1;
+const SUPPLEMENTARY_VOLUME_DESCRIPTOR: u8 = 2;
+const VOLUME_DESCRIPTOR_TERMINATOR: u8 = 255;
+
+/// Escape sequences identifying a supplementary volume descriptor as Joliet,
+/// for UCS-2 levels 1, 2 and 3.
+const JOLIET_ESCAPE_SEQUENCES: &[&[u8]] = &[b"%/@", b"%/C", b"%/E"];
+
+const FLAG_HIDDEN: u8 = 0x01;
+const FLAG_DIRECTORY: u8 = 0x02;
+
+/// Set on all but the last directory record of a file spanning multiple
+/// extents.
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+/// Limit on the number of system use continuation areas followed for a single
+/// directory record, in case they form a loop.
+const MAX_CONTINUATIONS: usize = 16;
+
+/// Limit on how deeply directories are nested, which is far deeper than any
+/// real image but keeps a crafted one from exhausting the stack.
+const MAX_DEPTH: usize = 256;
+
+/// Format provider for ISO 9660 images.
+pub struct Iso9660;
+
+impl super::Format for Iso9660 {
+    fn id(&self) -> &str {
+        "iso9660"
+    }
+
+    fn file_extensions(&self) -> &[&str] {
+        &["iso"]
+    }
+
+    fn match_bytes(&self, bytes: &[u8]) -> bool {
+        let offset = VOLUME_DESCRIPTOR_OFFSET as usize + 1;
+
+        bytes.get(offset..offset + STANDARD_IDENTIFIER.len()) == Some(STANDARD_IDENTIFIER)
+    }
+}
+
+impl fmt::Display for Iso9660 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ISO 9660")
+    }
+}
+
+impl super::ArchiveFormat for Iso9660 {
+    fn open<'r>(&self, input: Input<'r>) -> Result<Box<dyn ArchiveReader + 'r>> {
+        Ok(Box::new(IsoReader::new(input.into_file()?)?))
+    }
+}
+
+fn corrupt(message: impl fmt::Display) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("corrupt ISO 9660 image: {}", message))
+}
+
+/// Read exactly enough bytes to fill the buffer, treating an early end of file
+/// as a truncated image.
+fn read_exact(file: &mut File, buf: &mut [u8]) -> Result<()> {
+    file.read_exact(buf).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => corrupt("unexpected end of image"),
+        _ => e,
+    })
+}
+
+fn u16_le(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes(bytes[..2].try_into().unwrap())
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+/// How names in a directory tree are encoded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Names {
+    Iso,
+    Joliet,
+}
+
+#[derive(Clone, Debug)]
+struct DirectoryRecord {
+    extent: u32,
+    size: u32,
+    recorded: Option<DateTime<Local>>,
+    flags: u8,
+    name: Vec<u8>,
+    system_use: Vec<u8>,
+}
+
+impl DirectoryRecord {
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 33 || bytes.len() < 33 + bytes[32] as usize {
+            return Err(corrupt("truncated directory record"));
+        }
+
+        let name_len = bytes[32] as usize;
+
+        // The name is padded to an even length, after which comes the system
+        // use area.
+        let system_use_offset = (33 + name_len + (name_len + 1) % 2).min(bytes.len());
+
+        Ok(Self {
+            extent: u32_le(&bytes[2..]),
+            size: u32_le(&bytes[10..]),
+            recorded: parse_short_timestamp(&bytes[18..25]),
+            flags: bytes[25],
+            name: bytes[33..33 + name_len].to_vec(),
+            system_use: bytes[system_use_offset..].to_vec(),
+        })
+    }
+
+    fn is_directory(&self) -> bool {
+        self.flags & FLAG_DIRECTORY != 0
+    }
+
+    /// Check if this is the record for the directory itself or its parent,
+    /// which are named with a single 0 or 1 byte.
+    fn is_self_or_parent(&self) -> bool {
+        matches!(self.name.as_slice(), [0] | [1])
+    }
+
+    fn name(&self, names: Names) -> String {
+        let name = match names {
+            Names::Iso => self.name.iter().map(|&b| b as char).collect::<String>(),
+            Names::Joliet => {
+                let chars = self.name.chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect::<Vec<_>>();
+
+                String::from_utf16_lossy(&chars)
+            }
+        };
+
+        if self.is_directory() {
+            return name;
+        }
+
+        // Strip the file version, and the separator dot of a name without an
+        // extension.
+        let name = match name.rfind(';') {
+            Some(index) => &name[..index],
+            None => &name,
+        };
+
+        name.strip_suffix('.').unwrap_or(name).to_owned()
+    }
+}
+
+/// Parse a 7-byte recording date and time.
+fn parse_short_timestamp(bytes: &[u8]) -> Option<DateTime<Local>> {
+    let date = NaiveDate::from_ymd_opt(1900 + bytes[0] as i32, bytes[1].into(), bytes[2].into())?;
+    let time = NaiveTime::from_hms_opt(bytes[3].into(), bytes[4].into(), bytes[5].into())?;
+    let offset = FixedOffset::east_opt(bytes[6] as i8 as i32 * 15 * 60)?;
+
+    Some(offset.from_local_datetime(&date.and_time(time)).single()?.with_timezone(&Local))
+}
+
+/// Parse a 17-byte date and time in the format used by volume descriptors.
+fn parse_long_timestamp(bytes: &[u8]) -> Option<DateTime<Local>> {
+    let digits = std::str::from_utf8(&bytes[..16]).ok()?;
+    let field = |range: std::ops::Range<usize>| digits.get(range)?.parse::<u32>().ok();
+
+    let date = NaiveDate::from_ymd_opt(field(0..4)? as i32, field(4..6)?, field(6..8)?)?;
+    let time = NaiveTime::from_hms_milli_opt(field(8..10)?, field(10..12)?, field(12..14)?, field(14..16)? * 10)?;
+    let offset = FixedOffset::east_opt(bytes[16] as i8 as i32 * 15 * 60)?;
+
+    Some(offset.from_local_datetime(&date.and_time(time)).single()?.with_timezone(&Local))
+}
+
+/// Rock Ridge information about a file.
+#[derive(Debug, Default)]
+struct RockRidge {
+    name: Option<String>,
+    mode: Option<u32>,
+    link_target: Option<String>,
+
+    /// Whether the last symbolic link component continues in the next one.
+    continue_component: bool,
+
+    modified: Option<DateTime<Local>>,
+    device: Option<(u32, u32)>,
+
+    /// Location of a directory that was relocated to keep the tree within the
+    /// depth limit.
+    child_link: Option<u32>,
+
+    /// Set on the relocated directory itself, which should not be listed in
+    /// its new location.
+    relocated: bool,
+}
+
+/// Location of a system use continuation area.
+struct Continuation {
+    extent: u32,
+    offset: u32,
+    len: u32,
+}
+
+impl RockRidge {
+    /// Parse the system use entries in the given area, returning the location
+    /// of the next continuation area, if any.
+    fn parse(&mut self, mut area: &[u8]) -> Option<Continuation> {
+        let mut continuation = None;
+
+        while area.len() >= 4 {
+            let len = area[2] as usize;
+
+            if len < 4 || len > area.len() {
+                break;
+            }
+
+            let data = &area[4..len];
+
+            match &area[..2] {
+                b"NM" if !data.is_empty() => {
+                    let flags = data[0];
+                    let part = String::from_utf8_lossy(&data[1..]);
+                    let name = self.name.get_or_insert_with(String::new);
+
+                    // The current and parent directory flags are only used
+                    // on the records of those directories.
+                    if flags & 0x06 == 0 {
+                        name.push_str(&part);
+                    }
+                }
+
+                b"PX" if data.len() >= 8 => self.mode = Some(u32_le(data)),
+
+                b"PN" if data.len() >= 16 => self.device = Some((u32_le(data), u32_le(&data[8..]))),
+
+                b"SL" if !data.is_empty() => {
+                    let target = self.link_target.get_or_insert_with(String::new);
+                    let mut components = &data[1..];
+
+                    while components.len() >= 2 {
+                        let flags = components[0];
+                        let len = (components[1] as usize).min(components.len() - 2);
+                        let content = &components[2..2 + len];
+
+                        // A component continued from the previous one is
+                        // joined without a separator.
+                        if !target.is_empty() && !target.ends_with('/') && !self.continue_component {
+                            target.push('/');
+                        }
+
+                        if flags & 0x02 != 0 {
+                            target.push('.');
+                        } else if flags & 0x04 != 0 {
+                            target.push_str("..");
+                        } else if flags & 0x08 != 0 {
+                            target.push('/');
+                        } else {
+                            target.push_str(&String::from_utf8_lossy(content));
+                        }
+
+                        self.continue_component = flags & 0x01 != 0;
+                        components = &components[2 + len..];
+                    }
+                }
+
+                b"TF" if !data.is_empty() => {
+                    let flags = data[0];
+                    let size = if flags & 0x80 != 0 { 17 } else { 7 };
+                    let mut timestamps = &data[1..];
+
+                    // Timestamps are stored in order of their flag bits, of
+                    // which the modification time is the second.
+                    for bit in 0..7 {
+                        if flags & (1 << bit) == 0 {
+                            continue;
+                        }
+
+                        if timestamps.len() < size {
+                            break;
+                        }
+
+                        if bit == 1 {
+                            self.modified = if size == 17 {
+                                parse_long_timestamp(timestamps)
+                            } else {
+                                parse_short_timestamp(timestamps)
+                            };
+                        }
+
+                        timestamps = &timestamps[size..];
+                    }
+                }
+
+                b"CL" if data.len() >= 4 => self.child_link = Some(u32_le(data)),
+
+                b"RE" => self.relocated = true,
+
+                b"CE" if data.len() >= 24 => {
+                    continuation = Some(Continuation {
+                        extent: u32_le(data),
+                        offset: u32_le(&data[8..]),
+                        len: u32_le(&data[16..]),
+                    });
+                }
+
+                b"ST" => break,
+
+                _ => {}
+            }
+
+            area = &area[len..];
+        }
+
+        continuation
+    }
+}
+
+/// Information about an entry in the image, gathered up front.
+struct EntryInfo {
+    path: PathBuf,
+    metadata: Metadata,
+
+    /// Byte offsets and lengths of the extents containing the file data.
+    extents: Vec<(u64, u64)>,
+
+    link_target: Option<PathBuf>,
+}
+
+struct IsoReader {
+    file: File,
+
+    /// Size of the image in bytes, which bounds the size of any structure
+    /// read from it.
+    size: u64,
+
+    block_size: u64,
+
+    /// Whether the tree being read uses Rock Ridge.
+    rock_ridge: bool,
+
+    /// Number of bytes to skip at the start of each system use area, as given
+    /// by the SUSP indicator.
+    susp_skip: usize,
+
+    names: Names,
+    entries: Vec<EntryInfo>,
+    offset: usize,
+}
+
+impl IsoReader {
+    fn new(mut file: File) -> Result<Self> {
+        let mut primary = None;
+        let mut joliet = None;
+        let mut sector = 0;
+
+        loop {
+            let mut descriptor = vec![0; SECTOR_SIZE as usize];
+            file.seek(SeekFrom::Start(VOLUME_DESCRIPTOR_OFFSET + sector * SECTOR_SIZE))?;
+            read_exact(&mut file, &mut descriptor)?;
+            sector += 1;
+
+            if &descriptor[1..6] != STANDARD_IDENTIFIER {
+                return Err(corrupt("invalid volume descriptor"));
+            }
+
+            match descriptor[0] {
+                PRIMARY_VOLUME_DESCRIPTOR => primary = primary.or(Some(descriptor)),
+                SUPPLEMENTARY_VOLUME_DESCRIPTOR if JOLIET_ESCAPE_SEQUENCES.contains(&&descriptor[88..91]) => {
+                    joliet = joliet.or(Some(descriptor));
+                }
+                VOLUME_DESCRIPTOR_TERMINATOR => break,
+                _ => {}
+            }
+        }
+
+        let primary = primary.ok_or_else(|| corrupt("no primary volume descriptor"))?;
+
+        let mut reader = Self {
+            size: file.metadata()?.len(),
+            file,
+            block_size: u16_le(&primary[128..]).into(),
+            rock_ridge: false,
+            susp_skip: 0,
+            names: Names::Iso,
+            entries: Vec::new(),
+            offset: 0,
+        };
+
+        if reader.block_size == 0 {
+            return Err(corrupt("invalid logical block size"));
+        }
+
+        let mut root = DirectoryRecord::parse(&primary[156..190])?;
+
+        // Rock Ridge is announced in the system use area of the first record
+        // of the root directory.
+        let root_data = reader.read_extent(root.extent, root.size.min(SECTOR_SIZE as u32))?;
+
+        if let Some(first) = root_data.first().map(|&len| len as usize).filter(|&len| len > 0 && len <= root_data.len()) {
+            let record = DirectoryRecord::parse(&root_data[..first])?;
+
+            if record.system_use.len() >= 7 && &record.system_use[..2] == b"SP" && record.system_use[4..6] == [0xbe, 0xef] {
+                reader.susp_skip = record.system_use[6] as usize;
+                reader.rock_ridge = true;
+            }
+        }
+
+        if !reader.rock_ridge {
+            if let Some(joliet) = joliet {
+                root = DirectoryRecord::parse(&joliet[156..190])?;
+                reader.names = Names::Joliet;
+            }
+        }
+
+        log::debug!("reading {:?} names, Rock Ridge: {}", reader.names, reader.rock_ridge);
+
+        let mut visited = HashSet::new();
+        reader.read_directory(&root, Path::new(""), &mut visited)?;
+
+        Ok(reader)
+    }
+
+    fn read_extent(&mut self, extent: u32, len: u32) -> Result<Vec<u8>> {
+        self.read_at(u64::from(extent) * self.block_size, len)
+    }
+
+    /// Read bytes at the given offset, checking that they lie within the image
+    /// before allocating room for them, since the length comes from the image
+    /// itself.
+    fn read_at(&mut self, offset: u64, len: u32) -> Result<Vec<u8>> {
+        if offset.saturating_add(len.into()) > self.size {
+            return Err(corrupt("unexpected end of image"));
+        }
+
+        let mut data = vec![0; len as usize];
+        self.file.seek(SeekFrom::Start(offset))?;
+        read_exact(&mut self.file, &mut data)?;
+
+        Ok(data)
+    }
+
+    fn rock_ridge(&mut self, record: &DirectoryRecord) -> Result<RockRidge> {
+        let mut rock_ridge = RockRidge::default();
+
+        if !self.rock_ridge {
+            return Ok(rock_ridge);
+        }
+
+        let mut continuation = rock_ridge.parse(record.system_use.get(self.susp_skip..).unwrap_or_default());
+
+        for _ in 0..MAX_CONTINUATIONS {
+            let next = match continuation {
+                Some(next) => next,
+                None => break,
+            };
+
+            let area = self.read_at(u64::from(next.extent) * self.block_size + u64::from(next.offset), next.len)?;
+
+            continuation = rock_ridge.parse(&area);
+        }
+
+        Ok(rock_ridge)
+    }
+
+    /// Read all records of a directory, adding entries for them and reading
+    /// any subdirectories.
+    ///
+    /// Returns true if the directory only holds relocated directories, which
+    /// makes it the `rr_moved` directory that should not be listed itself.
+    fn read_directory(&mut self, directory: &DirectoryRecord, path: &Path, visited: &mut HashSet<u32>) -> Result<bool> {
+        if !visited.insert(directory.extent) {
+            return Err(corrupt("directory loop"));
+        }
+
+        if path.components().count() >= MAX_DEPTH {
+            return Err(corrupt("directories are nested too deeply"));
+        }
+
+        let data = self.read_extent(directory.extent, directory.size)?;
+        let mut position = 0;
+
+        // Extents of a file spanning multiple records so far.
+        let mut extents = Vec::new();
+
+        let first_entry = self.entries.len();
+        let mut relocated = false;
+
+        while position < data.len() {
+            let len = data[position] as usize;
+
+            // Records do not span sectors, so the rest of a sector is padded
+            // with zeroes.
+            if len == 0 {
+                position = (position / SECTOR_SIZE as usize + 1) * SECTOR_SIZE as usize;
+                continue;
+            }
+
+            let record = DirectoryRecord::parse(data.get(position..position + len).ok_or_else(|| corrupt("truncated directory record"))?)?;
+            position += len;
+
+            if record.is_self_or_parent() {
+                continue;
+            }
+
+            extents.push((u64::from(record.extent) * self.block_size, u64::from(record.size)));
+
+            if record.flags & FLAG_MULTI_EXTENT != 0 {
+                continue;
+            }
+
+            let mut rock_ridge = self.rock_ridge(&record)?;
+
+            if rock_ridge.relocated {
+                relocated = true;
+                extents.clear();
+                continue;
+            }
+
+            let name = rock_ridge.name.clone().unwrap_or_else(|| record.name(self.names));
+            let path = path.join(name);
+
+            // A relocated directory is stood in for by an empty file linking
+            // to its real location, where its own record describes it.
+            let directory = match rock_ridge.child_link {
+                Some(extent) => {
+                    let data = self.read_extent(extent, SECTOR_SIZE as u32)?;
+                    let len = (data[0] as usize).min(data.len());
+                    let directory = DirectoryRecord::parse(&data[..len])?;
+                    let relocated = self.rock_ridge(&directory)?;
+
+                    rock_ridge.mode = relocated.mode.or(rock_ridge.mode);
+                    rock_ridge.modified = relocated.modified.or(rock_ridge.modified);
+
+                    Some(directory)
+                }
+                None if record.is_directory() => Some(record.clone()),
+                None => None,
+            };
+
+            let entry_type = match (&directory, rock_ridge.mode) {
+                (Some(_), _) => EntryType::Directory,
+                (None, Some(mode)) => EntryType::from_unix_mode(mode),
+                (None, None) => EntryType::File,
+            };
+
+            let metadata = Metadata::builder()
+                .entry_type(entry_type)
+                .size(match entry_type {
+                    EntryType::File => extents.iter().map(|&(_, len)| len).sum(),
+                    _ => 0,
+                })
+                .modified(rock_ridge.modified.or(record.recorded))
+                .hidden(record.flags & FLAG_HIDDEN != 0)
+                .unix_mode(rock_ridge.mode.map(|mode| mode & 0o7777))
+                .device(rock_ridge.device.filter(|_| entry_type.is_device()))
+                .build();
+
+            self.entries.push(EntryInfo {
+                path: path.clone(),
+                metadata,
+                extents: if matches!(entry_type, EntryType::File) {
+                    std::mem::take(&mut extents)
+                } else {
+                    Vec::new()
+                },
+                link_target: rock_ridge.link_target.map(PathBuf::from),
+            });
+            extents.clear();
+
+            if let Some(directory) = directory {
+                let index = self.entries.len() - 1;
+
+                if self.read_directory(&directory, &path, visited)? {
+                    self.entries.remove(index);
+                }
+            }
+        }
+
+        Ok(relocated && self.entries.len() == first_entry)
+    }
+}
+
+impl ArchiveReader for IsoReader {
+    fn len(&mut self) -> Option<u64> {
+        Some(self.entries.len() as u64)
+    }
+
+    fn entry(&mut self) -> Result<Option<Box<dyn Entry + '_>>> {
+        if let Some(info) = self.entries.get(self.offset) {
+            self.offset += 1;
+
+            Ok(Some(Box::new(IsoEntry {
+                file: &mut self.file,
+                info,
+                extent: 0,
+                position: 0,
+            })))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+struct IsoEntry<'a> {
+    file: &'a mut File,
+    info: &'a EntryInfo,
+
+    /// Index of the extent being read and the position within it.
+    extent: usize,
+    position: u64,
+}
+
+impl Entry for IsoEntry<'_> {
+    fn path(&self) -> Cow<'_, Path> {
+        self.info.path.as_path().into()
+    }
+
+    fn metadata(&self) -> Metadata {
+        self.info.metadata.clone()
+    }
+
+    fn read_link(&mut self) -> Result<Option<Cow<'_, Path>>> {
+        Ok(self.info.link_target.as_deref().map(Cow::from))
+    }
+}
+
+impl Read for IsoEntry<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        while let Some(&(offset, len)) = self.info.extents.get(self.extent) {
+            if self.position >= len {
+                self.extent += 1;
+                self.position = 0;
+                continue;
+            }
+
+            let remaining = (len - self.position).min(buf.len() as u64) as usize;
+
+            self.file.seek(SeekFrom::Start(offset + self.position))?;
+            let read = self.file.read(&mut buf[..remaining])?;
+
+            if read == 0 && remaining > 0 {
+                return Err(corrupt(format_args!("unexpected end of image reading {}", self.info.path.display())));
+            }
+
+            self.position += read as u64;
+
+            return Ok(read);
+        }
+
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::ArchiveFormat;
+
+    /// Modification time of every entry in the fixtures, which were created by
+    /// `bsdtar` with and without Rock Ridge and Joliet extensions.
+    const MTIME: i64 = 1_622_548_800;
+
+    fn lines() -> String {
+        (0..50)
+            .map(|i| format!("line {}: the quick brown fox jumps over {} lazy dogs\n", i, i * i % 97))
+            .collect()
+    }
+
+    /// Path, type, mode, and contents or link target of an entry.
+    type Summary = (String, EntryType, Option<u32>, String);
+
+    /// Read every entry of an image, along with its contents or link target.
+    fn read_all(bytes: &[u8]) -> Result<Vec<Summary>> {
+        let mut reader = Iso9660.open(Input::from_reader(bytes)?)?;
+        let mut entries = Vec::new();
+
+        while let Some(mut entry) = reader.entry()? {
+            let metadata = entry.metadata();
+            assert_eq!(metadata.modified.map(|dt| dt.timestamp()), Some(MTIME));
+
+            let contents = match metadata.entry_type {
+                EntryType::SymbolicLink => entry.read_link()?.unwrap().to_string_lossy().into_owned(),
+                _ => {
+                    let mut contents = String::new();
+                    entry.read_to_string(&mut contents)?;
+                    contents
+                }
+            };
+
+            entries.push((entry.path().to_string_lossy().into_owned(), metadata.entry_type, metadata.unix_mode, contents));
+        }
+
+        Ok(entries)
+    }
+
+    /// Read the path of every entry of an image.
+    fn read_all_paths(bytes: &[u8]) -> Result<Vec<String>> {
+        let mut reader = Iso9660.open(Input::from_reader(bytes)?)?;
+        let mut paths = Vec::new();
+
+        while let Some(entry) = reader.entry()? {
+            paths.push(entry.path().to_string_lossy().into_owned());
+        }
+
+        Ok(paths)
+    }
+
+    #[test]
+    fn read_rock_ridge() {
+        assert_eq!(read_all(include_bytes!("../../../tests/fixtures/sample-rockridge.iso")).unwrap(), vec![
+            ("dir".to_owned(), EntryType::Directory, Some(0o755), String::new()),
+            ("dir/lines.txt".to_owned(), EntryType::File, Some(0o644), lines()),
+            ("hello.txt".to_owned(), EntryType::File, Some(0o640), "hello from a fixture\n".to_owned()),
+            ("link".to_owned(), EntryType::SymbolicLink, Some(0o777), "hello.txt".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn read_without_rock_ridge() {
+        let expected = |dir: &str, lines_txt: &str, hello_txt: &str| vec![
+            (dir.to_owned(), EntryType::Directory, None, String::new()),
+            (format!("{}/{}", dir, lines_txt), EntryType::File, None, lines()),
+            (hello_txt.to_owned(), EntryType::File, None, "hello from a fixture\n".to_owned()),
+        ];
+
+        // Joliet names keep their case, while plain ISO 9660 names do not.
+        assert_eq!(
+            read_all(include_bytes!("../../../tests/fixtures/sample-joliet.iso")).unwrap(),
+            expected("dir", "lines.txt", "hello.txt"),
+        );
+        assert_eq!(
+            read_all(include_bytes!("../../../tests/fixtures/sample-plain.iso")).unwrap(),
+            expected("DIR", "LINES.TXT", "HELLO.TXT"),
+        );
+    }
+
+    #[test]
+    fn read_continuation_areas() {
+        // Names and link targets this long do not fit in a directory record,
+        // and continue in a separate area.
+        let name = "long-name-".repeat(20);
+
+        assert_eq!(read_all(include_bytes!("../../../tests/fixtures/sample-long-names.iso")).unwrap(), vec![
+            ("link".to_owned(), EntryType::SymbolicLink, Some(0o777), name.clone()),
+            (name, EntryType::File, Some(0o644), "hello\n".to_owned()),
+        ]);
+    }
+
+    /// Build a directory record of a single-sector directory.
+    fn directory_record(extent: u32, name: u8) -> Vec<u8> {
+        let mut record = vec![0; 34];
+        record[0] = 34;
+        record[2..6].copy_from_slice(&extent.to_le_bytes());
+        record[6..10].copy_from_slice(&extent.to_be_bytes());
+        record[10..14].copy_from_slice(&(SECTOR_SIZE as u32).to_le_bytes());
+        record[14..18].copy_from_slice(&(SECTOR_SIZE as u32).to_be_bytes());
+        record[25] = FLAG_DIRECTORY;
+        record[32] = 1;
+        record[33] = name;
+        record
+    }
+
+    /// Build an image of directories nested the given number of levels deep,
+    /// each in its own sector.
+    fn nested_image(depth: u32) -> Vec<u8> {
+        let first = 18;
+        let mut image = vec![0; VOLUME_DESCRIPTOR_OFFSET as usize];
+
+        let mut primary = vec![0; SECTOR_SIZE as usize];
+        primary[0] = PRIMARY_VOLUME_DESCRIPTOR;
+        primary[1..6].copy_from_slice(STANDARD_IDENTIFIER);
+        primary[128..130].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        primary[156..190].copy_from_slice(&directory_record(first, 0));
+        image.extend(primary);
+
+        let mut terminator = vec![0; SECTOR_SIZE as usize];
+        terminator[0] = VOLUME_DESCRIPTOR_TERMINATOR;
+        terminator[1..6].copy_from_slice(STANDARD_IDENTIFIER);
+        image.extend(terminator);
+
+        for extent in first..=first + depth {
+            let mut directory = [directory_record(extent, 0), directory_record(extent - 1, 1)].concat();
+
+            if extent < first + depth {
+                directory.extend(directory_record(extent + 1, b'D'));
+            }
+
+            directory.resize(SECTOR_SIZE as usize, 0);
+            image.extend(directory);
+        }
+
+        image
+    }
+
+    #[test]
+    fn deeply_nested_directories_are_an_error() {
+        assert_eq!(read_all_paths(&nested_image(10)).unwrap().last().unwrap(), "D/D/D/D/D/D/D/D/D/D");
+
+        let error = read_all_paths(&nested_image(MAX_DEPTH as u32 + 1)).unwrap_err();
+        assert!(error.to_string().contains("nested too deeply"), "unexpected error: {}", error);
+    }
+
+    #[test]
+    fn oversized_lengths_are_an_error() {
+        let mut root_size = include_bytes!("../../../tests/fixtures/sample-rockridge.iso").to_vec();
+        let mut continuation_len = include_bytes!("../../../tests/fixtures/sample-long-names.iso").to_vec();
+
+        let offset = VOLUME_DESCRIPTOR_OFFSET as usize + 156 + 10;
+        root_size[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        root_size[offset + 4..offset + 8].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        let continuations = continuation_len.windows(4)
+            .enumerate()
+            .filter(|(_, window)| window == b"CE\x1c\x01")
+            .map(|(offset, _)| offset)
+            .collect::<Vec<_>>();
+
+        for offset in continuations {
+            continuation_len[offset + 20..offset + 24].copy_from_slice(&u32::MAX.to_le_bytes());
+            continuation_len[offset + 24..offset + 28].copy_from_slice(&u32::MAX.to_be_bytes());
+        }
+
+        for bytes in &[root_size, continuation_len] {
+            let error = read_all(bytes).unwrap_err();
+            assert!(error.to_string().contains("unexpected end of image"), "unexpected error: {}", error);
+        }
+    }
+}
@@ -1,32 +1,131 @@
 use crate::{
     archive::ArchiveReader,
+    compress::formats::{CompressionFormat, CompressionOptions},
     format::Format,
     input::Input,
     output::Output,
 };
+use chrono::prelude::*;
+use glob::Pattern;
 use std::{
-    io::Result,
+    io::{Error, Result},
     path::Path,
 };
+use structopt::StructOpt;
 
 mod ar;
 mod cab;
 mod cpio;
 mod fat;
+mod iso9660;
+mod mpq;
+mod rar;
+mod sevenz;
 mod tar;
 mod zip;
 
+pub use self::{
+    ar::ArFormat,
+    cpio::CpioFormat,
+    fat::FatType,
+    zip::ZipMethod,
+};
+
+/// Shorthand file extensions for compressed tar archives, along with the file
+/// extension of the compression format they imply.
+const TAR_SHORTHAND_EXTENSIONS: &[(&str, &str)] = &[
+    ("taz", "Z"),
+    ("tb2", "bz2"),
+    ("tbz", "bz2"),
+    ("tbz2", "bz2"),
+    ("tgz", "gz"),
+    ("tlz", "lz"),
+    ("txz", "xz"),
+    ("tZ", "Z"),
+    ("tzst", "zst"),
+];
+
+/// Number of seconds between the Windows epoch (1601) and the Unix epoch.
+const WINDOWS_EPOCH_OFFSET: i64 = 11_644_473_600;
+
 /// A provider implementation for a specific archive format.
 pub trait ArchiveFormat: Format {
     /// Open the given input for reading.
     fn open<'r>(&self, input: Input<'r>) -> Result<Box<dyn ArchiveReader + 'r>>;
 
-    /// Create a writer for writing an archive to a stream.
-    fn create<'w>(&self, _sink: &'w mut Output) -> Result<Box<dyn super::ArchiveWriter + 'w>> {
-        unimplemented!() // TODO: return equivalent error
+    /// Create a writer for writing an archive to a stream, according to the
+    /// given archive options.
+    ///
+    /// Formats that compress entries themselves should do so according to the
+    /// given compression options.
+    fn create(
+        &self,
+        _output: Output,
+        _options: &ArchiveOptions,
+        _compression_options: &CompressionOptions,
+    ) -> Result<Box<dyn super::ArchiveWriter>> {
+        Err(Error::other(format!("creating {} archives is not supported", self)))
     }
 }
 
+// Options controlling how archives are written in specific formats.
+// Options meant for other formats than the one being written are ignored.
+#[derive(Debug, Default, StructOpt)]
+pub struct ArchiveOptions {
+    /// Compression method to use for entries in ZIP archives: store, deflate,
+    /// bzip2, or zstd.
+    ///
+    /// Entries that do not get any smaller when compressed are stored without
+    /// compression regardless.
+    #[structopt(long, default_value = "deflate")]
+    pub zip_method: ZipMethod,
+
+    /// Store entries matching the given glob pattern in ZIP archives without
+    /// compression, such as files that are already compressed. Can be given
+    /// multiple times.
+    #[structopt(long = "store", number_of_values = 1)]
+    pub zip_store: Vec<Pattern>,
+
+    /// Variant of the ar format to create archives in: gnu or bsd.
+    ///
+    /// The variants differ in how names longer than 15 bytes are stored. Use
+    /// gnu for Linux systems and bsd for BSD and macOS systems.
+    #[structopt(long, default_value = "gnu")]
+    pub ar_format: ArFormat,
+
+    /// Create ar archives deterministically, recording zero for all
+    /// timestamps, user IDs, and group IDs, and the same permissions for all
+    /// members.
+    #[structopt(long)]
+    pub ar_deterministic: bool,
+
+    /// Add a symbol table to ar archives indexing the symbols defined by the
+    /// object files in them, as needed by linkers for static libraries.
+    #[structopt(long)]
+    pub ar_symbol_table: bool,
+
+    /// Variant of the cpio format to create archives in: newc, crc, or odc.
+    ///
+    /// The crc variant is the same as newc, with a checksum of each file's
+    /// data. The odc variant is the older portable format, which is limited to
+    /// smaller inode and device numbers.
+    #[structopt(long, default_value = "newc")]
+    pub cpio_format: CpioFormat,
+
+    /// Size of FAT images to create, in bytes.
+    ///
+    /// A KiB, MiB, or GiB suffix may be used. FAT images have a fixed size,
+    /// so this is required when creating one.
+    #[structopt(long, parse(try_from_str = crate::options::parse_size))]
+    pub fat_size: Option<u64>,
+
+    /// FAT variant to format images with: 12, 16, or 32.
+    ///
+    /// By default the variant is chosen based on the size of the image.
+    #[structopt(long)]
+    pub fat_type: Option<FatType>,
+}
+
 /// Get all enabled formats.
 pub fn all() -> &'static [&'static dyn ArchiveFormat] {
     &[
@@ -34,22 +133,65 @@ pub fn all() -> &'static [&'static dyn ArchiveFormat] {
         &cab::Cab,
         &cpio::Cpio,
         &fat::Fat,
+        &iso9660::Iso9660,
+        &mpq::Mpq,
+        &rar::Rar,
+        &sevenz::SevenZ,
         &tar::Tar,
         &zip::Zip,
     ]
 }
 
-/// Get an appropriate format provider for a file with the given file extension.
-pub fn for_extension(path: &Path) -> Option<&'static dyn ArchiveFormat> {
-    if let Some(file_name) = path.file_name()?.to_str() {
-        for format in all() {
-            for ext in format.file_extensions() {
-                if file_name.ends_with(ext) {
-                    return Some(*format);
-                }
-            }
+/// Get the format provider with the given ID, ignoring case.
+pub fn for_id(id: &str) -> Option<&'static dyn ArchiveFormat> {
+    all().iter()
+        .find(|format| format.id().eq_ignore_ascii_case(id))
+        .copied()
+}
+
+/// Get the format provider that uses the given file extension, if any.
+pub fn for_extension(extension: &str) -> Option<&'static dyn ArchiveFormat> {
+    all().iter()
+        .find(|format| format.file_extensions().contains(&extension))
+        .copied()
+}
+
+/// Get an appropriate format provider for a file with the given path based on
+/// its file extensions, along with any compression formats applied on top of
+/// the archive.
+///
+/// Compound extensions such as `.tar.gz` as well as shorthands like `.tgz` are
+/// recognized. Compression formats are returned outermost first.
+pub fn for_path(path: &Path) -> Option<(&'static dyn ArchiveFormat, Vec<&'static dyn CompressionFormat>)> {
+    let mut file_name = path.file_name()?.to_str()?;
+    let mut compression = Vec::new();
+
+    while let Some((stem, extension)) = file_name.rsplit_once('.') {
+        if let Some(format) = for_extension(extension) {
+            return Some((format, compression));
+        }
+
+        if let Some((_, compression_extension)) = TAR_SHORTHAND_EXTENSIONS.iter().find(|(ext, _)| *ext == extension) {
+            compression.push(crate::compress::formats::for_extension(compression_extension)?);
+            return Some((&tar::Tar, compression));
         }
+
+        compression.push(crate::compress::formats::for_extension(extension)?);
+        file_name = stem;
     }
 
     None
 }
+
+/// Convert a Windows FILETIME to a local date and time. Zero is commonly used
+/// to mean that no time was recorded.
+fn from_filetime(filetime: u64) -> Option<DateTime<Local>> {
+    if filetime == 0 {
+        return None;
+    }
+
+    let seconds = (filetime / 10_000_000) as i64 - WINDOWS_EPOCH_OFFSET;
+    let nanos = (filetime % 10_000_000) as u32 * 100;
+
+    Local.timestamp_opt(seconds, nanos).single()
+}
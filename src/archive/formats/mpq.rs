@@ -0,0 +1,262 @@
+//! http://www.zezula.net/en/mpq/mpqformat.html
+//!
+//! MPQ archives do not store the names of their files, only hashes of them.
+//! Most archives include a `(listfile)` naming the files they contain, which
+//! is used to enumerate entries.
+
+use crate::{
+    archive::{ArchiveReader, Entry, EntryType, Metadata},
+    input::Input,
+};
+use std::{
+    borrow::Cow,
+    fmt,
+    fs,
+    io::{self, Cursor, Error, ErrorKind, Read, Result, Write},
+    path::{Path, PathBuf},
+};
+use tempfile::NamedTempFile;
+
+const MPQ_SIGNATURE: &[u8] = b"MPQ\x1a";
+
+/// Signature of the user data header that may precede the archive header.
+const USER_DATA_SIGNATURE: &[u8] = b"MPQ\x1b";
+
+const LISTFILE: &str = "(listfile)";
+
+/// Limit on how many times larger the contents of a file may be than the whole
+/// archive. Deflate, which most archives use, compresses by a factor of 1032 at
+/// most. The limit guards against allocating whatever size a corrupt archive
+/// claims a file has, since files can only be read all at once.
+const MAX_COMPRESSION_RATIO: u64 = 1032;
+
+/// Format provider for MPQ.
+pub struct Mpq;
+
+impl super::Format for Mpq {
+    fn id(&self) -> &str {
+        "mpq"
+    }
+
+    fn file_extensions(&self) -> &[&str] {
+        &["mpq"]
+    }
+
+    fn match_bytes(&self, bytes: &[u8]) -> bool {
+        bytes.starts_with(MPQ_SIGNATURE) || bytes.starts_with(USER_DATA_SIGNATURE)
+    }
+}
+
+impl fmt::Display for Mpq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MPQ")
+    }
+}
+
+impl super::ArchiveFormat for Mpq {
+    fn open<'r>(&self, input: Input<'r>) -> Result<Box<dyn ArchiveReader + 'r>> {
+        Ok(Box::new(MpqReader::new(input)?))
+    }
+}
+
+struct MpqReader {
+    archive: mpq::Archive,
+
+    /// Size of the archive in bytes.
+    len: u64,
+
+    names: Vec<String>,
+    offset: usize,
+}
+
+impl MpqReader {
+    fn new(mut input: Input<'_>) -> Result<Self> {
+        // The mpq crate can only open archives by path.
+        let (mut archive, len) = match input.path() {
+            Some(path) => (mpq::Archive::open(path)?, fs::metadata(path)?.len()),
+            None => {
+                let mut file = NamedTempFile::new()?;
+                let len = io::copy(&mut input, &mut file)?;
+                file.flush()?;
+                (mpq::Archive::open(file.path())?, len)
+            }
+        };
+
+        let listfile = match archive.open_file(LISTFILE) {
+            Ok(file) => read_file(&mut archive, len, &file)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(Error::other(
+                "MPQ archive has no (listfile), so its entries cannot be listed",
+            )),
+            Err(e) => return Err(e),
+        };
+
+        // Names may be separated by line breaks or semicolons, and the list
+        // may name files that are not actually in the archive.
+        let names = String::from_utf8_lossy(&listfile)
+            .split(['\r', '\n', ';'])
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .filter(|name| match archive.open_file(name) {
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    log::debug!("skipping {} named in (listfile) but not found in archive", name);
+                    false
+                }
+                _ => true,
+            })
+            .map(String::from)
+            .collect();
+
+        Ok(Self {
+            archive,
+            len,
+            names,
+            offset: 0,
+        })
+    }
+}
+
+impl ArchiveReader for MpqReader {
+    fn len(&mut self) -> Option<u64> {
+        Some(self.names.len() as u64)
+    }
+
+    fn entry(&mut self) -> Result<Option<Box<dyn Entry + '_>>> {
+        if let Some(name) = self.names.get(self.offset) {
+            self.offset += 1;
+
+            let file = match self.archive.open_file(name) {
+                Ok(file) => Some(file),
+                // The mpq crate refuses to open empty files.
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => None,
+                Err(e) => return Err(e),
+            };
+
+            Ok(Some(Box::new(MpqEntry {
+                path: PathBuf::from(name.replace('\\', "/")),
+                metadata: Metadata::builder()
+                    .entry_type(EntryType::File)
+                    .size(file.as_ref().map_or(0, |file| file.size().into()))
+                    .build(),
+                archive: &mut self.archive,
+                archive_len: self.len,
+                file,
+                data: None,
+            })))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+struct MpqEntry<'a> {
+    path: PathBuf,
+    metadata: Metadata,
+    archive: &'a mut mpq::Archive,
+    archive_len: u64,
+    file: Option<mpq::File>,
+
+    /// Decompressed file contents, read on first use.
+    data: Option<Cursor<Vec<u8>>>,
+}
+
+impl Entry for MpqEntry<'_> {
+    fn path(&self) -> Cow<'_, Path> {
+        self.path.as_path().into()
+    }
+
+    fn metadata(&self) -> Metadata {
+        self.metadata.clone()
+    }
+}
+
+impl Read for MpqEntry<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.data.is_none() {
+            let data = match self.file.as_ref() {
+                Some(file) => read_file(self.archive, self.archive_len, file)?,
+                None => Vec::new(),
+            };
+
+            self.data = Some(Cursor::new(data));
+        }
+
+        self.data.as_mut().unwrap().read(buf)
+    }
+}
+
+/// Read and decompress the entire contents of a file, which is the only way
+/// the mpq crate allows files to be read.
+fn read_file(archive: &mut mpq::Archive, archive_len: u64, file: &mpq::File) -> Result<Vec<u8>> {
+    if u64::from(file.size()) > archive_len.saturating_mul(MAX_COMPRESSION_RATIO) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("corrupt MPQ archive: file size {} is too large for an archive of {} bytes", file.size(), archive_len),
+        ));
+    }
+
+    let mut data = vec![0; file.size() as usize];
+    let len = file.read(archive, &mut data)?;
+    data.truncate(len);
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{self, ArchiveFormat};
+
+    fn lines() -> String {
+        (0..50)
+            .map(|i| format!("line {}: the quick brown fox jumps over {} lazy dogs\n", i, i * i % 97))
+            .collect()
+    }
+
+    /// Read the path, size, and contents of every entry of an archive.
+    fn read_all(bytes: &[u8]) -> Result<Vec<(String, u64, String)>> {
+        let mut reader = Mpq.open(Input::from_reader(bytes)?)?;
+        let mut entries = Vec::new();
+
+        while let Some(mut entry) = reader.entry()? {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+
+            entries.push((entry.path().to_string_lossy().into_owned(), entry.metadata().size, contents));
+        }
+
+        Ok(entries)
+    }
+
+    #[test]
+    fn read_fixtures() {
+        // The (listfile) also names a file that is not in the archives, which
+        // is skipped. The fixtures were created by `make-mpq.py`, and store
+        // `dir\lines.txt` compressed in sectors.
+        let expected = vec![
+            ("hello.txt".to_owned(), 21, "hello from a fixture\n".to_owned()),
+            ("dir/lines.txt".to_owned(), lines().len() as u64, lines()),
+        ];
+
+        assert_eq!(read_all(include_bytes!("../../../tests/fixtures/sample.mpq")).unwrap(), expected);
+        assert_eq!(read_all(include_bytes!("../../../tests/fixtures/sample-user-data.mpq")).unwrap(), expected);
+    }
+
+    #[test]
+    fn detect_fixtures() {
+        for bytes in &[
+            &include_bytes!("../../../tests/fixtures/sample.mpq")[..],
+            &include_bytes!("../../../tests/fixtures/sample-user-data.mpq")[..],
+        ] {
+            let format = archive::detect(&mut Input::from_reader(*bytes).unwrap()).unwrap().unwrap();
+
+            assert_eq!(format.id(), "mpq");
+        }
+    }
+
+    #[test]
+    fn oversized_file_is_an_error() {
+        let error = read_all(include_bytes!("../../../tests/fixtures/sample-oversized.mpq")).unwrap_err();
+
+        assert!(error.to_string().contains("too large"), "unexpected error: {}", error);
+    }
+}
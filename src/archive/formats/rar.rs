@@ -0,0 +1,625 @@
+//! https://www.rarlab.com/technote.htm
+//!
+//! RAR archives are read using the UnRAR library, which only knows how to open
+//! archives by path and hands out entry data through a callback. Inputs that
+//! are not plain files are copied to a temporary file first, and the data of
+//! each entry is buffered as it is extracted so that it can be served through
+//! `Read`.
+
+use crate::{
+    archive::{ArchiveReader, Entry, EntryType, Metadata},
+    input::Input,
+};
+use std::{
+    borrow::Cow,
+    fmt,
+    fs,
+    io::{self, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write},
+    mem,
+    os::raw::{c_char, c_int, c_uint},
+    path::{Path, PathBuf},
+    ptr,
+    slice,
+};
+use tempfile::{NamedTempFile, SpooledTempFile};
+use unrar_sys::*;
+
+const RAR4_SIGNATURE: &[u8] = b"Rar!\x1a\x07\x00";
+const RAR5_SIGNATURE: &[u8] = b"Rar!\x1a\x07\x01\x00";
+
+/// Entry data larger than this is buffered on disk instead of in memory.
+const MAX_MEMORY_BUFFER: usize = 8 * 1024 * 1024;
+
+/// Host OS values reported for entries.
+const HOST_UNIX: u32 = 3;
+
+/// Link types reported for entries.
+const FSREDIR_UNIXSYMLINK: u32 = 1;
+const FSREDIR_WINSYMLINK: u32 = 2;
+const FSREDIR_HARDLINK: u32 = 4;
+
+/// Windows file attributes.
+const FILE_ATTRIBUTE_READONLY: u32 = 0x1;
+const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+
+/// Unix file type bits.
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Format provider for RAR.
+pub struct Rar;
+
+impl super::Format for Rar {
+    fn id(&self) -> &str {
+        "rar"
+    }
+
+    fn file_extensions(&self) -> &[&str] {
+        &["rar"]
+    }
+
+    fn match_bytes(&self, bytes: &[u8]) -> bool {
+        bytes.starts_with(RAR4_SIGNATURE) || bytes.starts_with(RAR5_SIGNATURE)
+    }
+}
+
+impl fmt::Display for Rar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RAR")
+    }
+}
+
+impl super::ArchiveFormat for Rar {
+    fn open<'r>(&self, input: Input<'r>) -> Result<Box<dyn ArchiveReader + 'r>> {
+        Ok(Box::new(RarReader::new(input)?))
+    }
+}
+
+/// State shared with the UnRAR callback.
+#[derive(Default)]
+struct CallbackState {
+    /// Data of the entry currently being extracted.
+    data: Option<SpooledTempFile>,
+
+    /// Error that occurred while buffering entry data.
+    error: Option<Error>,
+
+    /// Name of a volume that was requested but could not be found.
+    missing_volume: Option<String>,
+
+    /// Whether a password was requested.
+    password_requested: bool,
+}
+
+extern "C" fn callback(message: UINT, user_data: LPARAM, p1: LPARAM, p2: LPARAM) -> c_int {
+    let state = unsafe { &mut *(user_data as *mut CallbackState) };
+
+    match message {
+        UCM_PROCESSDATA => {
+            let bytes = unsafe { slice::from_raw_parts(p1 as *const u8, p2 as usize) };
+
+            if let Some(data) = state.data.as_mut() {
+                if let Err(e) = data.write_all(bytes) {
+                    state.error = Some(e);
+                    return -1;
+                }
+            }
+
+            1
+        }
+
+        UCM_CHANGEVOLUMEW => {
+            if p2 == RAR_VOL_ASK {
+                state.missing_volume = Some(unsafe { from_wide_ptr(p1 as *const WCHAR) });
+                -1
+            } else {
+                1
+            }
+        }
+
+        UCM_NEEDPASSWORD | UCM_NEEDPASSWORDW => {
+            state.password_requested = true;
+            -1
+        }
+
+        _ => 0,
+    }
+}
+
+/// Entry header as declared by UnRAR.
+///
+/// The library declares its structures with 1-byte packing, which the
+/// definition of `HeaderDataEx` in `unrar_sys` does not account for, causing
+/// every field after the first pointer to be misplaced.
+#[repr(C, packed)]
+struct HeaderData {
+    archive_name: [c_char; 1024],
+    archive_name_w: [WCHAR; 1024],
+    filename: [c_char; 1024],
+    filename_w: [WCHAR; 1024],
+    flags: c_uint,
+    pack_size: c_uint,
+    pack_size_high: c_uint,
+    unp_size: c_uint,
+    unp_size_high: c_uint,
+    host_os: c_uint,
+    file_crc: c_uint,
+    file_time: c_uint,
+    unp_ver: c_uint,
+    method: c_uint,
+    file_attr: c_uint,
+    comment_buffer: *mut c_char,
+    comment_buffer_size: c_uint,
+    comment_size: c_uint,
+    comment_state: c_uint,
+    dict_size: c_uint,
+    hash_type: c_uint,
+    hash: [c_char; 32],
+    redir_type: c_uint,
+    redir_name: *mut WCHAR,
+    redir_name_size: c_uint,
+    dir_target: c_uint,
+    mtime_low: c_uint,
+    mtime_high: c_uint,
+    ctime_low: c_uint,
+    ctime_high: c_uint,
+    atime_low: c_uint,
+    atime_high: c_uint,
+    reserved: [c_uint; 988],
+}
+
+impl Default for HeaderData {
+    fn default() -> Self {
+        // All fields are plain integers or nullable pointers.
+        unsafe { mem::zeroed() }
+    }
+}
+
+struct RarReader {
+    handle: Handle,
+    state: Box<CallbackState>,
+
+    /// Copy of the input, for inputs that are not files on disk.
+    temp_file: Option<NamedTempFile>,
+
+    /// Whether the current entry has been processed already, either by
+    /// extracting it or by skipping over it.
+    processed: bool,
+}
+
+impl RarReader {
+    fn new(mut input: Input<'_>) -> Result<Self> {
+        let (path, temp_file) = match input.path().filter(|path| path.to_str().is_some()) {
+            Some(path) => (path.to_owned(), None),
+            None => {
+                let mut file = NamedTempFile::new()?;
+                io::copy(&mut input, &mut file)?;
+                file.flush()?;
+                (file.path().to_owned(), Some(file))
+            }
+        };
+
+        let mut state = Box::<CallbackState>::default();
+        let name = to_wide(path.to_str().unwrap());
+
+        let mut data = OpenArchiveDataEx {
+            archive_name_w: name.as_ptr(),
+            open_mode: RAR_OM_EXTRACT,
+            callback: Some(callback),
+            user_data: &mut *state as *mut CallbackState as LPARAM,
+            ..OpenArchiveDataEx::default()
+        };
+
+        let handle = unsafe { RAROpenArchiveEx(&mut data) };
+
+        let reader = Self {
+            handle,
+            state,
+            temp_file,
+            processed: true,
+        };
+
+        if data.flags & ROADF_ENCHEADERS != 0 || reader.state.password_requested {
+            return Err(encrypted_error());
+        }
+
+        if handle.is_null() || data.open_result as c_int != ERAR_SUCCESS {
+            return Err(reader.error(data.open_result as c_int));
+        }
+
+        if data.flags & ROADF_VOLUME != 0 {
+            log::debug!("archive is part of a multi-volume set");
+        }
+
+        if data.flags & ROADF_SOLID != 0 {
+            log::debug!("archive is solid, entries must be read in order");
+        }
+
+        Ok(reader)
+    }
+
+    /// Convert an UnRAR error code to an I/O error, taking into account
+    /// anything recorded by the callback.
+    fn error(&self, code: c_int) -> Error {
+        if let Some(volume) = self.state.missing_volume.as_ref() {
+            if self.temp_file.is_some() {
+                return Error::new(ErrorKind::NotFound, "multi-volume RAR archives can only be read from a file on disk");
+            }
+
+            return Error::new(ErrorKind::NotFound, format!("next volume of multi-volume RAR archive not found: {}", volume));
+        }
+
+        if self.state.password_requested {
+            return encrypted_error();
+        }
+
+        match code {
+            ERAR_NO_MEMORY => Error::other("not enough memory to read RAR archive"),
+            ERAR_BAD_DATA => Error::new(ErrorKind::InvalidData, "corrupt RAR archive: CRC check failed"),
+            ERAR_BAD_ARCHIVE => Error::new(ErrorKind::InvalidData, "not a valid RAR archive"),
+            ERAR_UNKNOWN_FORMAT => Error::new(ErrorKind::InvalidData, "unsupported RAR archive format"),
+            ERAR_EOPEN => Error::new(ErrorKind::NotFound, "failed to open RAR archive"),
+            ERAR_EREAD => Error::other("failed to read RAR archive"),
+            ERAR_MISSING_PASSWORD | ERAR_BAD_PASSWORD => encrypted_error(),
+            code => Error::other(format!("failed to read RAR archive (UnRAR error {})", code)),
+        }
+    }
+
+    /// Process the data of the current entry, either extracting it into the
+    /// data buffer or skipping over it.
+    fn process(&mut self, extract: bool) -> Result<()> {
+        if self.processed {
+            return Ok(());
+        }
+
+        self.processed = true;
+        self.state.data = if extract {
+            Some(SpooledTempFile::new(MAX_MEMORY_BUFFER))
+        } else {
+            None
+        };
+
+        let operation = if extract { RAR_TEST } else { RAR_SKIP };
+        let code = unsafe { RARProcessFile(self.handle, operation, ptr::null(), ptr::null()) };
+
+        if let Some(e) = self.state.error.take() {
+            return Err(e);
+        }
+
+        if code != ERAR_SUCCESS {
+            return Err(self.error(code));
+        }
+
+        if let Some(data) = self.state.data.as_mut() {
+            data.seek(SeekFrom::Start(0))?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the target of the current entry if it is a symbolic link stored the
+    /// way older archives store them, with the target as the entry data.
+    ///
+    /// UnRAR does not hand out the data of such links, and only uses it to
+    /// create the link itself, so let it do that in a temporary directory and
+    /// read the link back.
+    #[cfg(unix)]
+    fn extract_link_target(&mut self, path: &Path) -> Result<Option<PathBuf>> {
+        if self.processed {
+            return Ok(None);
+        }
+
+        self.processed = true;
+        self.state.data = None;
+
+        let dir = tempfile::tempdir()?;
+        let link = dir.path().join("link");
+        let name = to_wide(&link.to_string_lossy());
+
+        let code = unsafe { RARProcessFileW(self.handle, RAR_EXTRACT, ptr::null(), name.as_ptr()) };
+
+        if code != ERAR_SUCCESS {
+            return Err(self.error(code));
+        }
+
+        // UnRAR refuses to create links with absolute targets or targets that
+        // point outside of the archive.
+        match fs::read_link(&link) {
+            Ok(target) => Ok(Some(target)),
+            Err(e) => {
+                log::warn!("cannot read target of symbolic link {}: {}", path.display(), e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// UnRAR only creates Unix symbolic links on Unix.
+    #[cfg(not(unix))]
+    fn extract_link_target(&mut self, _path: &Path) -> Result<Option<PathBuf>> {
+        Ok(None)
+    }
+}
+
+impl ArchiveReader for RarReader {
+    fn entry(&mut self) -> Result<Option<Box<dyn Entry + '_>>> {
+        // UnRAR requires the previous entry to be processed before the next
+        // header can be read.
+        self.process(false)?;
+
+        let mut header = Box::new(HeaderData::default());
+        let mut link_target = vec![0 as WCHAR; 1024];
+        header.redir_name = link_target.as_mut_ptr();
+        header.redir_name_size = link_target.len() as u32;
+
+        match unsafe { RARReadHeaderEx(self.handle, &mut *header as *mut HeaderData as *mut HeaderDataEx) } {
+            ERAR_SUCCESS => {}
+            ERAR_END_ARCHIVE => return Ok(None),
+            code => return Err(self.error(code)),
+        }
+
+        self.processed = false;
+
+        let filename = header.filename_w;
+        let path = PathBuf::from(from_wide(&filename));
+        let link_target = match header.redir_type {
+            0 => None,
+            _ => Some(from_wide(&link_target)).filter(|target| !target.is_empty()).map(PathBuf::from),
+        };
+
+        let unix = header.host_os == HOST_UNIX;
+
+        let entry_type = if header.flags & RHDF_DIRECTORY != 0 {
+            EntryType::Directory
+        } else {
+            match header.redir_type {
+                FSREDIR_UNIXSYMLINK | FSREDIR_WINSYMLINK => EntryType::SymbolicLink,
+                FSREDIR_HARDLINK => EntryType::HardLink,
+                _ if unix && header.file_attr & S_IFMT == S_IFLNK => EntryType::SymbolicLink,
+                _ => EntryType::File,
+            }
+        };
+
+        let metadata = Metadata::builder()
+            .entry_type(entry_type)
+            .size(u64::from(header.unp_size) | u64::from(header.unp_size_high) << 32)
+            .compressed_size(Some(u64::from(header.pack_size) | u64::from(header.pack_size_high) << 32))
+            .modified(super::from_filetime(u64::from(header.mtime_low) | u64::from(header.mtime_high) << 32))
+            .read_only(!unix && header.file_attr & FILE_ATTRIBUTE_READONLY != 0)
+            .hidden(!unix && header.file_attr & FILE_ATTRIBUTE_HIDDEN != 0)
+            .unix_mode(if unix { Some(header.file_attr & 0o7777) } else { None })
+            .build();
+
+        Ok(Some(Box::new(RarEntry {
+            reader: self,
+            path,
+            metadata,
+            link_target,
+            encrypted: header.flags & RHDF_ENCRYPTED != 0,
+            continued: header.flags & RHDF_SPLITBEFORE != 0,
+        })))
+    }
+}
+
+impl Drop for RarReader {
+    fn drop(&mut self) {
+        if !self.handle.is_null() {
+            unsafe {
+                RARCloseArchive(self.handle);
+            }
+        }
+    }
+}
+
+struct RarEntry<'a> {
+    reader: &'a mut RarReader,
+    path: PathBuf,
+    metadata: Metadata,
+    link_target: Option<PathBuf>,
+    encrypted: bool,
+
+    /// Whether this entry starts in a previous volume.
+    continued: bool,
+}
+
+impl Entry for RarEntry<'_> {
+    fn path(&self) -> Cow<'_, Path> {
+        self.path.as_path().into()
+    }
+
+    fn metadata(&self) -> Metadata {
+        self.metadata.clone()
+    }
+
+    fn read_link(&mut self) -> Result<Option<Cow<'_, Path>>> {
+        if !matches!(self.metadata.entry_type, EntryType::SymbolicLink | EntryType::HardLink) {
+            return Ok(None);
+        }
+
+        if self.link_target.is_none() {
+            self.link_target = self.reader.extract_link_target(&self.path)?;
+        }
+
+        Ok(self.link_target.as_deref().map(Cow::from))
+    }
+}
+
+impl Read for RarEntry<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if !self.reader.processed {
+            if self.encrypted {
+                return Err(Error::other(format!("{} is encrypted, which is not supported", self.path.display())));
+            }
+
+            if self.continued {
+                return Err(Error::other(format!(
+                    "{} continues from a previous volume, open the first volume of the archive instead",
+                    self.path.display(),
+                )));
+            }
+
+            self.reader.process(true)?;
+        }
+
+        match self.reader.state.data.as_mut() {
+            Some(data) => data.read(buf),
+            None => Ok(0),
+        }
+    }
+}
+
+fn encrypted_error() -> Error {
+    Error::other("RAR archive is encrypted, which is not supported")
+}
+
+/// Convert a null-terminated wide string buffer to a string.
+fn from_wide(wide: &[WCHAR]) -> String {
+    let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+
+    #[cfg(windows)]
+    return String::from_utf16_lossy(&wide[..len]);
+
+    #[cfg(not(windows))]
+    return wide[..len].iter()
+        .map(|&c| char::from_u32(c as u32).unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+}
+
+/// Convert a null-terminated wide string pointer to a string.
+unsafe fn from_wide_ptr(wide: *const WCHAR) -> String {
+    let mut len = 0;
+
+    while *wide.add(len) != 0 {
+        len += 1;
+    }
+
+    from_wide(slice::from_raw_parts(wide, len))
+}
+
+/// Convert a string to a null-terminated wide string.
+fn to_wide(s: &str) -> Vec<WCHAR> {
+    #[cfg(windows)]
+    let mut wide = s.encode_utf16().map(|c| c as WCHAR).collect::<Vec<_>>();
+
+    #[cfg(not(windows))]
+    let mut wide = s.chars().map(|c| c as WCHAR).collect::<Vec<_>>();
+
+    wide.push(0);
+    wide
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::ArchiveFormat;
+    use chrono::prelude::*;
+
+    /// Modification time of every entry in the fixtures, which were created by
+    /// `make-rar.py`.
+    const MTIME: i64 = 1_622_548_800;
+
+    fn lines() -> String {
+        (0..50)
+            .map(|i| format!("line {}: the quick brown fox jumps over {} lazy dogs\n", i, i * i % 97))
+            .collect()
+    }
+
+    /// Path, type, mode, and contents or link target of an entry.
+    type Summary = (String, EntryType, Option<u32>, String);
+
+    /// Read every entry of an archive, along with its contents or link target,
+    /// checking that the metadata of each is read from the right fields of the
+    /// header data.
+    fn read_all(bytes: &[u8], mtime: i64) -> Result<Vec<Summary>> {
+        let mut reader = Rar.open(Input::from_reader(bytes)?)?;
+        let mut entries = Vec::new();
+
+        while let Some(mut entry) = reader.entry()? {
+            let metadata = entry.metadata();
+            assert_eq!(metadata.modified.map(|dt| dt.timestamp()), Some(mtime));
+
+            let contents = match metadata.entry_type {
+                EntryType::SymbolicLink => entry.read_link()?.unwrap().to_string_lossy().into_owned(),
+                _ => {
+                    let mut contents = String::new();
+                    entry.read_to_string(&mut contents)?;
+                    contents
+                }
+            };
+
+            // Entries are stored uncompressed, apart from the links of newer
+            // archives, which have no data at all.
+            if metadata.entry_type == EntryType::File {
+                assert_eq!(metadata.size, contents.len() as u64);
+                assert_eq!(metadata.compressed_size, Some(metadata.size));
+            }
+
+            entries.push((entry.path().to_string_lossy().into_owned(), metadata.entry_type, metadata.unix_mode, contents));
+        }
+
+        Ok(entries)
+    }
+
+    /// Read the data of every entry of an archive, as the test command does.
+    fn test_all(bytes: &[u8]) -> Result<()> {
+        let mut reader = Rar.open(Input::from_reader(bytes)?)?;
+
+        while let Some(mut entry) = reader.entry()? {
+            io::copy(&mut entry, &mut io::sink())?;
+        }
+
+        Ok(())
+    }
+
+    fn expected() -> Vec<Summary> {
+        vec![
+            ("hello.txt".to_owned(), EntryType::File, Some(0o640), "hello from a fixture\n".to_owned()),
+            ("dir/lines.txt".to_owned(), EntryType::File, Some(0o644), lines()),
+            ("link".to_owned(), EntryType::SymbolicLink, Some(0o777), "hello.txt".to_owned()),
+            ("dir".to_owned(), EntryType::Directory, Some(0o755), String::new()),
+        ]
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_rar4() {
+        // Older archives record times in local time.
+        let mtime = Local.with_ymd_and_hms(2021, 6, 1, 12, 0, 0).unwrap().timestamp();
+
+        assert_eq!(read_all(include_bytes!("../../../tests/fixtures/sample-rar4.rar"), mtime).unwrap(), expected());
+    }
+
+    #[test]
+    fn read_rar5() {
+        assert_eq!(read_all(include_bytes!("../../../tests/fixtures/sample-rar5.rar"), MTIME).unwrap(), expected());
+    }
+
+    #[test]
+    fn corrupt_archive_is_an_error() {
+        for bytes in &[
+            &include_bytes!("../../../tests/fixtures/sample-rar4.rar")[..],
+            &include_bytes!("../../../tests/fixtures/sample-rar5.rar")[..],
+        ] {
+            // Damage the name of the first entry, and separately its data.
+            for pattern in &[&b"hello.txt"[..], &b"hello from"[..]] {
+                let mut bytes = bytes.to_vec();
+                let offset = bytes.windows(pattern.len()).position(|window| window == *pattern).unwrap();
+                bytes[offset] = b'j';
+
+                let error = test_all(&bytes).unwrap_err();
+                assert_eq!(error.kind(), ErrorKind::InvalidData, "unexpected error: {}", error);
+            }
+        }
+    }
+
+    #[test]
+    fn encrypted_entry_is_an_error() {
+        let bytes = include_bytes!("../../../tests/fixtures/sample-encrypted.rar");
+        let mut reader = Rar.open(Input::from_reader(&bytes[..]).unwrap()).unwrap();
+        let mut entry = reader.entry().unwrap().unwrap();
+
+        assert_eq!(entry.path(), Path::new("hello.txt"));
+
+        let error = entry.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(error.to_string().contains("encrypted"), "unexpected error: {}", error);
+    }
+}
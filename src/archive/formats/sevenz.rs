@@ -0,0 +1,1250 @@
+//! https://www.7-zip.org/sdk.html (DOC/7zFormat.txt)
+//!
+//! A 7z archive is a short signature header followed by packed streams and a
+//! header at the end of the file describing how they are laid out. Files are
+//! grouped into folders, each of which is a single stream compressed by a
+//! chain of coders. Solid archives store many files in one folder, so files
+//! have to be decompressed in order.
+
+use crate::{
+    archive::{ArchiveReader, Entry, EntryType, Metadata},
+    input::Input,
+};
+use lzma_sys::*;
+use std::{
+    borrow::Cow,
+    convert::TryInto,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Error, ErrorKind, Read, Result, Seek, SeekFrom},
+    mem,
+    path::{Path, PathBuf},
+    ptr,
+};
+
+const SIGNATURE: &[u8] = b"7z\xbc\xaf\x27\x1c";
+const SIGNATURE_HEADER_SIZE: u64 = 32;
+
+/// IDs of the properties that make up the header.
+mod property {
+    pub const END: u8 = 0x00;
+    pub const HEADER: u8 = 0x01;
+    pub const ARCHIVE_PROPERTIES: u8 = 0x02;
+    pub const ADDITIONAL_STREAMS_INFO: u8 = 0x03;
+    pub const MAIN_STREAMS_INFO: u8 = 0x04;
+    pub const FILES_INFO: u8 = 0x05;
+    pub const PACK_INFO: u8 = 0x06;
+    pub const UNPACK_INFO: u8 = 0x07;
+    pub const SUBSTREAMS_INFO: u8 = 0x08;
+    pub const SIZE: u8 = 0x09;
+    pub const CRC: u8 = 0x0a;
+    pub const FOLDER: u8 = 0x0b;
+    pub const CODERS_UNPACK_SIZE: u8 = 0x0c;
+    pub const NUM_UNPACK_STREAM: u8 = 0x0d;
+    pub const EMPTY_STREAM: u8 = 0x0e;
+    pub const EMPTY_FILE: u8 = 0x0f;
+    pub const ANTI: u8 = 0x10;
+    pub const NAMES: u8 = 0x11;
+    pub const MTIME: u8 = 0x14;
+    pub const WIN_ATTRIBUTES: u8 = 0x15;
+    pub const ENCODED_HEADER: u8 = 0x17;
+}
+
+/// IDs of coders.
+mod method {
+    pub const COPY: &[u8] = &[0x00];
+    pub const LZMA: &[u8] = &[0x03, 0x01, 0x01];
+    pub const LZMA2: &[u8] = &[0x21];
+    pub const BCJ: &[u8] = &[0x03, 0x03, 0x01, 0x03];
+    pub const AES: &[u8] = &[0x06, 0xf1, 0x07, 0x01];
+}
+
+const FILE_ATTRIBUTE_READONLY: u32 = 0x1;
+const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+
+/// Set by p7zip and others when the high 16 bits of the attributes hold a Unix
+/// file mode.
+const FILE_ATTRIBUTE_UNIX_EXTENSION: u32 = 0x8000;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Format provider for 7z.
+pub struct SevenZ;
+
+impl super::Format for SevenZ {
+    fn id(&self) -> &str {
+        "7z"
+    }
+
+    fn file_extensions(&self) -> &[&str] {
+        &["7z"]
+    }
+
+    fn match_bytes(&self, bytes: &[u8]) -> bool {
+        bytes.starts_with(SIGNATURE)
+    }
+}
+
+impl fmt::Display for SevenZ {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("7z")
+    }
+}
+
+impl super::ArchiveFormat for SevenZ {
+    fn open<'r>(&self, input: Input<'r>) -> Result<Box<dyn ArchiveReader + 'r>> {
+        Ok(Box::new(SevenZReader::new(input.into_file()?)?))
+    }
+}
+
+fn corrupt(message: impl fmt::Display) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("corrupt 7z archive: {}", message))
+}
+
+fn encrypted_error() -> Error {
+    Error::other("7z archive is encrypted, which is not supported")
+}
+
+#[derive(Debug)]
+struct Coder {
+    id: Vec<u8>,
+    num_in_streams: usize,
+    num_out_streams: usize,
+    properties: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct Folder {
+    coders: Vec<Coder>,
+
+    /// Pairs of coder input and output stream indexes that are connected.
+    bind_pairs: Vec<(usize, usize)>,
+
+    /// Coder input stream indexes that are read from packed streams.
+    packed_streams: Vec<usize>,
+
+    /// Sizes of each coder output stream.
+    unpack_sizes: Vec<u64>,
+
+    crc: Option<u32>,
+}
+
+impl Folder {
+    /// Index of the output stream that is not bound to another coder, which
+    /// produces the final decoded data.
+    fn main_out_stream(&self) -> Option<usize> {
+        (0..self.unpack_sizes.len()).find(|&i| !self.bind_pairs.iter().any(|&(_, out)| out == i))
+    }
+
+    fn unpack_size(&self) -> u64 {
+        self.main_out_stream().map_or(0, |i| self.unpack_sizes[i])
+    }
+}
+
+/// A file stored within a folder.
+#[derive(Debug)]
+struct Substream {
+    size: u64,
+    crc: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+struct StreamsInfo {
+    pack_pos: u64,
+    pack_sizes: Vec<u64>,
+    folders: Vec<Folder>,
+
+    /// Files stored in each folder.
+    substreams: Vec<Vec<Substream>>,
+}
+
+impl StreamsInfo {
+    /// Get the absolute file offset and size of the packed streams used by
+    /// the given folder.
+    fn pack_streams(&self, folder: usize) -> Vec<(u64, u64)> {
+        let first = self.folders[..folder].iter()
+            .map(|folder| folder.packed_streams.len())
+            .sum::<usize>();
+        let count = self.folders[folder].packed_streams.len();
+
+        let mut offset = SIGNATURE_HEADER_SIZE + self.pack_pos + self.pack_sizes[..first].iter().sum::<u64>();
+
+        self.pack_sizes[first..first + count].iter()
+            .map(|&size| {
+                offset += size;
+                (offset - size, size)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default)]
+struct FileInfo {
+    name: String,
+    has_stream: bool,
+    is_dir: bool,
+    is_anti: bool,
+    mtime: Option<u64>,
+    attributes: Option<u32>,
+
+    /// The folder containing the data of this file, the offset of the file
+    /// within the decoded folder, and its size.
+    stream: Option<(usize, u64, u64)>,
+    crc: Option<u32>,
+}
+
+/// Cursor over the bytes of a header.
+struct HeaderReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> HeaderReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(corrupt("unexpected end of header"));
+        }
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    /// Read a variable-length number, where the number of leading one bits
+    /// in the first byte gives the number of bytes that follow.
+    fn number(&mut self) -> Result<u64> {
+        let first = self.u8()?;
+        let mut value = 0;
+
+        for i in 0..8 {
+            let mask = 0x80 >> i;
+
+            if first & mask == 0 {
+                return Ok(value | u64::from(first & (mask - 1)) << (8 * i));
+            }
+
+            value |= u64::from(self.u8()?) << (8 * i);
+        }
+
+        Ok(value)
+    }
+
+    /// Read a number that is used as a count or index.
+    fn count(&mut self) -> Result<usize> {
+        let value = self.number()?;
+
+        // Every counted item takes up at least one bit of the header, which
+        // guards against huge allocations for corrupt headers.
+        if value > self.data.len() as u64 * 8 {
+            return Err(corrupt("count out of range"));
+        }
+
+        Ok(value as usize)
+    }
+
+    fn bit_vector(&mut self, len: usize) -> Result<Vec<bool>> {
+        let bytes = self.bytes(len.div_ceil(8))?;
+
+        Ok((0..len).map(|i| bytes[i / 8] & (0x80 >> (i % 8)) != 0).collect())
+    }
+
+    /// Read a bit vector preceded by a flag indicating that all bits are set.
+    fn defined_vector(&mut self, len: usize) -> Result<Vec<bool>> {
+        if self.u8()? != 0 {
+            Ok(vec![true; len])
+        } else {
+            self.bit_vector(len)
+        }
+    }
+
+    fn digests(&mut self, len: usize) -> Result<Vec<Option<u32>>> {
+        self.defined_vector(len)?
+            .into_iter()
+            .map(|defined| if defined { self.u32().map(Some) } else { Ok(None) })
+            .collect()
+    }
+
+    fn expect(&mut self, id: u8) -> Result<()> {
+        match self.u8()? {
+            byte if byte == id => Ok(()),
+            byte => Err(corrupt(format_args!("expected property {:#x}, found {:#x}", id, byte))),
+        }
+    }
+
+    fn skip_external(&mut self) -> Result<()> {
+        match self.u8()? {
+            0 => Ok(()),
+            _ => Err(corrupt("external header data is not supported")),
+        }
+    }
+
+    fn header(&mut self) -> Result<(StreamsInfo, Vec<FileInfo>)> {
+        let mut info = StreamsInfo::default();
+        let mut files = Vec::new();
+
+        loop {
+            match self.u8()? {
+                property::END => break,
+                property::ARCHIVE_PROPERTIES => self.skip_archive_properties()?,
+                property::ADDITIONAL_STREAMS_INFO => {
+                    self.streams_info()?;
+                }
+                property::MAIN_STREAMS_INFO => info = self.streams_info()?,
+                property::FILES_INFO => files = self.files_info()?,
+                id => return Err(corrupt(format_args!("unexpected property {:#x} in header", id))),
+            }
+        }
+
+        // Assign the substreams of each folder to the files with data, in
+        // order.
+        let mut substreams = info.substreams.iter()
+            .enumerate()
+            .flat_map(|(folder, substreams)| {
+                substreams.iter().scan(0, move |offset, substream| {
+                    *offset += substream.size;
+                    Some((folder, *offset - substream.size, substream))
+                })
+            });
+
+        for file in files.iter_mut().filter(|file| file.has_stream) {
+            let (folder, offset, substream) = substreams.next().ok_or_else(|| corrupt("more files than streams"))?;
+
+            file.stream = Some((folder, offset, substream.size));
+            file.crc = substream.crc;
+        }
+
+        Ok((info, files))
+    }
+
+    fn streams_info(&mut self) -> Result<StreamsInfo> {
+        let mut info = StreamsInfo::default();
+        let mut has_substreams = false;
+
+        loop {
+            match self.u8()? {
+                property::END => break,
+                property::PACK_INFO => self.pack_info(&mut info)?,
+                property::UNPACK_INFO => self.unpack_info(&mut info)?,
+                property::SUBSTREAMS_INFO => {
+                    self.substreams_info(&mut info)?;
+                    has_substreams = true;
+                }
+                id => return Err(corrupt(format_args!("unexpected property {:#x} in streams info", id))),
+            }
+        }
+
+        // Check that the folders use exactly the packed streams that were
+        // listed, and that their offsets cannot overflow, so that they can be
+        // located without further checks.
+        let num_packed_streams = info.folders.iter()
+            .map(|folder| folder.packed_streams.len())
+            .sum::<usize>();
+
+        if num_packed_streams != info.pack_sizes.len() {
+            return Err(corrupt("number of packed streams does not match pack info"));
+        }
+
+        let pack_end = info.pack_sizes.iter()
+            .try_fold(info.pack_pos, |offset, &size| offset.checked_add(size))
+            .and_then(|end| end.checked_add(SIGNATURE_HEADER_SIZE));
+
+        if pack_end.is_none() {
+            return Err(corrupt("packed streams are too large"));
+        }
+
+        // Without substreams info, each folder holds a single stream.
+        if !has_substreams {
+            info.substreams = info.folders.iter()
+                .map(|folder| vec![Substream {
+                    size: folder.unpack_size(),
+                    crc: folder.crc,
+                }])
+                .collect();
+        }
+
+        Ok(info)
+    }
+
+    fn pack_info(&mut self, info: &mut StreamsInfo) -> Result<()> {
+        info.pack_pos = self.number()?;
+        let count = self.count()?;
+
+        loop {
+            match self.u8()? {
+                property::END => break,
+                property::SIZE => {
+                    info.pack_sizes = (0..count).map(|_| self.number()).collect::<Result<_>>()?;
+                }
+                property::CRC => {
+                    self.digests(count)?;
+                }
+                id => return Err(corrupt(format_args!("unexpected property {:#x} in pack info", id))),
+            }
+        }
+
+        if info.pack_sizes.len() != count {
+            return Err(corrupt("missing pack sizes"));
+        }
+
+        Ok(())
+    }
+
+    fn unpack_info(&mut self, info: &mut StreamsInfo) -> Result<()> {
+        self.expect(property::FOLDER)?;
+        let count = self.count()?;
+        self.skip_external()?;
+
+        info.folders = (0..count).map(|_| self.folder()).collect::<Result<_>>()?;
+
+        self.expect(property::CODERS_UNPACK_SIZE)?;
+
+        for folder in &mut info.folders {
+            let num_out_streams = folder.coders.iter().map(|coder| coder.num_out_streams).sum();
+            folder.unpack_sizes = (0..num_out_streams).map(|_| self.number()).collect::<Result<_>>()?;
+        }
+
+        loop {
+            match self.u8()? {
+                property::END => break,
+                property::CRC => {
+                    for (folder, crc) in info.folders.iter_mut().zip(self.digests(count)?) {
+                        folder.crc = crc;
+                    }
+                }
+                id => return Err(corrupt(format_args!("unexpected property {:#x} in unpack info", id))),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn folder(&mut self) -> Result<Folder> {
+        let mut folder = Folder::default();
+        let num_coders = self.count()?;
+
+        for _ in 0..num_coders {
+            let flags = self.u8()?;
+
+            if flags & 0x80 != 0 {
+                return Err(corrupt("alternative coder methods are not supported"));
+            }
+
+            let id = self.bytes((flags & 0x0f) as usize)?.to_vec();
+
+            let (num_in_streams, num_out_streams) = if flags & 0x10 != 0 {
+                (self.count()?, self.count()?)
+            } else {
+                (1, 1)
+            };
+
+            let properties = if flags & 0x20 != 0 {
+                let len = self.count()?;
+                self.bytes(len)?.to_vec()
+            } else {
+                Vec::new()
+            };
+
+            folder.coders.push(Coder {
+                id,
+                num_in_streams,
+                num_out_streams,
+                properties,
+            });
+        }
+
+        let total_in_streams = folder.coders.iter().map(|coder| coder.num_in_streams).sum::<usize>();
+        let total_out_streams = folder.coders.iter().map(|coder| coder.num_out_streams).sum::<usize>();
+
+        if total_out_streams == 0 || total_in_streams < total_out_streams - 1 {
+            return Err(corrupt("invalid coder streams"));
+        }
+
+        for _ in 0..total_out_streams - 1 {
+            let (input, output) = (self.count()?, self.count()?);
+
+            if input >= total_in_streams || output >= total_out_streams {
+                return Err(corrupt("bind pair refers to a missing coder stream"));
+            }
+
+            folder.bind_pairs.push((input, output));
+        }
+
+        let num_packed_streams = total_in_streams - folder.bind_pairs.len();
+
+        if num_packed_streams == 1 {
+            let stream = (0..total_in_streams)
+                .find(|&i| !folder.bind_pairs.iter().any(|&(input, _)| input == i))
+                .ok_or_else(|| corrupt("no packed stream in folder"))?;
+
+            folder.packed_streams.push(stream);
+        } else {
+            for _ in 0..num_packed_streams {
+                let stream = self.count()?;
+
+                if stream >= total_in_streams {
+                    return Err(corrupt("packed stream refers to a missing coder stream"));
+                }
+
+                folder.packed_streams.push(stream);
+            }
+        }
+
+        Ok(folder)
+    }
+
+    fn substreams_info(&mut self, info: &mut StreamsInfo) -> Result<()> {
+        let mut counts = vec![1; info.folders.len()];
+        let mut property = self.u8()?;
+
+        if property == property::NUM_UNPACK_STREAM {
+            for count in &mut counts {
+                *count = self.count()?;
+            }
+
+            property = self.u8()?;
+        }
+
+        // Sizes are given for all but the last stream in each folder, which
+        // takes up the remainder of the folder.
+        let has_sizes = property == property::SIZE;
+
+        info.substreams = info.folders.iter()
+            .zip(&counts)
+            .map(|(folder, &count)| {
+                let mut substreams = Vec::with_capacity(count);
+                let mut remaining = folder.unpack_size();
+
+                for i in 0..count {
+                    let size = if i + 1 < count && has_sizes {
+                        self.number()?
+                    } else {
+                        remaining
+                    };
+
+                    remaining = remaining.checked_sub(size).ok_or_else(|| corrupt("substream sizes exceed folder size"))?;
+
+                    substreams.push(Substream {
+                        size,
+                        crc: if count == 1 { folder.crc } else { None },
+                    });
+                }
+
+                Ok(substreams)
+            })
+            .collect::<Result<_>>()?;
+
+        if has_sizes {
+            property = self.u8()?;
+        }
+
+        loop {
+            match property {
+                property::END => break,
+                property::CRC => {
+                    let mut unknown = info.substreams.iter_mut()
+                        .flatten()
+                        .filter(|substream| substream.crc.is_none())
+                        .collect::<Vec<_>>();
+
+                    let digests = self.digests(unknown.len())?;
+
+                    for (substream, crc) in unknown.iter_mut().zip(digests) {
+                        substream.crc = crc;
+                    }
+                }
+                id => return Err(corrupt(format_args!("unexpected property {:#x} in substreams info", id))),
+            }
+
+            property = self.u8()?;
+        }
+
+        Ok(())
+    }
+
+    fn files_info(&mut self) -> Result<Vec<FileInfo>> {
+        let count = self.count()?;
+        let mut files = (0..count).map(|_| FileInfo::default()).collect::<Vec<_>>();
+        let mut empty_streams = vec![false; count];
+        let mut empty_files = Vec::new();
+        let mut anti = Vec::new();
+
+        loop {
+            let property = self.u8()?;
+
+            if property == property::END {
+                break;
+            }
+
+            let size = self.number()?;
+            let mut data = HeaderReader {
+                data: self.bytes(size.try_into().map_err(|_| corrupt("property too large"))?)?,
+            };
+
+            let num_empty_streams = empty_streams.iter().filter(|&&empty| empty).count();
+
+            match property {
+                property::EMPTY_STREAM => empty_streams = data.bit_vector(count)?,
+                property::EMPTY_FILE => empty_files = data.bit_vector(num_empty_streams)?,
+                property::ANTI => anti = data.bit_vector(num_empty_streams)?,
+
+                property::NAMES => {
+                    data.skip_external()?;
+
+                    let chars = data.data.chunks_exact(2)
+                        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                        .collect::<Vec<_>>();
+                    let mut names = chars.split(|&c| c == 0);
+
+                    for file in &mut files {
+                        file.name = String::from_utf16_lossy(names.next().ok_or_else(|| corrupt("missing file names"))?);
+                    }
+                }
+
+                property::MTIME => {
+                    let defined = data.defined_vector(count)?;
+                    data.skip_external()?;
+
+                    for (file, defined) in files.iter_mut().zip(defined) {
+                        if defined {
+                            file.mtime = Some(data.u64()?);
+                        }
+                    }
+                }
+
+                property::WIN_ATTRIBUTES => {
+                    let defined = data.defined_vector(count)?;
+                    data.skip_external()?;
+
+                    for (file, defined) in files.iter_mut().zip(defined) {
+                        if defined {
+                            file.attributes = Some(data.u32()?);
+                        }
+                    }
+                }
+
+                // Other properties such as creation and access times are not
+                // used.
+                _ => {}
+            }
+        }
+
+        let mut empty_index = 0;
+
+        for (file, empty_stream) in files.iter_mut().zip(empty_streams) {
+            file.has_stream = !empty_stream;
+
+            if empty_stream {
+                file.is_dir = !empty_files.get(empty_index).copied().unwrap_or(false);
+                file.is_anti = anti.get(empty_index).copied().unwrap_or(false);
+                empty_index += 1;
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn skip_archive_properties(&mut self) -> Result<()> {
+        while self.u8()? != property::END {
+            let size = self.count()?;
+            self.bytes(size)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Read the header of the archive, decoding it first if necessary.
+fn read_header(file: &mut File) -> Result<(StreamsInfo, Vec<FileInfo>)> {
+    let mut signature_header = [0; SIGNATURE_HEADER_SIZE as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut signature_header)?;
+
+    if !signature_header.starts_with(SIGNATURE) {
+        return Err(corrupt("invalid signature"));
+    }
+
+    if signature_header[6] != 0 {
+        return Err(Error::new(ErrorKind::InvalidData, format!("unsupported 7z archive version {}", signature_header[6])));
+    }
+
+    let start_header_crc = u32::from_le_bytes(signature_header[8..12].try_into().unwrap());
+
+    if crc32fast::hash(&signature_header[12..]) != start_header_crc {
+        return Err(corrupt("start header CRC mismatch"));
+    }
+
+    let next_header_offset = u64::from_le_bytes(signature_header[12..20].try_into().unwrap());
+    let next_header_size = u64::from_le_bytes(signature_header[20..28].try_into().unwrap());
+    let next_header_crc = u32::from_le_bytes(signature_header[28..32].try_into().unwrap());
+
+    // An empty archive has no header at all.
+    if next_header_size == 0 {
+        return Ok(Default::default());
+    }
+
+    let file_size = file.metadata()?.len();
+
+    let header_end = SIGNATURE_HEADER_SIZE.checked_add(next_header_offset)
+        .and_then(|offset| offset.checked_add(next_header_size));
+
+    if header_end.is_none_or(|end| end > file_size) {
+        return Err(corrupt("header is beyond the end of the file"));
+    }
+
+    let mut header = vec![0; next_header_size as usize];
+    file.seek(SeekFrom::Start(SIGNATURE_HEADER_SIZE + next_header_offset))?;
+    file.read_exact(&mut header)?;
+
+    if crc32fast::hash(&header) != next_header_crc {
+        return Err(corrupt("header CRC mismatch"));
+    }
+
+    loop {
+        let mut reader = HeaderReader {
+            data: &header,
+        };
+
+        match reader.u8()? {
+            property::HEADER => return reader.header(),
+
+            // The header is itself compressed and described by the streams
+            // info that follows.
+            property::ENCODED_HEADER => {
+                let info = reader.streams_info()?;
+                let folder = info.folders.first().ok_or_else(|| corrupt("encoded header has no folders"))?;
+                let mut decoded = Vec::new();
+
+                decode_folder(file, &info, 0)?
+                    .take(folder.unpack_size())
+                    .read_to_end(&mut decoded)?;
+
+                if decoded.len() as u64 != folder.unpack_size() {
+                    return Err(corrupt("truncated encoded header"));
+                }
+
+                if folder.crc.is_some_and(|crc| crc != crc32fast::hash(&decoded)) {
+                    return Err(corrupt("encoded header CRC mismatch"));
+                }
+
+                header = decoded;
+            }
+
+            id => return Err(corrupt(format_args!("unexpected header type {:#x}", id))),
+        }
+    }
+}
+
+/// Create a reader that decodes the given folder.
+fn decode_folder(file: &File, info: &StreamsInfo, index: usize) -> Result<Box<dyn Read>> {
+    let folder = &info.folders[index];
+
+    if folder.coders.iter().any(|coder| coder.id == method::AES) {
+        return Err(encrypted_error());
+    }
+
+    if folder.packed_streams.len() != 1 || folder.coders.iter().any(|coder| coder.num_in_streams != 1 || coder.num_out_streams != 1) {
+        return Err(Error::other("7z coders with multiple streams such as BCJ2 are not supported"));
+    }
+
+    // With only simple coders, stream indexes are the same as coder indexes.
+    // Follow the chain from the coder producing the final output to the one
+    // reading the packed stream, which is also the order liblzma expects.
+    let mut filters = Vec::new();
+    let mut coder = folder.main_out_stream().ok_or_else(|| corrupt("folder has no output"))?;
+
+    for _ in 0..folder.coders.len() {
+        filters.extend(Filter::new(&folder.coders[coder])?);
+
+        match folder.bind_pairs.iter().find(|&&(input, _)| input == coder) {
+            Some(&(_, output)) => coder = output,
+            None => break,
+        }
+    }
+
+    let (offset, size) = info.pack_streams(index)[0];
+    let mut file = file.try_clone()?;
+    file.seek(SeekFrom::Start(offset))?;
+    let packed = BufReader::new(file.take(size));
+
+    if filters.is_empty() {
+        Ok(Box::new(packed))
+    } else {
+        Ok(Box::new(RawDecoder::new(packed, filters)?))
+    }
+}
+
+/// A liblzma filter for a coder.
+enum Filter {
+    Lzma(Box<lzma_options_lzma>),
+    Lzma2(Box<lzma_options_lzma>),
+    X86,
+}
+
+impl Filter {
+    /// Get the filter for a coder, or `None` if it does not transform data.
+    fn new(coder: &Coder) -> Result<Option<Self>> {
+        let properties = &coder.properties;
+
+        match coder.id.as_slice() {
+            method::COPY => Ok(None),
+
+            method::LZMA if properties.len() >= 5 => {
+                let mut options = Self::lzma_options(u32::from_le_bytes(properties[1..5].try_into().unwrap()));
+                let mut byte = u32::from(properties[0]);
+
+                if byte >= 9 * 5 * 5 {
+                    return Err(corrupt("invalid LZMA properties"));
+                }
+
+                options.lc = byte % 9;
+                byte /= 9;
+                options.lp = byte % 5;
+                options.pb = byte / 5;
+
+                Ok(Some(Filter::Lzma(options)))
+            }
+
+            method::LZMA2 if !properties.is_empty() => {
+                let dict_size = match properties[0] {
+                    bits @ 0..=39 => (2 | u32::from(bits) & 1) << (bits / 2 + 11),
+                    40 => u32::MAX,
+                    _ => return Err(corrupt("invalid LZMA2 properties")),
+                };
+
+                Ok(Some(Filter::Lzma2(Self::lzma_options(dict_size))))
+            }
+
+            method::BCJ => Ok(Some(Filter::X86)),
+
+            method::LZMA | method::LZMA2 => Err(corrupt("missing LZMA properties")),
+
+            id => Err(Error::other(format!("unsupported 7z compression method {}", method_name(id)))),
+        }
+    }
+
+    fn lzma_options(dict_size: u32) -> Box<lzma_options_lzma> {
+        // All other options are only used for encoding.
+        let mut options: Box<lzma_options_lzma> = Box::new(unsafe { mem::zeroed() });
+        options.dict_size = dict_size;
+        options
+    }
+
+    fn as_lzma_filter(&mut self) -> lzma_filter {
+        match self {
+            Filter::Lzma(options) => lzma_filter {
+                id: LZMA_FILTER_LZMA1,
+                options: &mut **options as *mut lzma_options_lzma as *mut _,
+            },
+            Filter::Lzma2(options) => lzma_filter {
+                id: LZMA_FILTER_LZMA2,
+                options: &mut **options as *mut lzma_options_lzma as *mut _,
+            },
+            Filter::X86 => lzma_filter {
+                id: LZMA_FILTER_X86,
+                options: ptr::null_mut(),
+            },
+        }
+    }
+}
+
+/// Get a readable name for a coder ID, for error messages.
+fn method_name(id: &[u8]) -> String {
+    match id {
+        [0x03, 0x03, 0x01, 0x1b] => "BCJ2".into(),
+        [0x03, 0x04, 0x01] => "PPMd".into(),
+        [0x04, 0x01, 0x08] => "Deflate".into(),
+        [0x04, 0x01, 0x09] => "Deflate64".into(),
+        [0x04, 0x02, 0x02] => "BZip2".into(),
+        _ => id.iter().map(|byte| format!("{:02x}", byte)).collect(),
+    }
+}
+
+/// Decoder for a raw chain of liblzma filters without any container format.
+struct RawDecoder<R> {
+    reader: R,
+    stream: lzma_stream,
+
+    /// Options referenced by the stream.
+    _filters: Vec<Filter>,
+}
+
+impl<R: BufRead> RawDecoder<R> {
+    fn new(reader: R, mut filters: Vec<Filter>) -> Result<Self> {
+        let mut chain = filters.iter_mut().map(Filter::as_lzma_filter).collect::<Vec<_>>();
+        chain.push(lzma_filter {
+            id: LZMA_VLI_UNKNOWN,
+            options: ptr::null_mut(),
+        });
+
+        let mut decoder = Self {
+            reader,
+            stream: unsafe { mem::zeroed() },
+            _filters: filters,
+        };
+
+        match unsafe { lzma_raw_decoder(&mut decoder.stream, chain.as_ptr()) } {
+            LZMA_OK => Ok(decoder),
+            LZMA_MEM_ERROR => Err(Error::other("out of memory")),
+            _ => Err(Error::other("unsupported 7z coder options")),
+        }
+    }
+}
+
+impl<R: BufRead> Read for RawDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            let input = self.reader.fill_buf()?;
+            let eof = input.is_empty();
+
+            self.stream.next_in = input.as_ptr();
+            self.stream.avail_in = input.len();
+            self.stream.next_out = buf.as_mut_ptr();
+            self.stream.avail_out = buf.len();
+
+            let result = unsafe { lzma_code(&mut self.stream, LZMA_RUN) };
+
+            let consumed = input.len() - self.stream.avail_in;
+            let produced = buf.len() - self.stream.avail_out;
+            self.reader.consume(consumed);
+
+            match result {
+                LZMA_OK | LZMA_STREAM_END | LZMA_BUF_ERROR => {}
+                LZMA_DATA_ERROR => return Err(corrupt("compressed data is invalid")),
+                LZMA_MEM_ERROR => return Err(Error::other("out of memory")),
+                result => return Err(Error::other(format!("liblzma error {}", result))),
+            }
+
+            if produced > 0 || buf.is_empty() || result == LZMA_STREAM_END || (eof && consumed == 0) {
+                return Ok(produced);
+            }
+        }
+    }
+}
+
+impl<R> Drop for RawDecoder<R> {
+    fn drop(&mut self) {
+        unsafe {
+            lzma_end(&mut self.stream);
+        }
+    }
+}
+
+struct SevenZReader {
+    file: File,
+    info: StreamsInfo,
+    files: Vec<FileInfo>,
+    index: usize,
+
+    /// Decoder for the folder currently being read, along with its index and
+    /// the current position within the decoded folder.
+    folder: Option<(usize, Box<dyn Read>, u64)>,
+}
+
+impl SevenZReader {
+    fn new(mut file: File) -> Result<Self> {
+        let (info, mut files) = read_header(&mut file)?;
+
+        // Anti-items mark files to be deleted when updating an archive, and
+        // are not really entries.
+        files.retain(|file| !file.is_anti);
+
+        Ok(Self {
+            file,
+            info,
+            files,
+            index: 0,
+            folder: None,
+        })
+    }
+
+    /// Read data of a file stored in the given folder starting at the given
+    /// offset.
+    fn read_folder(&mut self, index: usize, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        // Decoding can only move forward, so start over if the folder has
+        // already been read past the requested offset.
+        if !matches!(self.folder, Some((folder, _, position)) if folder == index && position <= offset) {
+            self.folder = Some((index, decode_folder(&self.file, &self.info, index)?, 0));
+        }
+
+        let (_, decoder, position) = self.folder.as_mut().unwrap();
+
+        if *position < offset {
+            let skipped = io::copy(&mut decoder.by_ref().take(offset - *position), &mut io::sink())?;
+            *position += skipped;
+
+            if *position < offset {
+                return Err(corrupt("unexpected end of compressed data"));
+            }
+        }
+
+        let result = decoder.read(buf);
+
+        match result {
+            Ok(len) => *position += len as u64,
+            Err(_) => self.folder = None,
+        }
+
+        result
+    }
+}
+
+impl ArchiveReader for SevenZReader {
+    fn len(&mut self) -> Option<u64> {
+        Some(self.files.len() as u64)
+    }
+
+    fn entry(&mut self) -> Result<Option<Box<dyn Entry + '_>>> {
+        if self.index >= self.files.len() {
+            return Ok(None);
+        }
+
+        let index = self.index;
+        self.index += 1;
+
+        let file = &self.files[index];
+        let attributes = file.attributes.unwrap_or(0);
+        let unix_mode = if attributes & FILE_ATTRIBUTE_UNIX_EXTENSION != 0 {
+            Some(attributes >> 16)
+        } else {
+            None
+        };
+
+        let entry_type = if file.is_dir || attributes & FILE_ATTRIBUTE_DIRECTORY != 0 {
+            EntryType::Directory
+        } else if unix_mode.is_some_and(|mode| mode & S_IFMT == S_IFLNK) {
+            EntryType::SymbolicLink
+        } else {
+            EntryType::File
+        };
+
+        // The compressed size of a file is only known if it is the only file
+        // in its folder.
+        let compressed_size = file.stream
+            .filter(|&(folder, _, _)| self.info.substreams[folder].len() == 1)
+            .map(|(folder, _, _)| self.info.pack_streams(folder).iter().map(|&(_, size)| size).sum());
+
+        let metadata = Metadata::builder()
+            .entry_type(entry_type)
+            .size(file.stream.map_or(0, |(_, _, size)| size))
+            .compressed_size(compressed_size)
+            .modified(file.mtime.and_then(super::from_filetime))
+            .read_only(attributes & FILE_ATTRIBUTE_READONLY != 0)
+            .hidden(attributes & FILE_ATTRIBUTE_HIDDEN != 0)
+            .unix_mode(unix_mode.map(|mode| mode & 0o7777))
+            .build();
+
+        Ok(Some(Box::new(SevenZEntry {
+            path: PathBuf::from(&file.name),
+            metadata,
+            stream: file.stream,
+            crc: file.crc,
+            hasher: crc32fast::Hasher::new(),
+            read: 0,
+            reader: self,
+        })))
+    }
+}
+
+struct SevenZEntry<'a> {
+    reader: &'a mut SevenZReader,
+    path: PathBuf,
+    metadata: Metadata,
+    stream: Option<(usize, u64, u64)>,
+    crc: Option<u32>,
+    hasher: crc32fast::Hasher,
+
+    /// Number of bytes read so far.
+    read: u64,
+}
+
+impl Entry for SevenZEntry<'_> {
+    fn path(&self) -> Cow<'_, Path> {
+        self.path.as_path().into()
+    }
+
+    fn metadata(&self) -> Metadata {
+        self.metadata.clone()
+    }
+
+    fn read_link(&mut self) -> Result<Option<Cow<'_, Path>>> {
+        if !matches!(self.metadata.entry_type, EntryType::SymbolicLink) {
+            return Ok(None);
+        }
+
+        // Symbolic links are stored as a file containing the target path.
+        let mut target = String::new();
+        self.read_to_string(&mut target)?;
+
+        Ok(Some(PathBuf::from(target).into()))
+    }
+}
+
+impl Read for SevenZEntry<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let (folder, offset, size) = match self.stream {
+            Some(stream) => stream,
+            None => return Ok(0),
+        };
+
+        let remaining = size - self.read;
+
+        if remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+        let len = self.reader.read_folder(folder, offset + self.read, &mut buf[..len])?;
+
+        if len == 0 {
+            return Err(corrupt(format_args!("unexpected end of data for {}", self.path.display())));
+        }
+
+        self.hasher.update(&buf[..len]);
+        self.read += len as u64;
+
+        if self.read == size {
+            if let Some(crc) = self.crc {
+                if self.hasher.clone().finalize() != crc {
+                    return Err(corrupt(format_args!("CRC mismatch for {}", self.path.display())));
+                }
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::ArchiveFormat;
+
+    /// Modification time of every entry in the fixtures, which were created by
+    /// `bsdtar` with each of the compression methods it supports.
+    const MTIME: i64 = 1_622_548_800;
+
+    fn lines() -> String {
+        (0..50)
+            .map(|i| format!("line {}: the quick brown fox jumps over {} lazy dogs\n", i, i * i % 97))
+            .collect()
+    }
+
+    /// Path, type, mode, and contents or link target of an entry.
+    type Summary = (String, EntryType, Option<u32>, String);
+
+    /// Read every entry of an archive, along with its contents or link target.
+    fn read_all(bytes: &[u8]) -> Result<Vec<Summary>> {
+        let mut reader = SevenZ.open(Input::from_reader(bytes)?)?;
+        let mut entries = Vec::new();
+
+        while let Some(mut entry) = reader.entry()? {
+            let metadata = entry.metadata();
+            assert_eq!(metadata.modified.map(|dt| dt.timestamp()), Some(MTIME));
+
+            let contents = match metadata.entry_type {
+                EntryType::SymbolicLink => entry.read_link()?.unwrap().to_string_lossy().into_owned(),
+                _ => {
+                    let mut contents = String::new();
+                    entry.read_to_string(&mut contents)?;
+                    contents
+                }
+            };
+
+            entries.push((entry.path().to_string_lossy().into_owned(), metadata.entry_type, metadata.unix_mode, contents));
+        }
+
+        Ok(entries)
+    }
+
+    #[test]
+    fn read_fixtures() {
+        let expected = vec![
+            ("hello.txt".to_owned(), EntryType::File, Some(0o640), "hello from a fixture\n".to_owned()),
+            ("dir/lines.txt".to_owned(), EntryType::File, Some(0o644), lines()),
+            ("link".to_owned(), EntryType::SymbolicLink, Some(0o777), "hello.txt".to_owned()),
+            ("dir".to_owned(), EntryType::Directory, Some(0o755), String::new()),
+        ];
+
+        for bytes in &[
+            &include_bytes!("../../../tests/fixtures/sample-copy.7z")[..],
+            &include_bytes!("../../../tests/fixtures/sample-lzma.7z")[..],
+            &include_bytes!("../../../tests/fixtures/sample-lzma2.7z")[..],
+        ] {
+            assert_eq!(read_all(bytes).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn unsupported_method_is_an_error() {
+        let error = read_all(include_bytes!("../../../tests/fixtures/sample-deflate.7z")).unwrap_err();
+
+        assert!(error.to_string().contains("Deflate"), "unexpected error: {}", error);
+    }
+
+    /// Build an archive from packed streams and a raw header, with valid
+    /// checksums so that the header is parsed.
+    fn build(packed: &[u8], header: &[u8]) -> Vec<u8> {
+        let mut start_header = Vec::new();
+        start_header.extend_from_slice(&(packed.len() as u64).to_le_bytes());
+        start_header.extend_from_slice(&(header.len() as u64).to_le_bytes());
+        start_header.extend_from_slice(&crc32fast::hash(header).to_le_bytes());
+
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0, 4]);
+        bytes.extend_from_slice(&crc32fast::hash(&start_header).to_le_bytes());
+        bytes.extend_from_slice(&start_header);
+        bytes.extend_from_slice(packed);
+        bytes.extend_from_slice(header);
+        bytes
+    }
+
+    #[test]
+    fn invalid_streams_are_an_error() {
+        use property::*;
+
+        for (header, message) in &[
+            // A folder without any packed streams to read from.
+            (
+                vec![ENCODED_HEADER, UNPACK_INFO, FOLDER, 1, 0, 1, 0x01, 0x00, CODERS_UNPACK_SIZE, 5, END, END],
+                "number of packed streams",
+            ),
+            // Two coders bound to an output stream that does not exist.
+            (
+                vec![ENCODED_HEADER, PACK_INFO, 0, 1, SIZE, 5, END, UNPACK_INFO, FOLDER, 1, 0, 2, 0x01, 0x00, 0x01, 0x00, 0, 7, CODERS_UNPACK_SIZE, 5, 5, END, END],
+                "bind pair",
+            ),
+            // Packed streams extending past the largest possible offset.
+            (
+                vec![ENCODED_HEADER, PACK_INFO, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 1, SIZE, 5, END, UNPACK_INFO, FOLDER, 1, 0, 1, 0x01, 0x00, CODERS_UNPACK_SIZE, 5, END, END],
+                "too large",
+            ),
+        ] {
+            let error = read_all(&build(b"hello", header)).unwrap_err();
+
+            assert!(error.to_string().contains(message), "unexpected error: {}", error);
+        }
+    }
+
+    #[test]
+    fn truncated_archive_is_an_error() {
+        let bytes = include_bytes!("../../../tests/fixtures/sample-lzma2.7z");
+
+        for len in 0..bytes.len() {
+            assert!(read_all(&bytes[..len]).is_err(), "truncated to {} bytes", len);
+        }
+    }
+}
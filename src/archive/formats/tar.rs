@@ -6,18 +6,26 @@
 //! [TAR archive]: https://en.wikipedia.org/wiki/Tar_%28computing%29
 
 use crate::{
-    archive::{ArchiveReader, Entry, EntryType, Metadata},
+    archive::{ArchiveReader, ArchiveWriter, Entry, EntryType, Metadata},
+    compress::formats::CompressionOptions,
     input::Input,
+    output::Output,
 };
 use chrono::prelude::*;
 use owning_ref::OwningHandle;
 use std::{
     borrow::Cow,
     fmt,
-    io::{Read, Result},
-    path::{Path, PathBuf},
+    io::{self, Read, Result},
+    path::Path,
 };
 
+/// Default permissions for files that don't specify a mode.
+const DEFAULT_FILE_MODE: u32 = 0o644;
+
+/// Default permissions for directories that don't specify a mode.
+const DEFAULT_DIRECTORY_MODE: u32 = 0o755;
+
 /// Format provider for tar.
 pub struct Tar;
 
@@ -45,6 +53,15 @@ impl super::ArchiveFormat for Tar {
     fn open<'r>(&self, input: Input<'r>) -> Result<Box<dyn ArchiveReader + 'r>> {
         Ok(Box::new(TarReader::new(input)?))
     }
+
+    fn create(
+        &self,
+        output: Output,
+        _options: &super::ArchiveOptions,
+        _compression_options: &CompressionOptions,
+    ) -> Result<Box<dyn ArchiveWriter>> {
+        Ok(Box::new(TarWriter::new(output)))
+    }
 }
 
 pub struct TarReader<'r, R: Read + 'r> {
@@ -86,14 +103,24 @@ impl<'r, R: Read + 'r> Entry for tar::Entry<'r, R> {
                 tar::EntryType::Regular | tar::EntryType::Continuous => EntryType::File,
                 tar::EntryType::Directory => EntryType::Directory,
                 tar::EntryType::Symlink => EntryType::SymbolicLink,
+                tar::EntryType::Link => EntryType::HardLink,
+                tar::EntryType::Char => EntryType::CharacterDevice,
+                tar::EntryType::Block => EntryType::BlockDevice,
+                tar::EntryType::Fifo => EntryType::Fifo,
                 _ => EntryType::Unsupported,
             })
             .size(self.header().size().unwrap_or(0))
             .modified(self.header()
                 .mtime()
                 .ok()
-                .map(|ts| Local.timestamp(ts as i64, 0)))
+                .and_then(|ts| Local.timestamp_opt(ts as i64, 0).single()))
             .unix_mode(self.header().mode().ok())
+            .device(if self.header().entry_type().is_character_special() || self.header().entry_type().is_block_special() {
+                self.header().device_major().ok().flatten()
+                    .zip(self.header().device_minor().ok().flatten())
+            } else {
+                None
+            })
             .build()
     }
 
@@ -101,3 +128,210 @@ impl<'r, R: Read + 'r> Entry for tar::Entry<'r, R> {
         Ok(self.link_name_bytes().map(crate::paths::path_from_unix_path_bytes))
     }
 }
+
+/// Writer for tar archives.
+///
+/// Entries are written using GNU-style headers, which allows paths and link
+/// names longer than the 100 bytes supported by the original format to be
+/// stored using GNU long name extension entries.
+pub struct TarWriter {
+    builder: tar::Builder<Output>,
+}
+
+impl TarWriter {
+    fn new(output: Output) -> Self {
+        Self {
+            builder: tar::Builder::new(output),
+        }
+    }
+
+    fn append(&mut self, path: &Path, mut header: tar::Header, data: impl Read) -> Result<()> {
+        self.builder.append_data(&mut header, path, data)
+    }
+}
+
+impl ArchiveWriter for TarWriter {
+    fn add_directory(&mut self, path: &Path, metadata: Metadata) -> Result<()> {
+        let header = create_header(&metadata, tar::EntryType::Directory, 0, DEFAULT_DIRECTORY_MODE);
+
+        self.append(path, header, io::empty())
+    }
+
+    fn add_file(&mut self, path: &Path, metadata: Metadata, file: &mut dyn Read) -> Result<()> {
+        let entry_type = match metadata.entry_type {
+            EntryType::CharacterDevice => tar::EntryType::Char,
+            EntryType::BlockDevice => tar::EntryType::Block,
+            EntryType::Fifo => tar::EntryType::Fifo,
+            _ => {
+                let header = create_header(&metadata, tar::EntryType::Regular, metadata.size, DEFAULT_FILE_MODE);
+
+                return self.append(path, header, file);
+            }
+        };
+
+        // Special files are stored without any data.
+        let mut header = create_header(&metadata, entry_type, 0, DEFAULT_FILE_MODE);
+
+        if let Some((major, minor)) = metadata.device {
+            header.set_device_major(major)?;
+            header.set_device_minor(minor)?;
+        }
+
+        self.append(path, header, io::empty())
+    }
+
+    fn add_symlink(&mut self, path: &Path, metadata: Metadata, target: &Path) -> Result<()> {
+        let mut header = create_header(&metadata, tar::EntryType::Symlink, 0, 0o777);
+
+        self.builder.append_link(&mut header, path, target)
+    }
+
+    fn add_hard_link(&mut self, path: &Path, metadata: Metadata, target: &Path) -> Result<()> {
+        let mut header = create_header(&metadata, tar::EntryType::Link, 0, DEFAULT_FILE_MODE);
+
+        self.builder.append_link(&mut header, path, target)
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.builder.into_inner()?.finish()
+    }
+}
+
+/// Create a tar header for an entry with the given metadata.
+fn create_header(metadata: &Metadata, entry_type: tar::EntryType, size: u64, default_mode: u32) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+
+    header.set_entry_type(entry_type);
+    header.set_size(size);
+    header.set_mode(metadata.unix_mode.map(|mode| mode & 0o7777).unwrap_or(default_mode));
+
+    // Timestamps before the UNIX epoch can't be represented, so clamp them.
+    header.set_mtime(metadata.modified
+        .map(|dt| dt.timestamp().max(0) as u64)
+        .unwrap_or(0));
+
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{formats::ArchiveOptions, ArchiveFormat};
+    use std::path::PathBuf;
+    use structopt::StructOpt;
+
+    #[test]
+    fn special_files_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("special.tar");
+        let options = ArchiveOptions::from_iter(&["test"]);
+        let compression_options = CompressionOptions::from_iter(&["test"]);
+
+        let mut writer = Tar.create(Output::create(&path).unwrap(), &options, &compression_options).unwrap();
+
+        for (name, entry_type, device) in &[
+            ("null", EntryType::CharacterDevice, Some((1, 3))),
+            ("loop0", EntryType::BlockDevice, Some((7, 0))),
+            ("pipe", EntryType::Fifo, None),
+        ] {
+            let metadata = Metadata::builder()
+                .entry_type(*entry_type)
+                .unix_mode(Some(0o600))
+                .device(*device)
+                .build();
+
+            writer.add_file(Path::new(name), metadata, &mut io::empty()).unwrap();
+        }
+
+        writer.finish().unwrap();
+
+        let mut reader = Tar.open(Input::open(&path).unwrap()).unwrap();
+        let mut entries = Vec::new();
+
+        while let Some(entry) = reader.entry().unwrap() {
+            let metadata = entry.metadata();
+            entries.push((entry.path().into_owned(), metadata.entry_type, metadata.device, metadata.unix_mode));
+        }
+
+        assert_eq!(entries, vec![
+            (PathBuf::from("null"), EntryType::CharacterDevice, Some((1, 3)), Some(0o600)),
+            (PathBuf::from("loop0"), EntryType::BlockDevice, Some((7, 0)), Some(0o600)),
+            (PathBuf::from("pipe"), EntryType::Fifo, None, Some(0o600)),
+        ]);
+    }
+
+    #[test]
+    fn hard_links_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("links.tar");
+        let options = ArchiveOptions::from_iter(&["test"]);
+        let compression_options = CompressionOptions::from_iter(&["test"]);
+
+        let mut writer = Tar.create(Output::create(&path).unwrap(), &options, &compression_options).unwrap();
+        let metadata = Metadata::builder().entry_type(EntryType::File).size(5).links(Some(2)).build();
+
+        writer.add_file(Path::new("a"), metadata.clone(), &mut &b"hello"[..]).unwrap();
+        writer.add_hard_link(Path::new("b"), metadata, Path::new("a")).unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = Tar.open(Input::open(&path).unwrap()).unwrap();
+        let mut entry = reader.entry().unwrap().unwrap();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"hello");
+        drop(entry);
+
+        let mut entry = reader.entry().unwrap().unwrap();
+        assert_eq!(entry.path(), Path::new("b"));
+        assert_eq!(entry.metadata().entry_type, EntryType::HardLink);
+        assert_eq!(entry.metadata().size, 0);
+        assert_eq!(entry.read_link().unwrap().as_deref(), Some(Path::new("a")));
+        drop(entry);
+
+        assert!(reader.entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("entries.tar");
+        let options = ArchiveOptions::from_iter(&["test"]);
+        let compression_options = CompressionOptions::from_iter(&["test"]);
+        let modified = Local.timestamp_opt(1622548800, 0).unwrap();
+        let long_dir = PathBuf::from("d".repeat(120));
+        let long_file = long_dir.join("f".repeat(120));
+
+        let mut writer = Tar.create(Output::create(&path).unwrap(), &options, &compression_options).unwrap();
+        let metadata = |entry_type, mode, size| Metadata::builder()
+            .entry_type(entry_type)
+            .size(size)
+            .modified(Some(modified))
+            .unix_mode(mode)
+            .build();
+
+        writer.add_directory(&long_dir, metadata(EntryType::Directory, Some(0o750), 0)).unwrap();
+        writer.add_file(&long_file, metadata(EntryType::File, Some(0o100640), 5), &mut &b"hello"[..]).unwrap();
+        writer.add_file(Path::new("default"), metadata(EntryType::File, None, 0), &mut io::empty()).unwrap();
+        writer.add_symlink(Path::new("link"), metadata(EntryType::SymbolicLink, None, 0), &long_file).unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = Tar.open(Input::open(&path).unwrap()).unwrap();
+        let mut entries = Vec::new();
+
+        while let Some(mut entry) = reader.entry().unwrap() {
+            let metadata = entry.metadata();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            let target = entry.read_link().unwrap().map(Cow::into_owned);
+
+            entries.push((entry.path().into_owned(), metadata.entry_type, metadata.unix_mode, metadata.modified, data, target));
+        }
+
+        assert_eq!(entries, vec![
+            (long_dir, EntryType::Directory, Some(0o750), Some(modified), vec![], None),
+            (long_file.clone(), EntryType::File, Some(0o640), Some(modified), b"hello".to_vec(), None),
+            (PathBuf::from("default"), EntryType::File, Some(0o644), Some(modified), vec![], None),
+            (PathBuf::from("link"), EntryType::SymbolicLink, Some(0o777), Some(modified), vec![], Some(long_file)),
+        ]);
+    }
+}
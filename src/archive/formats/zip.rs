@@ -4,23 +4,29 @@
 
 use crate::{
     archive::{ArchiveReader, ArchiveWriter, Entry, EntryType, Metadata},
+    compress::formats::CompressionOptions,
     input::Input,
     output::Output,
 };
 use chrono::prelude::*;
-use chrono::naive::{NaiveDate, NaiveDateTime, NaiveTime};
+use chrono::naive::NaiveDate;
+use glob::Pattern;
 use std::{
+    borrow::Cow,
     fmt,
-    io::{copy, Read, Result, Seek, Write},
+    io::{copy, Error, ErrorKind, Read, Result, Seek},
     path::Path,
+    str::FromStr,
 };
+use tempfile::SpooledTempFile;
 use zip::{
     read::{ZipArchive, ZipFile},
     result::ZipError,
-    write::ZipWriter,
 };
 
-const DEFAULT_COMPRESSION_METHOD: zip::CompressionMethod = zip::CompressionMethod::Deflated;
+/// How much of an entry being compressed to hold in memory before spilling it
+/// to a temporary file.
+const SCRATCH_MEMORY_LIMIT: usize = 8 * 1024 * 1024;
 
 pub struct Zip;
 
@@ -49,8 +55,20 @@ impl super::ArchiveFormat for Zip {
         Ok(Box::new(ZipReader::open(input.into_file()?)?))
     }
 
-    fn create<'w>(&self, output: &'w mut Output) -> Result<Box<dyn ArchiveWriter + 'w>> {
-        Ok(Box::new(ZipWriter::new(output)))
+    fn create(
+        &self,
+        output: Output,
+        options: &super::ArchiveOptions,
+        compression_options: &CompressionOptions,
+    ) -> Result<Box<dyn ArchiveWriter>> {
+        let (min, max, default) = options.zip_method.level_range();
+
+        Ok(Box::new(ZipWriter {
+            writer: zip::ZipWriter::new(output),
+            method: options.zip_method,
+            compression_level: compression_options.level(min, max, default),
+            store_patterns: options.zip_store.clone(),
+        }))
     }
 }
 
@@ -90,8 +108,8 @@ impl<R: Read + Seek> ArchiveReader for ZipReader<R> {
 struct ZipEntry<'a>(ZipFile<'a>);
 
 impl<'a> Entry for ZipEntry<'a> {
-    fn path(&self) -> std::borrow::Cow<'_, Path> {
-        self.0.sanitized_name().into()
+    fn path(&self) -> Cow<'_, Path> {
+        Path::new(self.0.name()).into()
     }
 
     fn metadata(&self) -> Metadata {
@@ -100,26 +118,43 @@ impl<'a> Entry for ZipEntry<'a> {
         Metadata::builder()
             .entry_type(if self.0.is_dir() {
                 EntryType::Directory
+            } else if self.is_symlink() {
+                EntryType::SymbolicLink
             } else {
                 EntryType::File
             })
             .size(self.0.size())
             .compressed_size(Some(self.0.compressed_size()))
-            .modified(Local.from_local_datetime(&NaiveDateTime::new(
-                NaiveDate::from_ymd(
-                    dt.year().into(),
-                    dt.month().into(),
-                    dt.day().into(),
-                ),
-                NaiveTime::from_hms(
-                    dt.hour().into(),
-                    dt.minute().into(),
-                    dt.second().into(),
-                ),
-            )).single())
+            .modified(NaiveDate::from_ymd_opt(
+                dt.year().into(),
+                dt.month().into(),
+                dt.day().into(),
+            ).and_then(|date| date.and_hms_opt(
+                dt.hour().into(),
+                dt.minute().into(),
+                dt.second().into(),
+            )).and_then(|datetime| Local.from_local_datetime(&datetime).single()))
             .unix_mode(self.0.unix_mode())
             .build()
     }
+
+    fn read_link(&mut self) -> Result<Option<Cow<'_, Path>>> {
+        if !self.is_symlink() {
+            return Ok(None);
+        }
+
+        // Symbolic links are stored as a file containing the target path.
+        let mut target = Vec::new();
+        self.0.read_to_end(&mut target)?;
+
+        Ok(Some(crate::paths::path_from_unix_path_bytes(Cow::Owned(target))))
+    }
+}
+
+impl<'a> ZipEntry<'a> {
+    fn is_symlink(&self) -> bool {
+        self.0.unix_mode().map(|mode| mode & 0o170000 == 0o120000).unwrap_or(false)
+    }
 }
 
 impl<'a> Read for ZipEntry<'a> {
@@ -128,29 +163,143 @@ impl<'a> Read for ZipEntry<'a> {
     }
 }
 
-impl<W: Write + Seek> ArchiveWriter for ZipWriter<W> {
+/// Compression methods that can be used for entries when creating ZIP archives.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ZipMethod {
+    Store,
+    #[default]
+    Deflate,
+    Bzip2,
+    Zstd,
+}
+
+impl FromStr for ZipMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "store" | "stored" => Ok(ZipMethod::Store),
+            "deflate" => Ok(ZipMethod::Deflate),
+            "bzip2" => Ok(ZipMethod::Bzip2),
+            "zstd" => Ok(ZipMethod::Zstd),
+            _ => Err(format!("unknown ZIP compression method: {}", s)),
+        }
+    }
+}
+
+impl ZipMethod {
+    fn compression_method(self) -> zip::CompressionMethod {
+        match self {
+            ZipMethod::Store => zip::CompressionMethod::Stored,
+            ZipMethod::Deflate => zip::CompressionMethod::Deflated,
+            ZipMethod::Bzip2 => zip::CompressionMethod::Bzip2,
+            ZipMethod::Zstd => zip::CompressionMethod::Zstd,
+        }
+    }
+
+    /// Get the minimum, maximum, and default compression levels of this method.
+    fn level_range(self) -> (u32, u32, u32) {
+        match self {
+            ZipMethod::Store => (0, 0, 0),
+            ZipMethod::Deflate => (1, 9, 6),
+            ZipMethod::Bzip2 => (1, 9, 6),
+            ZipMethod::Zstd => (1, 19, 3),
+        }
+    }
+}
+
+pub struct ZipWriter {
+    writer: zip::ZipWriter<Output>,
+    method: ZipMethod,
+    compression_level: u32,
+
+    /// Entries matching any of these patterns are stored without compression.
+    store_patterns: Vec<Pattern>,
+}
+
+impl ZipWriter {
+    fn method_for(&self, path: &Path) -> ZipMethod {
+        if self.store_patterns.iter().any(|pattern| crate::paths::matches_pattern(pattern, path)) {
+            ZipMethod::Store
+        } else {
+            self.method
+        }
+    }
+}
+
+impl ArchiveWriter for ZipWriter {
     fn add_directory(&mut self, path: &Path, metadata: Metadata) -> Result<()> {
-        self.add_directory_from_path(path, create_file_options(metadata))?;
+        let options = create_file_options(metadata);
+        self.writer.add_directory(path.to_string_lossy(), options)?;
 
         Ok(())
     }
 
     fn add_file(&mut self, path: &Path, metadata: Metadata, file: &mut dyn Read) -> Result<()> {
+        if metadata.entry_type != EntryType::File {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not a regular file, which ZIP archives cannot contain", path.display()),
+            ));
+        }
+
         // TODO: Handle encoding better.
-        self.start_file(path.to_string_lossy(), create_file_options(metadata))?;
-        copy(file, self)?;
+        let name = path.to_string_lossy();
+        let options = create_file_options(metadata);
+        let method = self.method_for(path);
+
+        if method == ZipMethod::Store {
+            self.writer.start_file(name, options.compression_method(zip::CompressionMethod::Stored))?;
+            copy(file, &mut self.writer)?;
+
+            return Ok(());
+        }
+
+        // Compress the entry into a scratch archive first, so that it can be
+        // stored without compression instead if compressing doesn't make it
+        // any smaller. The entry is compressed again rather than copied over
+        // raw, since raw copies lose the file type bits of the mode.
+        let mut input = SpooledTempFile::new(SCRATCH_MEMORY_LIMIT);
+        copy(file, &mut input)?;
+        input.rewind()?;
+
+        let compressed_options = options
+            .compression_method(method.compression_method())
+            .compression_level(Some(self.compression_level as i32));
+        let mut scratch = zip::ZipWriter::new(SpooledTempFile::new(SCRATCH_MEMORY_LIMIT));
+        scratch.start_file(name.clone(), compressed_options)?;
+        copy(&mut input, &mut scratch)?;
+        input.rewind()?;
+
+        let mut scratch = ZipArchive::new(scratch.finish()?)?;
+        let entry = scratch.by_index(0)?;
+
+        if entry.compressed_size() < entry.size() {
+            self.writer.start_file(name, compressed_options)?;
+        } else {
+            log::debug!("storing {} without compression as it did not shrink", name);
+
+            self.writer.start_file(name, options.compression_method(zip::CompressionMethod::Stored))?;
+        }
+
+        copy(&mut input, &mut self.writer)?;
 
         Ok(())
     }
 
-    fn finish(&mut self) -> Result<()> {
-        self.finish().map(|_| ()).map_err(convert_err)
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.writer.finish().map_err(convert_err)?.finish()
     }
 }
 
 fn create_file_options(metadata: Metadata) -> zip::write::FileOptions {
-    let mut options = zip::write::FileOptions::default()
-    .compression_method(DEFAULT_COMPRESSION_METHOD);
+    let mut options = zip::write::FileOptions::default();
+
+    // Only the permission bits are taken from the entry; the writer adds the
+    // file type bits matching the kind of entry being written.
+    if let Some(mode) = metadata.unix_mode {
+        options = options.unix_permissions(mode);
+    }
 
     if let Some(datetime) = metadata.modified {
         if let Ok(datetime) = zip::DateTime::from_date_and_time(
@@ -175,3 +324,75 @@ fn convert_err(error: ZipError) -> std::io::Error {
         e => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{formats::ArchiveOptions, ArchiveFormat};
+    use std::path::PathBuf;
+    use structopt::StructOpt;
+
+    #[test]
+    fn unix_modes_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("modes.zip");
+        let options = ArchiveOptions::from_iter(&["test", "--store", "*.bin"]);
+        let compression_options = CompressionOptions::from_iter(&["test"]);
+
+        let mut writer = Zip.create(Output::create(&path).unwrap(), &options, &compression_options).unwrap();
+        let metadata = |entry_type, mode| Metadata::builder().entry_type(entry_type).unix_mode(Some(mode)).build();
+
+        writer.add_directory(Path::new("dir"), metadata(EntryType::Directory, 0o40750)).unwrap();
+        // Compressed, stored by pattern, and stored because it did not shrink.
+        writer.add_file(Path::new("dir/text.txt"), metadata(EntryType::File, 0o100640), &mut &[b'a'; 4096][..]).unwrap();
+        writer.add_file(Path::new("data.bin"), metadata(EntryType::File, 0o100600), &mut &b"stored"[..]).unwrap();
+        writer.add_file(Path::new("run.sh"), metadata(EntryType::File, 0o755), &mut &b"x"[..]).unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = Zip.open(Input::open(&path).unwrap()).unwrap();
+        let mut entries = Vec::new();
+
+        while let Some(entry) = reader.entry().unwrap() {
+            let metadata = entry.metadata();
+            entries.push((entry.path().into_owned(), metadata.entry_type, metadata.unix_mode));
+        }
+
+        assert_eq!(entries, vec![
+            (PathBuf::from("dir"), EntryType::Directory, Some(0o40750)),
+            (PathBuf::from("dir/text.txt"), EntryType::File, Some(0o100640)),
+            (PathBuf::from("data.bin"), EntryType::File, Some(0o100600)),
+            (PathBuf::from("run.sh"), EntryType::File, Some(0o100755)),
+        ]);
+    }
+
+    #[test]
+    fn methods_and_store_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("methods.zip");
+        let options = ArchiveOptions::from_iter(&["test", "--zip-method", "bzip2", "--store", "*.png", "--store", "raw/*"]);
+        let compression_options = CompressionOptions::from_iter(&["test", "--best"]);
+
+        assert_eq!(options.zip_method, ZipMethod::Bzip2);
+        assert!(ArchiveOptions::from_iter_safe(&["test", "--zip-method", "lzma"]).is_err());
+
+        let mut writer = Zip.create(Output::create(&path).unwrap(), &options, &compression_options).unwrap();
+        let metadata = Metadata::builder().entry_type(EntryType::File).build();
+
+        for name in &["text.txt", "images/photo.png", "raw/data"] {
+            writer.add_file(Path::new(name), metadata.clone(), &mut &[b'a'; 4096][..]).unwrap();
+        }
+
+        writer.finish().unwrap();
+
+        let mut archive = ZipArchive::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let methods = (0..archive.len())
+            .map(|i| archive.by_index(i).map(|file| file.compression()).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(methods, vec![
+            zip::CompressionMethod::Bzip2,
+            zip::CompressionMethod::Stored,
+            zip::CompressionMethod::Stored,
+        ]);
+    }
+}
@@ -1,7 +1,7 @@
 //! Archive format APIs for reading and writing.
 
 use crate::{
-    compress,
+    compress::{self, formats::{CompressionFormat, CompressionOptions}},
     input::Input,
     output::Output
 };
@@ -15,7 +15,7 @@ pub mod formats;
 mod read;
 mod write;
 
-pub use self::{read::*, write::*};
+pub use self::{formats::ArchiveFormat, read::*, write::*};
 
 /// Possible entry types in an archive.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -33,10 +33,59 @@ pub enum EntryType {
     /// A symbolic link to another file by name.
     SymbolicLink,
 
+    /// A hard link to another entry in the same archive.
+    ///
+    /// The link target is the path of the other entry relative to the root of
+    /// the archive.
+    HardLink,
+
+    /// A character special device file.
+    CharacterDevice,
+
+    /// A block special device file.
+    BlockDevice,
+
+    /// A named pipe (FIFO).
+    Fifo,
+
     /// Some other type of file not supported by Naru.
     Unsupported,
 }
 
+impl EntryType {
+    /// A unique, stable identifier for this entry type.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::File => "file",
+            Self::Directory => "directory",
+            Self::SymbolicLink => "symlink",
+            Self::HardLink => "hardlink",
+            Self::CharacterDevice => "char_device",
+            Self::BlockDevice => "block_device",
+            Self::Fifo => "fifo",
+            Self::Unsupported => "unsupported",
+        }
+    }
+
+    /// Get the entry type corresponding to the file type bits of a UNIX mode.
+    pub fn from_unix_mode(mode: u32) -> Self {
+        match mode & 0o170000 {
+            0o100000 => Self::File,
+            0o040000 => Self::Directory,
+            0o120000 => Self::SymbolicLink,
+            0o020000 => Self::CharacterDevice,
+            0o060000 => Self::BlockDevice,
+            0o010000 => Self::Fifo,
+            _ => Self::Unsupported,
+        }
+    }
+
+    /// Check if this entry type is a device file.
+    pub fn is_device(&self) -> bool {
+        matches!(self, Self::CharacterDevice | Self::BlockDevice)
+    }
+}
+
 impl Default for EntryType {
     fn default() -> Self {
         Self::File
@@ -45,8 +94,23 @@ impl Default for EntryType {
 
 impl From<fs::FileType> for EntryType {
     fn from(file_type: fs::FileType) -> Self {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+
+            if file_type.is_char_device() {
+                return Self::CharacterDevice;
+            } else if file_type.is_block_device() {
+                return Self::BlockDevice;
+            } else if file_type.is_fifo() {
+                return Self::Fifo;
+            }
+        }
+
         if file_type.is_dir() {
             Self::Directory
+        } else if file_type.is_symlink() {
+            Self::SymbolicLink
         } else {
             Self::File
         }
@@ -77,6 +141,14 @@ pub struct Metadata {
     #[builder(default)]
     pub modified: Option<DateTime<Local>>,
 
+    /// Timestamp of when the entry was created.
+    #[builder(default)]
+    pub created: Option<DateTime<Local>>,
+
+    /// Timestamp of when the entry was last accessed.
+    #[builder(default)]
+    pub accessed: Option<DateTime<Local>>,
+
     /// Flag indicating that this file is marked as read-only.
     ///
     /// The meaning of this flag can vary depending on the file system, archive
@@ -97,6 +169,14 @@ pub struct Metadata {
     /// platforms, the information can certainly be visible on any platform.
     #[builder(default)]
     pub unix_mode: Option<u32>,
+
+    /// For device file entries, the major and minor device numbers.
+    #[builder(default)]
+    pub device: Option<(u32, u32)>,
+
+    /// The number of hard links to the file, if known.
+    #[builder(default)]
+    pub links: Option<u32>,
 }
 
 impl Metadata {
@@ -107,10 +187,34 @@ impl Metadata {
 
 impl From<fs::Metadata> for Metadata {
     fn from(metadata: fs::Metadata) -> Self {
+        let entry_type = EntryType::from(metadata.file_type());
+
+        #[cfg(unix)]
+        let (unix_mode, device, links) = {
+            use std::os::unix::fs::MetadataExt;
+
+            let device = if entry_type.is_device() {
+                Some((libc::major(metadata.rdev() as _) as u32, libc::minor(metadata.rdev() as _) as u32))
+            } else {
+                None
+            };
+
+            (Some(metadata.mode()), device, Some(metadata.nlink() as u32))
+        };
+
+        #[cfg(not(unix))]
+        let (unix_mode, device, links) = (None, None, None);
+
         Self::builder()
-            .entry_type(metadata.file_type().into())
+            .entry_type(entry_type)
             .size(metadata.len())
             .modified(metadata.modified().ok().map(From::from))
+            .created(metadata.created().ok().map(From::from))
+            .accessed(metadata.accessed().ok().map(From::from))
+            .read_only(metadata.permissions().readonly())
+            .unix_mode(unix_mode)
+            .device(device)
+            .links(links)
             .build()
     }
 }
@@ -119,27 +223,128 @@ impl From<fs::Metadata> for Metadata {
 ///
 /// If the stream has any stream compression algorithms applied, this function
 /// will first attempt to decode them first.
-pub fn open<'r>(mut input: Input<'r>) -> Result<Option<Box<dyn ArchiveReader + 'r>>> {
-    // Automatically decode any compression streams first.
-    input = compress::detect_decode(input)?;
+///
+/// The archive format and compression formats are detected automatically
+/// unless given explicitly.
+pub fn open<'r>(
+    input: Input<'r>,
+    format: Option<&'static dyn ArchiveFormat>,
+    compression: Option<&[&'static dyn CompressionFormat]>,
+) -> Result<Option<Box<dyn ArchiveReader + 'r>>> {
+    let mut input = match compression {
+        Some(compression) => compress::decode(input, compression)?,
+
+        // Automatically decode any compression streams first.
+        None => compress::detect_decode(input)?.0,
+    };
+
+    let format = match format {
+        Some(format) => format,
+        None => match detect(&mut input)? {
+            Some(format) => format,
+            None => return Ok(None),
+        },
+    };
+
+    Ok(Some(format.open(input)?))
+}
 
-    // Detect archive format.
+/// Detect the archive format of the given (already decoded) input stream by
+/// its starting bytes.
+pub fn detect(input: &mut Input<'_>) -> Result<Option<&'static dyn ArchiveFormat>> {
     for format in formats::all() {
         if format.match_bytes(input.fill_buf()?) {
             log::debug!("detected {} archive", format.id());
-            return Ok(Some(format.open(input)?));
+            return Ok(Some(*format));
         }
     }
 
     Ok(None)
 }
 
-pub fn create<'o>(output: &'o mut Output) -> Result<Option<Box<dyn ArchiveWriter + 'o>>> {
-    if let Some(path) = output.path() {
-        if let Some(format) = formats::for_extension(path) {
-            return Ok(Some(format.create(output)?));
-        }
+/// Create a writer for writing a new archive to the given output.
+///
+/// Unless given explicitly, the archive format and any stream compression to
+/// apply are detected from the file extensions of the output's path.
+pub fn create(
+    mut output: Output,
+    format: Option<&'static dyn ArchiveFormat>,
+    compression: Option<Vec<&'static dyn CompressionFormat>>,
+    options: &formats::ArchiveOptions,
+    compression_options: &CompressionOptions,
+) -> Result<Option<Box<dyn ArchiveWriter>>> {
+    let detected = output.path().and_then(formats::for_path);
+
+    let format = match format.or_else(|| detected.as_ref().map(|(format, _)| *format)) {
+        Some(format) => format,
+        None => return Ok(None),
+    };
+
+    let compression = compression
+        .or_else(|| detected.map(|(_, compression)| compression))
+        .unwrap_or_default();
+
+    // Wrap the output in compression encoders, starting with the outermost.
+    for compression_format in compression {
+        log::debug!("encoding output with {} compression", compression_format.id());
+        output = Output::from_encoder(compression_format.new_encoder(output, compression_options)?);
     }
 
-    Ok(None)
+    Ok(Some(format.create(output, options, compression_options)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Read, path::Path};
+    use structopt::StructOpt;
+
+    /// Resolve the archive format and compression format IDs for a path.
+    fn ids_for_path(path: &str) -> Option<(&'static str, Vec<&'static str>)> {
+        formats::for_path(Path::new(path))
+            .map(|(format, compression)| (format.id(), compression.iter().map(|format| format.id()).collect()))
+    }
+
+    #[test]
+    fn compound_extensions() {
+        assert_eq!(ids_for_path("out.tar"), Some(("tar", vec![])));
+        assert_eq!(ids_for_path("out.tar.gz"), Some(("tar", vec!["gzip"])));
+        assert_eq!(ids_for_path("out.tgz"), Some(("tar", vec!["gzip"])));
+        assert_eq!(ids_for_path("release-1.0.tar.xz"), Some(("tar", vec!["xz"])));
+        assert_eq!(ids_for_path("out.tar.zst.bz2"), Some(("tar", vec!["bzip2", "zstd"])));
+        assert_eq!(ids_for_path("out.zip"), Some(("zip", vec![])));
+        assert_eq!(ids_for_path("out.gz"), None);
+        assert_eq!(ids_for_path("out.tar.unknown"), None);
+    }
+
+    #[test]
+    fn compressed_archives_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = formats::ArchiveOptions::from_iter(&["test"]);
+        let compression_options = CompressionOptions::from_iter(&["test"]);
+
+        for name in &["out.tar.gz", "out.tgz", "out.tar.bz2", "out.tar.xz", "out.tar.zst"] {
+            let path = dir.path().join(name);
+            let mut writer = create(Output::create(&path).unwrap(), None, None, &options, &compression_options)
+                .unwrap()
+                .unwrap();
+            let metadata = Metadata::builder().entry_type(EntryType::File).size(5).build();
+
+            writer.add_file(Path::new("hello.txt"), metadata, &mut &b"hello"[..]).unwrap();
+            writer.finish().unwrap();
+
+            let (mut input, compression) = compress::detect_decode(Input::open(&path).unwrap()).unwrap();
+            let compression = compression.iter().map(|format| format.id()).collect::<Vec<_>>();
+            assert_eq!(compression, ids_for_path(name).unwrap().1, "{}", name);
+            assert_eq!(detect(&mut input).unwrap().map(|format| format.id()), Some("tar"), "{}", name);
+
+            let mut reader = open(Input::open(&path).unwrap(), None, None).unwrap().unwrap();
+            let mut entry = reader.entry().unwrap().unwrap();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+
+            assert_eq!(entry.path(), Path::new("hello.txt"));
+            assert_eq!(data, b"hello", "{}", name);
+        }
+    }
 }
@@ -27,8 +27,11 @@ pub trait Entry: Read {
     /// Get the metadata for this entry.
     fn metadata(&self) -> super::Metadata;
 
-    /// If this entry is a symbolic link of some kind, get the target path that
+    /// If this entry is a symbolic link or hard link, get the target path that
     /// the link points to.
+    ///
+    /// For hard links, the target is the path of another entry relative to the
+    /// root of the archive.
     fn read_link(&mut self) -> io::Result<Option<Cow<'_, Path>>> {
         Ok(None)
     }
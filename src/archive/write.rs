@@ -1,6 +1,6 @@
 use super::Metadata;
 use std::{
-    io::{Read, Result},
+    io::{Error, Read, Result},
     path::Path,
 };
 
@@ -15,9 +15,32 @@ pub trait ArchiveWriter {
     /// Add a file to the archive from a byte stream.
     ///
     /// The path for the directory specified is relative to the root of the
-    /// archive.
+    /// archive. Special files such as devices and FIFOs are also added this
+    /// way, without any data and with their type given by the metadata. Not
+    /// all formats support special files, in which case an error is returned.
     fn add_file(&mut self, path: &Path, metadata: Metadata, file: &mut dyn Read) -> Result<()>;
 
-    ///Finish writing the archive.
-    fn finish(&mut self) -> Result<()>;
+    /// Add a symbolic link to the archive pointing to the given target path.
+    ///
+    /// The path for the link specified is relative to the root of the archive.
+    /// The target is stored as-is. Not all formats support symbolic links, in
+    /// which case an error is returned.
+    fn add_symlink(&mut self, _path: &Path, _metadata: Metadata, _target: &Path) -> Result<()> {
+        Err(Error::other("symbolic links are not supported by this archive format"))
+    }
+
+    /// Add a hard link to the archive pointing to an entry added previously.
+    ///
+    /// Both the path of the link and its target are relative to the root of
+    /// the archive. Not all formats support hard links, in which case an error
+    /// is returned.
+    fn add_hard_link(&mut self, _path: &Path, _metadata: Metadata, _target: &Path) -> Result<()> {
+        Err(Error::other("hard links are not supported by this archive format"))
+    }
+
+    /// Finish writing the archive.
+    ///
+    /// This also finishes the output the archive is being written to, including
+    /// any compression streams it is wrapped in.
+    fn finish(self: Box<Self>) -> Result<()>;
 }
@@ -3,9 +3,9 @@
 use crate::{
     format::Format,
     input::Input,
-    util::MaybeBoxedMut,
+    output::Output,
 };
-use std::{fmt, io::{Read, Result, Write}};
+use std::{fmt, io::{Read, Result}};
 
 pub struct Bzip2;
 
@@ -34,7 +34,15 @@ impl super::CompressionFormat for Bzip2 {
         Ok(Box::new(bzip2::read::BzDecoder::new(input)))
     }
 
-    fn new_encoder<'w>(&self, writer: MaybeBoxedMut<'w, dyn Write>) -> Result<Box<dyn Write + 'w>> {
-        Ok(Box::new(bzip2::write::BzEncoder::new(writer, bzip2::Compression::Default)))
+    fn new_encoder(&self, output: Output, options: &super::CompressionOptions) -> Result<Box<dyn super::Encoder>> {
+        let level = bzip2::Compression::new(options.level(1, 9, bzip2::Compression::default().level()));
+
+        Ok(Box::new(bzip2::write::BzEncoder::new(output, level)))
+    }
+}
+
+impl super::Encoder for bzip2::write::BzEncoder<Output> {
+    fn finish(self: Box<Self>) -> Result<Output> {
+        bzip2::write::BzEncoder::finish(*self)
     }
 }
@@ -1,10 +1,47 @@
 //! https://en.wikipedia.org/wiki/Compress
+//!
+//! The format is a 3 byte header followed by a stream of LZW codes packed
+//! least significant bit first. Codes start out 9 bits wide and grow by one bit
+//! each time the code table fills up, up to the maximum width given in the
+//! header.
+//!
+//! A quirk inherited from the original implementation is that codes are
+//! written in groups of 8, so that a group of codes of width `n` is always `n`
+//! bytes long. Whenever the code width changes, the remainder of the current
+//! group is skipped.
 
 use crate::{
     format::Format,
     input::Input,
+    output::Output,
 };
-use std::{fmt, io::{Read, Result}};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{Error, ErrorKind, Read, Result, Write},
+};
+
+const MAGIC: [u8; 2] = [0x1f, 0x9d];
+
+/// Header flag indicating that clear codes may be used to reset the code
+/// table.
+const BLOCK_MODE: u8 = 0x80;
+
+/// Header bits containing the maximum code width.
+const MAX_BITS_MASK: u8 = 0x1f;
+
+const INIT_BITS: u8 = 9;
+const MAX_BITS: u8 = 16;
+
+/// Code used in block mode to reset the code table.
+const CLEAR: u32 = 256;
+
+/// First free code in block mode.
+const FIRST: u32 = 257;
+
+/// How many input bytes to compress between checking whether the compression
+/// ratio has dropped once the code table is full.
+const CHECK_GAP: u64 = 10_000;
 
 pub struct Compress;
 
@@ -29,4 +66,477 @@ impl fmt::Display for Compress {
 }
 
 impl super::CompressionFormat for Compress {
+    fn new_decoder<'r>(&self, input: Input<'r>) -> Result<Box<dyn Read + 'r>> {
+        Ok(Box::new(CompressDecoder::new(input)?))
+    }
+
+    fn new_encoder(&self, output: Output, _options: &super::CompressionOptions) -> Result<Box<dyn super::Encoder>> {
+        Ok(Box::new(CompressEncoder::new(output)?))
+    }
+}
+
+/// Get the largest code that can be used with the given code width before the
+/// width must increase.
+///
+/// Note that the original implementation always starts with 9 bit codes, even
+/// if the maximum width is also 9 bits.
+fn max_code(n_bits: u8, max_bits: u8) -> u32 {
+    if n_bits == max_bits && n_bits > INIT_BITS {
+        1 << max_bits
+    } else {
+        (1 << n_bits) - 1
+    }
+}
+
+fn corrupt(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("corrupt compress stream: {}", message))
+}
+
+pub struct CompressDecoder<R> {
+    reader: R,
+    max_bits: u8,
+    block_mode: bool,
+    n_bits: u8,
+    max_code: u32,
+
+    /// The next free code in the table.
+    free_ent: u32,
+
+    /// The previously read code, or `None` at the start of the stream or after
+    /// the table was cleared.
+    old_code: Option<u32>,
+
+    /// First byte of the most recently decoded string.
+    fin_char: u8,
+
+    prefix: Vec<u16>,
+    suffix: Vec<u8>,
+
+    /// The current group of codes being read, along with the bit position and
+    /// number of bits available.
+    group: [u8; MAX_BITS as usize + 2],
+    group_pos: usize,
+    group_bits: usize,
+
+    /// Decoded bytes not yet returned, in reverse order.
+    stack: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> CompressDecoder<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0; 3];
+        reader.read_exact(&mut header)?;
+
+        if header[..2] != MAGIC {
+            return Err(corrupt("invalid magic bytes"));
+        }
+
+        let max_bits = header[2] & MAX_BITS_MASK;
+
+        if !(INIT_BITS..=MAX_BITS).contains(&max_bits) {
+            return Err(corrupt(&format!("unsupported maximum code width of {} bits", max_bits)));
+        }
+
+        let block_mode = header[2] & BLOCK_MODE != 0;
+
+        Ok(Self {
+            reader,
+            max_bits,
+            block_mode,
+            n_bits: INIT_BITS,
+            max_code: max_code(INIT_BITS, max_bits),
+            free_ent: if block_mode { FIRST } else { 256 },
+            old_code: None,
+            fin_char: 0,
+            prefix: vec![0; 1 << max_bits],
+            suffix: vec![0; 1 << max_bits],
+            group: [0; MAX_BITS as usize + 2],
+            group_pos: 0,
+            group_bits: 0,
+            stack: Vec::new(),
+            done: false,
+        })
+    }
+
+    /// Read the next code from the stream, or `None` at the end of the stream.
+    fn read_code(&mut self) -> Result<Option<u32>> {
+        let n_bits = self.n_bits as usize;
+
+        // Read in the next group of codes if the current one is used up.
+        if self.group_pos + n_bits > self.group_bits {
+            let mut len = 0;
+
+            while len < n_bits {
+                match self.reader.read(&mut self.group[len..n_bits]) {
+                    Ok(0) => break,
+                    Ok(n) => len += n,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+
+            // Clear out any stale bytes from the previous group.
+            for byte in &mut self.group[len..] {
+                *byte = 0;
+            }
+
+            self.group_pos = 0;
+            self.group_bits = len * 8;
+
+            // Any bits left over that don't form a whole code are padding.
+            if n_bits > self.group_bits {
+                return Ok(None);
+            }
+        }
+
+        let byte = self.group_pos / 8;
+        let word = self.group[byte] as u32
+            | (self.group[byte + 1] as u32) << 8
+            | (self.group[byte + 2] as u32) << 16;
+        let code = (word >> (self.group_pos % 8)) & ((1 << n_bits) - 1);
+
+        self.group_pos += n_bits;
+
+        Ok(Some(code))
+    }
+
+    /// Skip the remainder of the current group of codes.
+    fn skip_group(&mut self) {
+        self.group_pos = 0;
+        self.group_bits = 0;
+    }
+
+    /// Decode the next code into the output stack.
+    fn decode_next(&mut self) -> Result<()> {
+        if self.free_ent > self.max_code {
+            self.skip_group();
+            self.n_bits += 1;
+            self.max_code = max_code(self.n_bits, self.max_bits);
+        }
+
+        let mut code = match self.read_code()? {
+            Some(code) => code,
+            None => {
+                self.done = true;
+                return Ok(());
+            }
+        };
+
+        let old_code = match self.old_code {
+            Some(old_code) => old_code,
+
+            // The first code after a reset is always a literal byte.
+            None => {
+                if code >= 256 {
+                    return Err(corrupt("expected a literal code"));
+                }
+
+                self.old_code = Some(code);
+                self.fin_char = code as u8;
+                self.stack.push(code as u8);
+
+                return Ok(());
+            }
+        };
+
+        if code == CLEAR && self.block_mode {
+            self.skip_group();
+            self.n_bits = INIT_BITS;
+            self.max_code = max_code(INIT_BITS, self.max_bits);
+            self.free_ent = FIRST;
+            self.old_code = None;
+
+            return Ok(());
+        }
+
+        let in_code = code;
+
+        // Special case for a code that is about to be defined by this step,
+        // which must be the previous string followed by its own first byte.
+        if code >= self.free_ent {
+            if code > self.free_ent {
+                return Err(corrupt("invalid code"));
+            }
+
+            self.stack.push(self.fin_char);
+            code = old_code;
+        }
+
+        // Walk the string backwards, pushing each byte onto the stack.
+        while code >= 256 {
+            self.stack.push(self.suffix[code as usize]);
+            code = self.prefix[code as usize] as u32;
+        }
+
+        self.fin_char = code as u8;
+        self.stack.push(self.fin_char);
+
+        // Add the new string to the table.
+        if self.free_ent < 1 << self.max_bits {
+            self.prefix[self.free_ent as usize] = old_code as u16;
+            self.suffix[self.free_ent as usize] = self.fin_char;
+            self.free_ent += 1;
+        }
+
+        self.old_code = Some(in_code);
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for CompressDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut len = 0;
+
+        // Each code only decodes to a few bytes, so keep going until the buffer
+        // is full. Otherwise callers that only look at what a single read
+        // returns, such as format detection, would see very little.
+        while len < buf.len() {
+            match self.stack.pop() {
+                Some(byte) => {
+                    buf[len] = byte;
+                    len += 1;
+                }
+                None if self.done => break,
+                None => self.decode_next()?,
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+pub struct CompressEncoder {
+    output: Output,
+    n_bits: u8,
+    max_code: u32,
+
+    /// The next free code in the table.
+    free_ent: u32,
+
+    /// Table of known strings, keyed by the code of their prefix and their
+    /// last byte.
+    table: HashMap<(u32, u8), u32>,
+
+    /// Code of the longest string matched so far that has not yet been
+    /// written.
+    prefix: Option<u32>,
+
+    /// The current group of codes being written, along with the number of bits
+    /// used.
+    group: [u8; MAX_BITS as usize + 2],
+    group_bits: usize,
+
+    in_count: u64,
+    bytes_out: u64,
+    checkpoint: u64,
+    ratio: u64,
+}
+
+impl CompressEncoder {
+    pub fn new(mut output: Output) -> Result<Self> {
+        output.write_all(&MAGIC)?;
+        output.write_all(&[BLOCK_MODE | MAX_BITS])?;
+
+        Ok(Self {
+            output,
+            n_bits: INIT_BITS,
+            max_code: max_code(INIT_BITS, MAX_BITS),
+            free_ent: FIRST,
+            table: HashMap::new(),
+            prefix: None,
+            group: [0; MAX_BITS as usize + 2],
+            group_bits: 0,
+            in_count: 0,
+            bytes_out: 3,
+            checkpoint: CHECK_GAP,
+            ratio: 0,
+        })
+    }
+
+    fn write_code(&mut self, code: u32) -> Result<()> {
+        let byte = self.group_bits / 8;
+        let word = code << (self.group_bits % 8);
+
+        self.group[byte] |= word as u8;
+        self.group[byte + 1] |= (word >> 8) as u8;
+        self.group[byte + 2] |= (word >> 16) as u8;
+        self.group_bits += self.n_bits as usize;
+
+        if self.group_bits == self.n_bits as usize * 8 {
+            self.flush_group()?;
+        }
+
+        // Widen the codes once the decoder's table will be full, padding out
+        // the current group as the decoder expects.
+        if self.free_ent > self.max_code {
+            self.pad_group()?;
+            self.n_bits += 1;
+            self.max_code = max_code(self.n_bits, MAX_BITS);
+        }
+
+        Ok(())
+    }
+
+    /// Write out the current group of codes.
+    fn flush_group(&mut self) -> Result<()> {
+        let len = self.group_bits.div_ceil(8);
+
+        self.output.write_all(&self.group[..len])?;
+        self.bytes_out += len as u64;
+        self.group = [0; MAX_BITS as usize + 2];
+        self.group_bits = 0;
+
+        Ok(())
+    }
+
+    /// Write out the current group of codes padded to its full length.
+    fn pad_group(&mut self) -> Result<()> {
+        if self.group_bits > 0 {
+            self.group_bits = self.n_bits as usize * 8;
+            self.flush_group()?;
+        }
+
+        Ok(())
+    }
+
+    /// Reset the code table if the compression ratio has dropped since the last
+    /// time it was checked.
+    fn check_ratio(&mut self) -> Result<()> {
+        self.checkpoint = self.in_count + CHECK_GAP;
+
+        let ratio = (self.in_count << 8) / self.bytes_out.max(1);
+
+        if ratio > self.ratio {
+            self.ratio = ratio;
+        } else {
+            self.ratio = 0;
+            self.table.clear();
+            self.free_ent = FIRST;
+            self.write_code(CLEAR)?;
+            self.pad_group()?;
+            self.n_bits = INIT_BITS;
+            self.max_code = max_code(INIT_BITS, MAX_BITS);
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for CompressEncoder {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        for &byte in buf {
+            self.in_count += 1;
+
+            let prefix = match self.prefix {
+                Some(prefix) => prefix,
+                None => {
+                    self.prefix = Some(byte as u32);
+                    continue;
+                }
+            };
+
+            if let Some(&code) = self.table.get(&(prefix, byte)) {
+                self.prefix = Some(code);
+                continue;
+            }
+
+            self.write_code(prefix)?;
+            self.prefix = Some(byte as u32);
+
+            if self.free_ent < 1 << MAX_BITS {
+                self.table.insert((prefix, byte), self.free_ent);
+                self.free_ent += 1;
+            } else if self.in_count >= self.checkpoint {
+                self.check_ratio()?;
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.output.flush()
+    }
+}
+
+impl super::Encoder for CompressEncoder {
+    fn finish(mut self: Box<Self>) -> Result<Output> {
+        if let Some(prefix) = self.prefix.take() {
+            self.write_code(prefix)?;
+        }
+
+        self.flush_group()?;
+
+        Ok(self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{archive, compress::formats::{CompressionFormat, CompressionOptions}};
+    use std::fs;
+    use structopt::StructOpt;
+
+    /// Text of the `lines.txt.Z` fixture, which was created by `bsdtar`.
+    fn lines() -> Vec<u8> {
+        (0..2000)
+            .map(|i| format!("line {}: the quick brown fox jumps over {} lazy dogs\n", i, i * i % 97))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.Z");
+        let options = CompressionOptions::from_iter(&["test"]);
+
+        let mut encoder = Compress.new_encoder(Output::create(&path).unwrap(), &options).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap().finish().unwrap();
+
+        fs::read(path).unwrap()
+    }
+
+    fn decompress(data: &[u8]) -> Vec<u8> {
+        let mut decoded = Vec::new();
+        CompressDecoder::new(data).unwrap().read_to_end(&mut decoded).unwrap();
+        decoded
+    }
+
+    #[test]
+    fn decode_fixture() {
+        assert_eq!(decompress(include_bytes!("../../../tests/fixtures/lines.txt.Z")), lines());
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(decompress(&compress(&lines())), lines());
+
+        // Data that doesn't compress well fills up the code table, so the
+        // encoder has to clear it.
+        let mut state = 1u32;
+        let noise = (0..1 << 20)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(decompress(&compress(&noise)), noise);
+    }
+
+    #[test]
+    fn detect_compressed_tar() {
+        let input = Input::from_reader(&include_bytes!("../../../tests/fixtures/hello.tar.Z")[..]).unwrap();
+        let mut reader = archive::open(input, None, None).unwrap().expect("archive format not detected");
+        let mut entry = reader.entry().unwrap().unwrap();
+        let mut contents = String::new();
+        entry.read_to_string(&mut contents).unwrap();
+
+        assert_eq!(entry.path().to_str(), Some("hello.txt"));
+        assert_eq!(contents, "hello from a compressed tar\n");
+    }
 }
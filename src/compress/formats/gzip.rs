@@ -5,8 +5,22 @@
 use crate::{
     format::Format,
     input::Input,
+    output::Output,
 };
-use std::{fmt, io::{Read, Result}};
+use std::{
+    fmt,
+    io::{Read, Result},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Value of the OS header field for the current platform.
+#[cfg(unix)]
+const OS: u8 = 3;
+#[cfg(windows)]
+const OS: u8 = 11;
+#[cfg(not(any(unix, windows)))]
+const OS: u8 = 255;
 
 pub struct Gzip;
 
@@ -34,4 +48,77 @@ impl super::CompressionFormat for Gzip {
     fn new_decoder<'r>(&self, input: Input<'r>) -> Result<Box<dyn Read + 'r>> {
         Ok(Box::new(flate2::read::GzDecoder::new(input)))
     }
+
+    fn new_encoder(&self, output: Output, options: &super::CompressionOptions) -> Result<Box<dyn super::Encoder>> {
+        // Record the time the data was compressed, since it does not come from
+        // a single file with its own modification time.
+        let mtime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as u32)
+            .unwrap_or(0);
+
+        let mut builder = flate2::GzBuilder::new()
+            .mtime(mtime)
+            .operating_system(OS);
+
+        if let Some(name) = output.path().and_then(original_file_name) {
+            builder = builder.filename(name);
+        }
+
+        Ok(Box::new(builder.write(output, flate2::Compression::new(options.level(1, 9, 6)))))
+    }
+}
+
+impl super::Encoder for flate2::write::GzEncoder<Output> {
+    fn finish(self: Box<Self>) -> Result<Output> {
+        flate2::write::GzEncoder::finish(*self)
+    }
+}
+
+/// Get the name of the uncompressed file for the given gzip file path, as
+/// `gunzip` would restore it.
+fn original_file_name(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_string_lossy();
+
+    if let Some(stem) = name.strip_suffix(".gz") {
+        Some(stem.to_owned())
+    } else {
+        name.strip_suffix(".tgz").map(|stem| format!("{}.tar", stem))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::formats::{CompressionFormat, CompressionOptions};
+    use std::{fs, io::Write};
+    use structopt::StructOpt;
+
+    #[test]
+    fn header_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt.gz");
+        let options = CompressionOptions::from_iter(&["test"]);
+
+        let mut encoder = Gzip.new_encoder(Output::create(&path).unwrap(), &options).unwrap();
+        encoder.write_all(b"hello\n").unwrap();
+        encoder.finish().unwrap().finish().unwrap();
+
+        let mut decoder = flate2::read::GzDecoder::new(fs::File::open(&path).unwrap());
+        let mut data = Vec::new();
+        decoder.read_to_end(&mut data).unwrap();
+        let header = decoder.header().unwrap();
+
+        assert_eq!(data, b"hello\n");
+        assert_eq!(header.filename(), Some(&b"notes.txt"[..]));
+        assert_eq!(header.operating_system(), OS);
+        assert_ne!(header.mtime(), 0);
+    }
+
+    #[test]
+    fn original_file_names() {
+        assert_eq!(original_file_name(Path::new("dir/notes.txt.gz")).as_deref(), Some("notes.txt"));
+        assert_eq!(original_file_name(Path::new("release.tgz")).as_deref(), Some("release.tar"));
+        assert_eq!(original_file_name(Path::new("notes.txt")), None);
+    }
 }
@@ -0,0 +1,119 @@
+//! The LZ4 frame format.
+//!
+//! https://github.com/lz4/lz4/blob/dev/doc/lz4_Frame_format.md
+
+use crate::{
+    format::Format,
+    input::Input,
+    output::Output,
+};
+use lz4_flex::frame::{FrameDecoder, FrameEncoder, FrameInfo};
+use std::{fmt, io::{Read, Result}};
+
+pub struct Lz4;
+
+impl Format for Lz4 {
+    fn id(&self) -> &str {
+        "lz4"
+    }
+
+    fn file_extensions(&self) -> &[&str] {
+        &["lz4"]
+    }
+
+    fn match_bytes(&self, bytes: &[u8]) -> bool {
+        // Either a regular frame or a legacy frame.
+        matches!(bytes, [0x04, 0x22, 0x4d, 0x18, ..] | [0x02, 0x21, 0x4c, 0x18, ..])
+    }
+}
+
+impl fmt::Display for Lz4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LZ4")
+    }
+}
+
+impl super::CompressionFormat for Lz4 {
+    fn new_decoder<'r>(&self, input: Input<'r>) -> Result<Box<dyn Read + 'r>> {
+        // Content checksums are verified by the decoder whenever a frame
+        // includes one.
+        Ok(Box::new(FrameDecoder::new(input)))
+    }
+
+    fn new_encoder(&self, output: Output, _options: &super::CompressionOptions) -> Result<Box<dyn super::Encoder>> {
+        let frame_info = FrameInfo::new().content_checksum(true);
+
+        Ok(Box::new(FrameEncoder::with_frame_info(frame_info, output)))
+    }
+}
+
+impl super::Encoder for FrameEncoder<Output> {
+    fn finish(self: Box<Self>) -> Result<Output> {
+        Ok(FrameEncoder::finish(*self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::formats::{CompressionFormat, CompressionOptions, Format};
+    use std::{fs, io::Write};
+    use structopt::StructOpt;
+
+    /// Text of the `lines.txt.lz4` fixtures, which were created by `lz4`.
+    fn lines() -> Vec<u8> {
+        (0..2000)
+            .map(|i| format!("line {}: the quick brown fox jumps over {} lazy dogs\n", i, i * i % 97))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.lz4");
+        let options = CompressionOptions::from_iter(&["test"]);
+
+        let mut encoder = Lz4.new_encoder(Output::create(&path).unwrap(), &options).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap().finish().unwrap();
+
+        fs::read(path).unwrap()
+    }
+
+    fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        Lz4.new_decoder(Input::from_reader(data)?)?.read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+
+    #[test]
+    fn decode_fixtures() {
+        for fixture in &[
+            &include_bytes!("../../../tests/fixtures/lines.txt.lz4")[..],
+            &include_bytes!("../../../tests/fixtures/lines-legacy.txt.lz4")[..],
+        ] {
+            assert!(Lz4.match_bytes(fixture));
+            assert_eq!(decompress(fixture).unwrap(), lines());
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let compressed = compress(&lines());
+
+        // The frame descriptor flags a content checksum.
+        assert!(Lz4.match_bytes(&compressed));
+        assert_ne!(compressed[4] & 0x04, 0);
+        assert_eq!(decompress(&compressed).unwrap(), lines());
+        assert_eq!(decompress(&compress(&[])).unwrap(), b"");
+    }
+
+    #[test]
+    fn corrupt_checksums_are_rejected() {
+        let mut compressed = compress(&lines());
+        let checksum = compressed.len() - 1;
+        compressed[checksum] ^= 0x55;
+
+        assert!(decompress(&compressed).is_err());
+    }
+}
@@ -1,11 +1,53 @@
 //! https://en.wikipedia.org/wiki/Lzip
 //! https://www.nongnu.org/lzip/manual/lzip_manual.html#File-format
+//!
+//! An lzip file is a sequence of members, each of which is a small header, a
+//! raw LZMA stream terminated by an end-of-stream marker, and a trailer. liblzma
+//! does not expose a raw LZMA1 decoder through `xz2`, so the legacy `.lzma`
+//! coders are used instead, adding or removing the `.lzma` header as needed.
 
 use crate::{
     format::Format,
     input::Input,
+    output::Output,
 };
-use std::{fmt, io::{Read, Result}};
+use std::{
+    convert::TryInto,
+    fmt,
+    io::{BufRead, Error, ErrorKind, Read, Result, Write},
+    mem,
+};
+use xz2::stream::{Action, LzmaOptions, Status, Stream};
+
+const MAGIC: &[u8; 4] = b"LZIP";
+const VERSION: u8 = 1;
+const HEADER_SIZE: u64 = 6;
+const TRAILER_SIZE: u64 = 20;
+
+/// Size of the header of the legacy `.lzma` format.
+const LZMA_HEADER_SIZE: usize = 13;
+
+/// LZMA properties byte for the only parameters lzip allows: lc=3, lp=0, pb=2.
+const LZMA_PROPERTIES: u8 = 0x5d;
+
+const MIN_DICT_SIZE: u32 = 1 << 12;
+const MAX_DICT_SIZE: u32 = 1 << 29;
+
+const DEFAULT_LEVEL: u32 = 6;
+
+/// Dictionary sizes used by the LZMA presets for each compression level.
+const DICT_SIZES: [u32; 10] = [
+    256 << 10,
+    1 << 20,
+    2 << 20,
+    4 << 20,
+    4 << 20,
+    8 << 20,
+    8 << 20,
+    16 << 20,
+    32 << 20,
+    64 << 20,
+];
 
 pub struct Lzip;
 
@@ -30,8 +72,385 @@ impl fmt::Display for Lzip {
 }
 
 impl super::CompressionFormat for Lzip {
+    fn new_decoder<'r>(&self, input: Input<'r>) -> Result<Box<dyn Read + 'r>> {
+        Ok(Box::new(LzipDecoder::new(input)))
+    }
+
+    fn new_encoder(&self, output: Output, options: &super::CompressionOptions) -> Result<Box<dyn super::Encoder>> {
+        let level = options.level(0, 9, DEFAULT_LEVEL);
+
+        Ok(Box::new(LzipEncoder::new(output, level, DICT_SIZES[level as usize])?))
+    }
+}
+
+fn corrupt(message: impl fmt::Display) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("corrupt lzip stream: {}", message))
+}
+
+/// Decode the coded dictionary size from a member header.
+fn decode_dict_size(byte: u8) -> Option<u32> {
+    let exponent = byte & 0x1f;
+
+    if !(12..=29).contains(&exponent) {
+        return None;
+    }
+
+    let base = 1u32 << exponent;
+    let size = base - (base / 16) * (byte >> 5) as u32;
+
+    if size < MIN_DICT_SIZE {
+        None
+    } else {
+        Some(size)
+    }
+}
+
+/// Code a dictionary size for a member header, rounding up to the nearest size
+/// that can be represented.
+fn encode_dict_size(size: u32) -> (u8, u32) {
+    let size = size.clamp(MIN_DICT_SIZE, MAX_DICT_SIZE);
+    let exponent = 32 - (size - 1).leading_zeros();
+    let base = 1u32 << exponent;
+
+    if exponent <= 12 {
+        return (exponent as u8, base);
+    }
+
+    // Subtract as many sixteenths of the base as possible while still being at
+    // least as big as the requested size.
+    let fraction = ((base - size) / (base / 16)).min(7);
+
+    ((fraction << 5) as u8 | exponent as u8, base - (base / 16) * fraction)
+}
+
+/// Create a stream for decoding the LZMA data of a single member.
+fn new_member_stream(dict_size: u32) -> Result<(Stream, [u8; LZMA_HEADER_SIZE])> {
+    let stream = Stream::new_lzma_decoder(u64::MAX)?;
+
+    // Legacy header with an unknown uncompressed size, which requires the
+    // end-of-stream marker that lzip always uses.
+    let mut header = [0xff; LZMA_HEADER_SIZE];
+    header[0] = LZMA_PROPERTIES;
+    header[1..5].copy_from_slice(&dict_size.to_le_bytes());
+
+    Ok((stream, header))
+}
+
+/// State of the member currently being decoded.
+struct Member {
+    stream: Stream,
+    lzma_header: [u8; LZMA_HEADER_SIZE],
+    crc: crc32fast::Hasher,
+}
+
+pub struct LzipDecoder<R> {
+    reader: R,
+    member: Option<Member>,
+    members: usize,
+    done: bool,
+}
+
+impl<R: BufRead> LzipDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            member: None,
+            members: 0,
+            done: false,
+        }
+    }
+
+    /// Read the header of the next member, if there is one.
+    fn start_member(&mut self) -> Result<bool> {
+        let mut header = [0; HEADER_SIZE as usize];
+        let mut len = 0;
+
+        while len < header.len() {
+            match self.reader.read(&mut header[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+
+        if len == 0 && self.members > 0 {
+            return Ok(false);
+        }
+
+        if len < 4 || &header[..4] != MAGIC {
+            if self.members > 0 {
+                // Like lzip itself, ignore any trailing data after the last
+                // member.
+                log::debug!("ignoring trailing data after lzip member {}", self.members);
+                return Ok(false);
+            }
+
+            return Err(corrupt("invalid magic bytes"));
+        }
+
+        if len < header.len() {
+            return Err(corrupt("truncated member header"));
+        }
+
+        if header[4] != VERSION {
+            return Err(corrupt(format_args!("unsupported version {}", header[4])));
+        }
+
+        let dict_size = decode_dict_size(header[5])
+            .ok_or_else(|| corrupt("invalid dictionary size"))?;
+
+        let (stream, lzma_header) = new_member_stream(dict_size)?;
+
+        self.member = Some(Member {
+            stream,
+            lzma_header,
+            crc: crc32fast::Hasher::new(),
+        });
+        self.members += 1;
+
+        Ok(true)
+    }
+
+    /// Read and verify the trailer of the member just decoded.
+    fn finish_member(&mut self, member: Member) -> Result<()> {
+        let mut trailer = [0; TRAILER_SIZE as usize];
+
+        self.reader.read_exact(&mut trailer).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => corrupt("truncated member trailer"),
+            _ => e,
+        })?;
+
+        let crc = u32::from_le_bytes(trailer[0..4].try_into().unwrap());
+        let data_size = u64::from_le_bytes(trailer[4..12].try_into().unwrap());
+        let member_size = u64::from_le_bytes(trailer[12..20].try_into().unwrap());
+
+        if crc != member.crc.finalize() {
+            return Err(corrupt("CRC mismatch"));
+        }
+
+        if data_size != member.stream.total_out() {
+            return Err(corrupt("data size mismatch"));
+        }
+
+        let lzma_size = member.stream.total_in() - LZMA_HEADER_SIZE as u64;
+
+        if member_size != HEADER_SIZE + lzma_size + TRAILER_SIZE {
+            return Err(corrupt("member size mismatch"));
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for LzipDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if self.member.is_none() && (self.done || !self.start_member()?) {
+                self.done = true;
+                return Ok(0);
+            }
+
+            let member = self.member.as_mut().unwrap();
+
+            // Feed the synthesized legacy header to the decoder first.
+            let header_pos = member.stream.total_in() as usize;
+            let feeding_header = header_pos < LZMA_HEADER_SIZE;
+            let input = if feeding_header {
+                &member.lzma_header[header_pos..]
+            } else {
+                self.reader.fill_buf()?
+            };
+
+            let eof = input.is_empty();
+            let total_in = member.stream.total_in();
+            let total_out = member.stream.total_out();
+
+            let status = member.stream.process(input, buf, Action::Run)?;
+
+            let consumed = (member.stream.total_in() - total_in) as usize;
+            let produced = (member.stream.total_out() - total_out) as usize;
+
+            if !feeding_header {
+                self.reader.consume(consumed);
+            }
+
+            member.crc.update(&buf[..produced]);
+
+            if status == Status::StreamEnd {
+                let member = self.member.take().unwrap();
+                self.finish_member(member)?;
+            } else if eof && consumed == 0 && produced == 0 {
+                return Err(corrupt("unexpected end of stream"));
+            }
+
+            if produced > 0 || buf.is_empty() {
+                return Ok(produced);
+            }
+        }
+    }
+}
+
+pub struct LzipEncoder {
+    output: Output,
+    stream: Stream,
+
+    /// Number of bytes of the legacy header produced by the LZMA encoder that
+    /// still need to be stripped.
+    skip: usize,
+
+    buffer: Vec<u8>,
+    crc: crc32fast::Hasher,
+    data_size: u64,
+}
+
+impl LzipEncoder {
+    pub fn new(mut output: Output, level: u32, dict_size: u32) -> Result<Self> {
+        let (coded_dict_size, dict_size) = encode_dict_size(dict_size);
+
+        let mut options = LzmaOptions::new_preset(level)?;
+        options
+            .dict_size(dict_size)
+            .literal_context_bits(3)
+            .literal_position_bits(0)
+            .position_bits(2);
+
+        output.write_all(MAGIC)?;
+        output.write_all(&[VERSION, coded_dict_size])?;
+
+        Ok(Self {
+            output,
+            stream: Stream::new_lzma_encoder(&options)?,
+            skip: LZMA_HEADER_SIZE,
+            buffer: Vec::with_capacity(32 * 1024),
+            crc: crc32fast::Hasher::new(),
+            data_size: 0,
+        })
+    }
+
+    /// Run the encoder over the given input, writing any output produced.
+    fn process(&mut self, input: &[u8], action: Action) -> Result<(usize, Status)> {
+        self.buffer.clear();
+
+        let total_in = self.stream.total_in();
+        let status = self.stream.process_vec(input, &mut self.buffer, action)?;
+        let consumed = (self.stream.total_in() - total_in) as usize;
+
+        let skip = self.skip.min(self.buffer.len());
+        self.skip -= skip;
+        self.output.write_all(&self.buffer[skip..])?;
+
+        Ok((consumed, status))
+    }
+}
+
+impl Write for LzipEncoder {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut written = 0;
+
+        while written < buf.len() {
+            let (consumed, _) = self.process(&buf[written..], Action::Run)?;
+            written += consumed;
+        }
+
+        self.crc.update(buf);
+        self.data_size += buf.len() as u64;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.output.flush()
+    }
 }
 
-pub struct LzipDecoder {}
+impl super::Encoder for LzipEncoder {
+    fn finish(mut self: Box<Self>) -> Result<Output> {
+        while self.process(&[], Action::Finish)?.1 != Status::StreamEnd {}
+
+        let lzma_size = self.stream.total_out() - LZMA_HEADER_SIZE as u64;
+        let crc = mem::replace(&mut self.crc, crc32fast::Hasher::new()).finalize();
+
+        self.output.write_all(&crc.to_le_bytes())?;
+        self.output.write_all(&self.data_size.to_le_bytes())?;
+        self.output.write_all(&(HEADER_SIZE + lzma_size + TRAILER_SIZE).to_le_bytes())?;
+
+        Ok(self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::formats::{CompressionFormat, CompressionOptions};
+    use std::fs;
+    use structopt::StructOpt;
+
+    /// Text of the `lines.txt.lz` fixture, which was created by `bsdtar`.
+    fn lines() -> Vec<u8> {
+        (0..2000)
+            .map(|i| format!("line {}: the quick brown fox jumps over {} lazy dogs\n", i, i * i % 97))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    fn compress(data: &[u8], args: &[&str]) -> Vec<u8> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.lz");
+        let options = CompressionOptions::from_iter(args);
+
+        let mut encoder = Lzip.new_encoder(Output::create(&path).unwrap(), &options).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap().finish().unwrap();
+
+        fs::read(path).unwrap()
+    }
+
+    fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        LzipDecoder::new(data).read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+
+    #[test]
+    fn decode_fixture() {
+        let fixture = include_bytes!("../../../tests/fixtures/lines.txt.lz");
+
+        assert_eq!(decompress(fixture).unwrap(), lines());
+
+        // Multiple members are decoded one after another.
+        assert_eq!(decompress(&[&fixture[..], &fixture[..]].concat()).unwrap(), [lines(), lines()].concat());
+    }
+
+    #[test]
+    fn round_trip() {
+        for args in &[&["test", "--fast"][..], &["test"][..]] {
+            assert_eq!(decompress(&compress(&lines(), args)).unwrap(), lines());
+            assert_eq!(decompress(&compress(&[], args)).unwrap(), b"");
+        }
+    }
 
-pub struct LzipEncoder {}
+    #[test]
+    fn dictionary_sizes() {
+        for &size in &[MIN_DICT_SIZE, 65_535, 1 << 20, (1 << 20) + 1, 3 << 20, 64 << 20, MAX_DICT_SIZE] {
+            let (byte, coded_size) = encode_dict_size(size);
+
+            assert!(coded_size >= size, "{} coded as {}", size, coded_size);
+            assert_eq!(decode_dict_size(byte), Some(coded_size));
+        }
+    }
+
+    #[test]
+    fn corrupt_streams_are_rejected() {
+        let fixture = include_bytes!("../../../tests/fixtures/lines.txt.lz");
+
+        // Damaged data, CRC, data size, and member size.
+        for offset in &[100, fixture.len() - 20, fixture.len() - 12, fixture.len() - 4] {
+            let mut bytes = fixture.to_vec();
+            bytes[*offset] ^= 0x55;
+            assert!(decompress(&bytes).is_err(), "damaged at offset {}", offset);
+        }
+
+        for len in &[HEADER_SIZE as usize - 1, 100, fixture.len() - 1] {
+            assert!(decompress(&fixture[..*len]).is_err(), "truncated to {} bytes", len);
+        }
+    }
+}
@@ -1,24 +1,88 @@
 use crate::{
     format::Format,
     input::Input,
-    util::MaybeBoxedMut,
+    output::Output,
 };
-use std::io::{Read, Result, Write};
+use std::io::{Error, Read, Result, Write};
+use structopt::StructOpt;
+
+pub use self::xz::XzCheck;
 
 mod bzip2;
 mod compress;
 mod gzip;
+mod lz4;
 mod lzip;
 mod xz;
 mod zlib;
+mod zstd;
 
 pub trait CompressionFormat: Format {
-    fn new_decoder<'r>(&self, input: Input<'r>) -> Result<Box<dyn Read + 'r>> {
-        unimplemented!()
+    /// Create a decoder that decompresses data read from the given input.
+    fn new_decoder<'r>(&self, _input: Input<'r>) -> Result<Box<dyn Read + 'r>> {
+        Err(Error::other(format!("{} compression is not supported for reading", self)))
+    }
+
+    /// Create an encoder that compresses data written to it into the given
+    /// output.
+    fn new_encoder(&self, _output: Output, _options: &CompressionOptions) -> Result<Box<dyn Encoder>> {
+        Err(Error::other(format!("{} compression is not supported for writing", self)))
     }
+}
+
+/// A stream that compresses data written to it into an underlying output.
+pub trait Encoder: Write {
+    /// Finish the compressed stream, writing any buffered data and trailers,
+    /// and return the underlying output.
+    fn finish(self: Box<Self>) -> Result<Output>;
+}
+
+// Options controlling how data is compressed when encoding. Formats ignore
+// the options that do not apply to them.
+#[derive(Debug, Default, StructOpt)]
+pub struct CompressionOptions {
+    /// Compression level to use.
+    ///
+    /// The range of levels supported depends on the compression format, and
+    /// levels outside of that range are clamped to the nearest one supported.
+    #[structopt(long, conflicts_with_all = &["fast", "best"])]
+    level: Option<u32>,
 
-    fn new_encoder<'w>(&self, writer: MaybeBoxedMut<'w, dyn Write>) -> Result<Box<dyn Write + 'w>> {
-        unimplemented!()
+    /// Compress as fast as possible, using the lowest compression level.
+    #[structopt(long, conflicts_with = "best")]
+    fast: bool,
+
+    /// Compress as well as possible, using the highest compression level.
+    #[structopt(long)]
+    best: bool,
+
+    /// Integrity check to use for xz compression: crc32, crc64, or sha256.
+    #[structopt(long, default_value = "crc64")]
+    pub xz_check: XzCheck,
+
+    /// Dictionary size to use for xz compression, in bytes.
+    ///
+    /// A KiB, MiB, or GiB suffix may be used. Larger dictionaries usually
+    /// compress better but require more memory to compress and decompress.
+    #[structopt(long, parse(try_from_str = crate::options::parse_size_u32))]
+    pub xz_dict_size: Option<u32>,
+
+    /// Use the slower "extreme" variant of the xz compression preset.
+    #[structopt(long)]
+    pub xz_extreme: bool,
+}
+
+impl CompressionOptions {
+    /// Get the compression level to use for a format that supports the given
+    /// range of levels.
+    pub fn level(&self, min: u32, max: u32, default: u32) -> u32 {
+        if self.fast {
+            min
+        } else if self.best {
+            max
+        } else {
+            self.level.map(|level| level.clamp(min, max)).unwrap_or(default)
+        }
     }
 }
 
@@ -28,8 +92,56 @@ pub fn all() -> &'static [&'static dyn CompressionFormat] {
         &bzip2::Bzip2,
         &compress::Compress,
         &gzip::Gzip,
+        &lz4::Lz4,
         &lzip::Lzip,
         &xz::Xz,
         &zlib::Zlib,
+        &zstd::Zstd,
     ]
 }
+
+/// Get the compression format with the given ID, ignoring case.
+pub fn for_id(id: &str) -> Option<&'static dyn CompressionFormat> {
+    all().iter()
+        .find(|format| format.id().eq_ignore_ascii_case(id))
+        .copied()
+}
+
+/// Get the compression format that uses the given file extension, if any.
+pub fn for_extension(extension: &str) -> Option<&'static dyn CompressionFormat> {
+    all().iter()
+        .find(|format| format.file_extensions().contains(&extension))
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(args: &[&str]) -> u32 {
+        CompressionOptions::from_iter(args).level(1, 9, 6)
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(level(&["test"]), 6);
+        assert_eq!(level(&["test", "--level", "3"]), 3);
+        assert_eq!(level(&["test", "--level", "0"]), 1);
+        assert_eq!(level(&["test", "--level", "22"]), 9);
+        assert_eq!(level(&["test", "--fast"]), 1);
+        assert_eq!(level(&["test", "--best"]), 9);
+
+        assert!(CompressionOptions::from_iter_safe(&["test", "--fast", "--best"]).is_err());
+        assert!(CompressionOptions::from_iter_safe(&["test", "--level", "3", "--best"]).is_err());
+    }
+
+    #[test]
+    fn xz_options() {
+        let options = CompressionOptions::from_iter(&["test", "--xz-check", "sha256", "--xz-dict-size", "8MiB", "--xz-extreme"]);
+
+        assert_eq!(options.xz_check, XzCheck::Sha256);
+        assert_eq!(options.xz_dict_size, Some(8 << 20));
+        assert!(options.xz_extreme);
+        assert!(CompressionOptions::from_iter_safe(&["test", "--xz-check", "md5"]).is_err());
+    }
+}
@@ -3,8 +3,16 @@
 use crate::{
     format::Format,
     input::Input,
+    output::Output,
 };
-use std::{fmt, io::{Read, Result}};
+use std::{fmt, io::{Read, Result}, str::FromStr};
+use xz2::stream::{Check, Filters, LzmaOptions, Stream};
+
+/// Default compression preset used by the `xz` command.
+const DEFAULT_PRESET: u32 = 6;
+
+/// Preset flag for using the slower "extreme" variant of a preset.
+const PRESET_EXTREME: u32 = 0x8000_0000;
 
 pub struct Xz;
 
@@ -32,4 +40,108 @@ impl super::CompressionFormat for Xz {
     fn new_decoder<'r>(&self, input: Input<'r>) -> Result<Box<dyn Read + 'r>> {
         Ok(Box::new(xz2::read::XzDecoder::new(input)))
     }
+
+    fn new_encoder(&self, output: Output, options: &super::CompressionOptions) -> Result<Box<dyn super::Encoder>> {
+        let mut preset = options.level(0, 9, DEFAULT_PRESET);
+
+        if options.xz_extreme {
+            preset |= PRESET_EXTREME;
+        }
+
+        let mut lzma_options = LzmaOptions::new_preset(preset)?;
+
+        if let Some(dict_size) = options.xz_dict_size {
+            lzma_options.dict_size(dict_size);
+        }
+
+        let stream = Stream::new_stream_encoder(
+            Filters::new().lzma2(&lzma_options),
+            options.xz_check.into(),
+        )?;
+
+        Ok(Box::new(xz2::write::XzEncoder::new_stream(output, stream)))
+    }
+}
+
+impl super::Encoder for xz2::write::XzEncoder<Output> {
+    fn finish(self: Box<Self>) -> Result<Output> {
+        xz2::write::XzEncoder::finish(*self)
+    }
+}
+
+/// Integrity check to store in xz streams.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum XzCheck {
+    Crc32,
+
+    /// Same default as the `xz` command.
+    #[default]
+    Crc64,
+
+    Sha256,
+}
+
+impl FromStr for XzCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "crc32" => Ok(XzCheck::Crc32),
+            "crc64" => Ok(XzCheck::Crc64),
+            "sha256" => Ok(XzCheck::Sha256),
+            _ => Err(format!("unknown xz check type: {}", s)),
+        }
+    }
+}
+
+impl From<XzCheck> for Check {
+    fn from(check: XzCheck) -> Self {
+        match check {
+            XzCheck::Crc32 => Check::Crc32,
+            XzCheck::Crc64 => Check::Crc64,
+            XzCheck::Sha256 => Check::Sha256,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::formats::{CompressionFormat, CompressionOptions};
+    use std::{fs, io::Write};
+    use structopt::StructOpt;
+
+    fn compress(data: &[u8], args: &[&str]) -> Vec<u8> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.xz");
+        let options = CompressionOptions::from_iter(args);
+
+        let mut encoder = Xz.new_encoder(Output::create(&path).unwrap(), &options).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap().finish().unwrap();
+
+        fs::read(path).unwrap()
+    }
+
+    fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        Xz.new_decoder(Input::from_reader(data)?)?.read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+
+    #[test]
+    fn check_types() {
+        // The check ID is stored in the second byte of the stream flags.
+        for (args, check_id) in &[
+            (&["test"][..], 0x04),
+            (&["test", "--xz-check", "crc32"][..], 0x01),
+            (&["test", "--xz-check", "crc64"][..], 0x04),
+            (&["test", "--xz-check", "sha256", "--xz-dict-size", "1MiB", "--xz-extreme"][..], 0x0a),
+        ] {
+            let compressed = compress(b"hello\n", args);
+
+            assert_eq!(compressed[7], *check_id, "{:?}", args);
+            assert_eq!(decompress(&compressed).unwrap(), b"hello\n");
+        }
+    }
 }
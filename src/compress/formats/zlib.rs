@@ -5,6 +5,7 @@
 use crate::{
     format::Format,
     input::Input,
+    output::Output,
 };
 use std::{fmt, io::{Read, Result}};
 
@@ -34,4 +35,42 @@ impl super::CompressionFormat for Zlib {
     fn new_decoder<'r>(&self, input: Input<'r>) -> Result<Box<dyn Read + 'r>> {
         Ok(Box::new(flate2::read::ZlibDecoder::new(input)))
     }
+
+    fn new_encoder(&self, output: Output, options: &super::CompressionOptions) -> Result<Box<dyn super::Encoder>> {
+        let level = flate2::Compression::new(options.level(1, 9, 6));
+
+        Ok(Box::new(flate2::write::ZlibEncoder::new(output, level)))
+    }
+}
+
+impl super::Encoder for flate2::write::ZlibEncoder<Output> {
+    fn finish(self: Box<Self>) -> Result<Output> {
+        flate2::write::ZlibEncoder::finish(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::formats::{CompressionFormat, CompressionOptions};
+    use std::{fs, io::Write};
+    use structopt::StructOpt;
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.zz");
+        let options = CompressionOptions::from_iter(&["test", "--best"]);
+
+        let mut encoder = Zlib.new_encoder(Output::create(&path).unwrap(), &options).unwrap();
+        encoder.write_all(b"hello\n").unwrap();
+        encoder.finish().unwrap().finish().unwrap();
+
+        let compressed = fs::read(&path).unwrap();
+        let mut decoded = Vec::new();
+        Zlib.new_decoder(Input::from_reader(&compressed[..]).unwrap()).unwrap().read_to_end(&mut decoded).unwrap();
+
+        assert!(Zlib.match_bytes(&compressed));
+        assert_eq!(decoded, b"hello\n");
+    }
 }
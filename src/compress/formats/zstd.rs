@@ -0,0 +1,128 @@
+//! The Zstandard compression format as defined in [RFC 8878].
+//!
+//! [RFC 8878]: https://tools.ietf.org/html/rfc8878
+
+use crate::{
+    format::Format,
+    input::Input,
+    output::Output,
+};
+use std::{fmt, io::{Read, Result}};
+
+/// Highest compression level supported without enabling long-distance "ultra"
+/// levels, which need much more memory to decompress.
+const MAX_LEVEL: u32 = 19;
+
+pub struct Zstd;
+
+impl Format for Zstd {
+    fn id(&self) -> &str {
+        "zstd"
+    }
+
+    fn file_extensions(&self) -> &[&str] {
+        &["zst"]
+    }
+
+    fn match_bytes(&self, bytes: &[u8]) -> bool {
+        match bytes {
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => true,
+
+            // Skippable frames have magic numbers 0x184D2A50 to 0x184D2A5F and
+            // may appear before the first regular frame.
+            [low, 0x2a, 0x4d, 0x18, ..] => low & 0xf0 == 0x50,
+
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Zstd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Zstandard")
+    }
+}
+
+impl super::CompressionFormat for Zstd {
+    fn new_decoder<'r>(&self, input: Input<'r>) -> Result<Box<dyn Read + 'r>> {
+        // The decoder continues on to any subsequent frames by default.
+        Ok(Box::new(zstd::stream::read::Decoder::with_buffer(input)?))
+    }
+
+    fn new_encoder(&self, output: Output, options: &super::CompressionOptions) -> Result<Box<dyn super::Encoder>> {
+        let level = options.level(1, MAX_LEVEL, zstd::DEFAULT_COMPRESSION_LEVEL as u32);
+
+        let mut encoder = zstd::stream::write::Encoder::new(output, level as i32)?;
+
+        // Record a checksum of the data in each frame so that corruption is
+        // detected when decoding, as with the other formats.
+        encoder.include_checksum(true)?;
+
+        Ok(Box::new(encoder))
+    }
+}
+
+impl super::Encoder for zstd::stream::write::Encoder<'static, Output> {
+    fn finish(self: Box<Self>) -> Result<Output> {
+        zstd::stream::write::Encoder::finish(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::formats::{CompressionFormat, CompressionOptions};
+    use std::{fs, io::Write};
+    use structopt::StructOpt;
+
+    fn lines() -> Vec<u8> {
+        (0..2000)
+            .map(|i| format!("line {}: the quick brown fox jumps over {} lazy dogs\n", i, i * i % 97))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    fn compress(data: &[u8], args: &[&str]) -> Vec<u8> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.zst");
+        let options = CompressionOptions::from_iter(args);
+
+        let mut encoder = Zstd.new_encoder(Output::create(&path).unwrap(), &options).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap().finish().unwrap();
+
+        fs::read(path).unwrap()
+    }
+
+    fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        Zstd.new_decoder(Input::from_reader(data)?)?.read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+
+    #[test]
+    fn round_trip() {
+        for args in &[&["test", "--fast"][..], &["test"][..], &["test", "--best"][..]] {
+            let compressed = compress(&lines(), args);
+
+            // The frame header descriptor flags a content checksum.
+            assert_ne!(compressed[4] & 0x04, 0);
+            assert_eq!(decompress(&compressed).unwrap(), lines());
+            assert_eq!(decompress(&compress(&[], args)).unwrap(), b"");
+        }
+    }
+
+    #[test]
+    fn corrupt_streams_are_rejected() {
+        let compressed = compress(&lines(), &["test"]);
+
+        // Damaged data and checksum.
+        for offset in &[compressed.len() / 2, compressed.len() - 1] {
+            let mut bytes = compressed.clone();
+            bytes[*offset] ^= 0x55;
+            assert!(decompress(&bytes).is_err(), "damaged at offset {}", offset);
+        }
+
+        assert!(decompress(&compressed[..compressed.len() - 1]).is_err());
+    }
+}
@@ -4,16 +4,20 @@
 //! not supported by the archive format itself.
 
 use crate::io::input::Input;
+use self::formats::CompressionFormat;
 use std::io::{BufRead, Result};
 
 pub mod formats;
 
 /// Decode the given input stream automatically (if required), returning a new
-/// deooded stream.
+/// decoded stream along with the compression formats that were detected,
+/// outermost first.
 ///
 /// This function will attempt to detect if multiple layers of compression
 /// algorithms are being used and decode them automatically.
-pub fn detect_decode<'r>(mut input: Input<'r>) -> Result<Input<'r>> {
+pub fn detect_decode<'r>(mut input: Input<'r>) -> Result<(Input<'r>, Vec<&'static dyn CompressionFormat>)> {
+    let mut detected = Vec::new();
+
     'format: loop {
         let buf = input.fill_buf()?;
 
@@ -24,12 +28,24 @@ pub fn detect_decode<'r>(mut input: Input<'r>) -> Result<Input<'r>> {
 
                 // Wrap the input in a decoder.
                 input = Input::from_reader(format.new_decoder(input)?)?;
+                detected.push(*format);
 
                 continue 'format;
             }
         }
 
         // No more compression formats detected.
-        return Ok(input);
+        return Ok((input, detected));
     }
 }
+
+/// Decode the given input stream using the given compression formats, outermost
+/// first, without attempting any detection.
+pub fn decode<'r>(mut input: Input<'r>, formats: &[&'static dyn CompressionFormat]) -> Result<Input<'r>> {
+    for format in formats {
+        log::debug!("decoding {} compression", format.id());
+        input = Input::from_reader(format.new_decoder(input)?)?;
+    }
+
+    Ok(input)
+}
@@ -1,12 +1,15 @@
 use crate::{
-    archive,
+    archive::{self, formats::ArchiveOptions, EntryType},
+    compress::formats::CompressionOptions,
+    format::FormatOptions,
     io::output::Output,
 };
 use indicatif::ProgressBar;
 use std::{
+    collections::HashMap,
     error::Error,
-    fs::File,
-    io::BufReader,
+    fs::{self, File},
+    io::{self, BufReader},
     path::PathBuf,
 };
 use structopt::StructOpt;
@@ -29,7 +32,25 @@ pub struct Command {
     #[structopt(long)]
     preserve_symlinks: bool,
 
-    /// Archive file ("-" for stdin)
+    /// If set, files with multiple hard links will be stored in the archive
+    /// once, with any other paths linking to them stored as hard links.
+    ///
+    /// Not all formats support hard link entries. Setting this flag when
+    /// trying to create an archive in a format that does not support it will
+    /// produce an error if any hard links are found.
+    #[structopt(long)]
+    preserve_hardlinks: bool,
+
+    #[structopt(flatten)]
+    formats: FormatOptions,
+
+    #[structopt(flatten)]
+    options: ArchiveOptions,
+
+    #[structopt(flatten)]
+    compression_options: CompressionOptions,
+
+    /// Archive file ("-" for stdout)
     #[structopt(parse(from_os_str))]
     output: PathBuf,
 
@@ -40,28 +61,48 @@ pub struct Command {
 
 impl Command {
     pub fn execute(&self) -> Result<(), Box<dyn Error>> {
-        let mut output = Output::create(&self.output)?;
+        let format = self.formats.archive_format()?;
+        let compression = self.formats.compression_formats()?;
+        let output = Output::create(&self.output)?;
 
-        if let Some(mut writer) = archive::create(&mut output)? {
-            let entries = collect_paths(&self.files)?;
+        if let Some(mut writer) = archive::create(output, format, compression, &self.options, &self.compression_options)? {
+            let entries = collect_paths(&self.files, self.preserve_symlinks)?;
 
             let progress_bar = ProgressBar::new(entries.len() as u64)
                 .with_style(super::progress_bar_style());
             progress_bar.enable_steady_tick(1000);
 
+            // Paths of files added so far that have multiple hard links, keyed
+            // by their device and inode numbers.
+            let mut hard_links = HashMap::new();
+
             for entry in entries {
                 let path = entry.path();
 
                 progress_bar.set_message(&path.to_string_lossy());
 
-                let metadata = entry.metadata()
+                let metadata: archive::Metadata = entry.metadata()
                     .map(Into::into)
                     .unwrap_or_default();
 
+                let hard_link_target = if self.preserve_hardlinks {
+                    hard_link_target(&entry, &mut hard_links)
+                } else {
+                    None
+                };
+
                 if entry.file_type().is_dir() {
                     writer.add_directory(path, metadata)?;
-                } else {
+                } else if entry.path_is_symlink() && self.preserve_symlinks {
+                    writer.add_symlink(path, metadata, &fs::read_link(path)?)?;
+                } else if let Some(target) = hard_link_target {
+                    writer.add_hard_link(path, metadata, &target)?;
+                } else if matches!(metadata.entry_type, EntryType::File) {
                     writer.add_file(path, metadata, &mut BufReader::new(File::open(path)?))?;
+                } else {
+                    // Special files such as devices have no data, and opening
+                    // them may block or have side effects.
+                    writer.add_file(path, metadata, &mut io::empty())?;
                 }
 
                 progress_bar.inc(1);
@@ -71,16 +112,42 @@ impl Command {
 
             progress_bar.finish_and_clear();
         } else {
-            eprintln!("Unrecognized file extension, specify format manually");
+            eprintln!("Unrecognized file extension, specify a format with --format");
         }
 
         Ok(())
     }
 }
 
-fn collect_paths(paths: &[PathBuf]) -> Result<Vec<walkdir::DirEntry>, Box<dyn Error>> {
+fn collect_paths(paths: &[PathBuf], preserve_symlinks: bool) -> Result<Vec<walkdir::DirEntry>, Box<dyn Error>> {
     paths.iter()
-        .flat_map(|path| WalkDir::new(path).follow_links(true))
+        .flat_map(|path| WalkDir::new(path).follow_links(!preserve_symlinks))
         .map(|result| result.map_err(|e| Box::new(e) as Box<dyn std::error::Error>))
         .collect()
 }
+
+/// If the given file has multiple hard links and another path to it was seen
+/// before, get that path. Otherwise the file is remembered for later paths.
+#[cfg(unix)]
+fn hard_link_target(entry: &walkdir::DirEntry, seen: &mut HashMap<(u64, u64), PathBuf>) -> Option<PathBuf> {
+    use std::{collections::hash_map, os::unix::fs::MetadataExt};
+
+    let metadata = entry.metadata().ok()?;
+
+    if metadata.is_dir() || metadata.nlink() < 2 {
+        return None;
+    }
+
+    match seen.entry((metadata.dev(), metadata.ino())) {
+        hash_map::Entry::Occupied(entry) => Some(entry.get().clone()),
+        hash_map::Entry::Vacant(vacant) => {
+            vacant.insert(entry.path().to_owned());
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn hard_link_target(_entry: &walkdir::DirEntry, _seen: &mut HashMap<(u64, u64), PathBuf>) -> Option<PathBuf> {
+    None
+}
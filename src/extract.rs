@@ -1,15 +1,18 @@
 use crate::{
     archive,
-    archive::{Entry, EntryType},
+    archive::{Entry, EntryType, Metadata},
+    format::FormatOptions,
     io::input::Input,
+    paths,
 };
+use filetime::FileTime;
 use glob::Pattern;
 use indicatif::ProgressBar;
 use std::{
     borrow::Cow,
     error::Error,
     fs,
-    fs::{File, OpenOptions},
+    fs::OpenOptions,
     io,
     path::{Path, PathBuf},
 };
@@ -40,15 +43,28 @@ pub struct Command {
     #[structopt(long)]
     ignore_permissions: bool,
 
-    /// Restore SUID/SGID bits.
+    /// Restore SUID/SGID and sticky bits.
     #[structopt(long)]
     preserve_extra_bits: bool,
 
+    /// Allow extracting entries with paths that point outside of the
+    /// destination directory.
+    ///
+    /// By default, entries with absolute paths are extracted relative to the
+    /// destination directory, and entries containing `..` components or paths
+    /// through symbolic links are rejected. Only use this flag with archives
+    /// you trust.
+    #[structopt(long)]
+    allow_unsafe_paths: bool,
+
     /// Extract slowly for testing purposes.
     #[cfg(debug_assertions)]
     #[structopt(long)]
     go_slow: bool,
 
+    #[structopt(flatten)]
+    formats: FormatOptions,
+
     /// Input file ("-" for stdin)
     #[structopt(parse(from_os_str))]
     input: PathBuf,
@@ -68,6 +84,8 @@ pub struct Command {
 
 impl Command {
     pub fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let format = self.formats.archive_format()?;
+        let compression = self.formats.compression_formats()?;
         let input = Input::open(&self.input)?;
 
         let dest = match &self.dest {
@@ -88,7 +106,7 @@ impl Command {
             },
         };
 
-        if let Some(mut reader) = archive::open(input)? {
+        if let Some(mut reader) = archive::open(input, format, compression.as_deref())? {
             // Ensure the target directory is created if it does not already
             // exist.
             fs::create_dir_all(dest.as_ref())?;
@@ -98,6 +116,17 @@ impl Command {
                 None => ProgressBar::new_spinner(),
             };
 
+            // Metadata for directories is restored after all entries have been
+            // extracted, since writing files into a directory would otherwise
+            // change its modified time (or fail if it is read-only).
+            let mut directories = Vec::new();
+
+            // Symbolic links are created after all other entries, so that
+            // their targets are checked against the final tree. A link created
+            // earlier could otherwise have its target redefined by a later
+            // entry to point outside of the destination.
+            let mut symlinks = Vec::new();
+
             while let Some(mut entry) = reader.entry()? {
                 let path = entry.path();
 
@@ -107,16 +136,32 @@ impl Command {
                     #[cfg(debug_assertions)]
                     {
                         if self.go_slow {
-                            std::thread::sleep_ms(1000);
+                            std::thread::sleep(std::time::Duration::from_secs(1));
                         }
                     }
 
-                    self.extract(&mut *entry, &dest)?;
+                    match self.extract(&mut *entry, &dest)? {
+                        Some(Deferred::Directory(path, metadata)) => directories.push((path, metadata)),
+                        Some(Deferred::SymbolicLink(link, target)) => symlinks.push((link, target)),
+                        None => {}
+                    }
                 }
 
                 progress_bar.inc(1);
             }
 
+            for (link, target) in symlinks {
+                self.create_symlink(&dest, &link, &target)?;
+            }
+
+            // Restore the deepest directories first so that restoring a parent
+            // is never affected by changes to its children.
+            directories.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+            for (path, metadata) in directories {
+                self.restore_metadata(&path, &metadata)?;
+            }
+
             progress_bar.finish_and_clear();
         } else {
             eprintln!("Unknown format");
@@ -129,36 +174,23 @@ impl Command {
         if self.files.is_empty() {
             true
         } else {
-            self.files.iter().any(|pattern| {
-                // If a slash occurs anywhere other than the end of the pattern,
-                // match against the whole path. Otherwise match against just
-                // the name.
-                let first_slash = pattern.as_str().find('/');
-
-                if first_slash == None || first_slash == Some(pattern.as_str().len() - 1) {
-                    match path.file_name() {
-                        Some(file_name) => pattern.matches_path(Path::new(file_name)),
-                        None => {
-                            log::debug!("couldn't match path against pattern, no filename: {}", path.display());
-                            false
-                        },
-                    }
-                } else {
-                    pattern.matches_path(path)
-                }
-            })
+            self.files.iter().any(|pattern| paths::matches_pattern(pattern, path))
         }
     }
 
     /// Extract an entry into the file system within the given path.
     ///
     /// The entire path of this entry within the archive will be recreated in
-    /// the destination path.
-    fn extract(&self, entry: &mut dyn Entry, dir: &Path) -> Result<(), Box<dyn Error>> {
-        let path = entry.path();
-        let dest = dir.join(&path);
-
-        // TODO: What if path points to a directory above the archive? (security)
+    /// the destination path. Directories and symbolic links are not finished
+    /// here, but returned to be finished once all other entries have been
+    /// extracted.
+    fn extract(&self, entry: &mut dyn Entry, dir: &Path) -> Result<Option<Deferred>, Box<dyn Error>> {
+        let path = entry.path().into_owned();
+        let dest = if self.allow_unsafe_paths {
+            dir.join(&path)
+        } else {
+            paths::resolve_extract_path(dir, &path)?
+        };
 
         // Create parent directories if required.
         if let Some(parent) = dest.parent() {
@@ -168,24 +200,408 @@ impl Command {
         let metadata = entry.metadata();
 
         match metadata.entry_type {
-            EntryType::Directory => fs::create_dir_all(dest)?,
+            EntryType::Directory => {
+                // Replace any symbolic link in the way, rather than creating
+                // the directory (and later restoring its metadata) wherever the
+                // link points.
+                if paths::is_symlink(&dest) {
+                    if self.keep_old_files {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!("{} already exists as a symbolic link", dest.display()),
+                        ).into());
+                    }
+
+                    fs::remove_file(&dest)?;
+                }
+
+                fs::create_dir_all(&dest)?;
+
+                return Ok(Some(Deferred::Directory(dest, metadata)));
+            }
 
             EntryType::File => {
+                self.remove_existing(&dest)?;
+
                 // Create the file and stream this entry's bytes into it.
                 let mut file = OpenOptions::new()
                     .create(true)
                     .create_new(self.keep_old_files)
                     .write(true)
                     .truncate(true)
-                    .open(dest)?;
+                    .open(&dest)?;
 
                 io::copy(entry, &mut file)?;
+                drop(file);
+
+                self.restore_metadata(&dest, &metadata)?;
+            }
+
+            EntryType::SymbolicLink => {
+                let target = match entry.read_link()? {
+                    Some(target) => target.into_owned(),
+                    None => {
+                        log::warn!("skipping entry {}, symbolic link has no target", path.display());
+                        return Ok(None);
+                    }
+                };
+
+                return Ok(Some(Deferred::SymbolicLink(dest, target)));
             }
+
+            EntryType::HardLink => {
+                let target = match entry.read_link()? {
+                    Some(target) => target.into_owned(),
+                    None => {
+                        log::warn!("skipping entry {}, hard link has no target", path.display());
+                        return Ok(None);
+                    }
+                };
+
+                // Link targets are paths within the archive, so they must be
+                // resolved the same way as entry paths.
+                let target = if self.allow_unsafe_paths {
+                    dir.join(&target)
+                } else {
+                    let target = paths::resolve_extract_path(dir, &target)?;
+                    paths::check_hard_link_target(&dest, &target)?;
+                    target
+                };
+
+                self.remove_existing(&dest)?;
+                fs::hard_link(&target, &dest).map_err(|e| io::Error::new(
+                    e.kind(),
+                    format!("cannot create hard link {} to {}: {}", path.display(), target.display(), e),
+                ))?;
+
+                // Some formats store the data for a set of hard linked files
+                // with the last link instead of the first. Never write through
+                // a symbolic link here, since the link could point anywhere.
+                if metadata.size > 0 {
+                    let mut file = open_no_follow(&dest)?;
+
+                    io::copy(entry, &mut file)?;
+                    drop(file);
+
+                    self.restore_metadata(&dest, &metadata)?;
+                }
+            }
+
+            EntryType::CharacterDevice | EntryType::BlockDevice | EntryType::Fifo => {
+                self.remove_existing(&dest)?;
+
+                match create_special_file(&dest, &metadata) {
+                    Ok(()) => self.restore_metadata(&dest, &metadata)?,
+
+                    // Creating device files usually requires elevated
+                    // privileges, so skip them if we don't have them.
+                    Err(e) if matches!(e.kind(), io::ErrorKind::PermissionDenied | io::ErrorKind::Unsupported) => {
+                        log::warn!("skipping entry {}, cannot create special file: {}", path.display(), e);
+                    }
+
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
             _ => {
                 log::warn!("skipping entry {}, unsupported type", path.display())
             }
         }
 
+        Ok(None)
+    }
+
+    /// Create a symbolic link extracted to `link` inside of the destination
+    /// directory `dir`, once all other entries have been extracted.
+    fn create_symlink(&self, dir: &Path, link: &Path, target: &Path) -> io::Result<()> {
+        if !self.allow_unsafe_paths {
+            paths::check_symlink_target(dir, link, target)?;
+        }
+
+        self.remove_existing(link)?;
+        create_symlink(target, link)
+    }
+
+    /// If a file, link, or special file already exists at the given path,
+    /// remove it so that the new entry replaces it instead of writing through
+    /// it.
+    fn remove_existing(&self, path: &Path) -> io::Result<()> {
+        if !self.keep_old_files {
+            if let Ok(metadata) = fs::symlink_metadata(path) {
+                if !metadata.is_dir() {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+
         Ok(())
     }
+
+    /// Apply the timestamps and permissions of an entry to an extracted file,
+    /// unless disabled.
+    fn restore_metadata(&self, path: &Path, metadata: &Metadata) -> io::Result<()> {
+        // Timestamps must be set first, since some platforms do not allow
+        // modifying read-only files.
+        //
+        // Use the variant that doesn't follow symbolic links, since it also
+        // avoids opening the file, which would block on named pipes.
+        if !self.ignore_timestamp {
+            if let Some(modified) = metadata.modified {
+                let accessed = metadata.accessed
+                    .map(|accessed| FileTime::from_system_time(accessed.into()))
+                    .unwrap_or_else(FileTime::now);
+
+                filetime::set_symlink_file_times(path, accessed, FileTime::from_system_time(modified.into()))?;
+            }
+        }
+
+        // Changing permissions follows symbolic links, and links don't have
+        // permissions of their own anyway.
+        if !self.ignore_permissions && !paths::is_symlink(path) {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+
+                if let Some(mode) = metadata.unix_mode {
+                    // Only restore SUID, SGID, and sticky bits if requested.
+                    let mask = if self.preserve_extra_bits {
+                        0o7777
+                    } else {
+                        0o0777
+                    };
+
+                    return fs::set_permissions(path, fs::Permissions::from_mode(mode & mask));
+                }
+            }
+
+            if metadata.read_only {
+                let mut permissions = fs::metadata(path)?.permissions();
+                permissions.set_readonly(true);
+                fs::set_permissions(path, permissions)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Extracted entries that are finished after all other entries.
+enum Deferred {
+    /// A directory whose metadata is yet to be restored.
+    Directory(PathBuf, Metadata),
+
+    /// A symbolic link to be created, along with its target.
+    SymbolicLink(PathBuf, PathBuf),
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    // Windows distinguishes between links to files and links to directories,
+    // so check what the target is, if it exists.
+    let target_is_dir = link.parent()
+        .map(|parent| parent.join(target).is_dir())
+        .unwrap_or(false);
+
+    if target_is_dir {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+/// Open an existing file for writing, failing if it is a symbolic link.
+#[cfg(unix)]
+fn open_no_follow(path: &Path) -> io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn open_no_follow(path: &Path) -> io::Result<fs::File> {
+    if paths::is_symlink(path) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is a symbolic link", path.display())));
+    }
+
+    OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(path)
+}
+
+#[cfg(unix)]
+fn create_special_file(path: &Path, metadata: &Metadata) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let file_type = match metadata.entry_type {
+        EntryType::CharacterDevice => libc::S_IFCHR,
+        EntryType::BlockDevice => libc::S_IFBLK,
+        _ => libc::S_IFIFO,
+    };
+    let permissions = metadata.unix_mode.unwrap_or(0o644) & 0o777;
+    let (major, minor) = metadata.device.unwrap_or((0, 0));
+    let path = CString::new(path.as_os_str().as_bytes())?;
+
+    let result = unsafe {
+        libc::mknod(
+            path.as_ptr(),
+            file_type | permissions as libc::mode_t,
+            libc::makedev(major as _, minor as _),
+        )
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn create_special_file(_path: &Path, _metadata: &Metadata) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "special files are not supported on this platform"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use tar::EntryType::*;
+
+    /// Write a tar archive containing the given links and directories, in
+    /// order. Entries without a target are directories.
+    fn write_tar(path: &Path, entries: &[(&str, tar::EntryType, Option<&str>)]) {
+        let mut builder = tar::Builder::new(fs::File::create(path).unwrap());
+
+        for (name, entry_type, target) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_path(name).unwrap();
+            header.set_mode(0o755);
+            header.set_entry_type(*entry_type);
+
+            if let Some(target) = target {
+                header.set_link_name_literal(target).unwrap();
+            }
+
+            header.set_size(0);
+            header.set_cksum();
+            builder.append(&header, io::empty()).unwrap();
+        }
+
+        builder.finish().unwrap();
+    }
+
+    fn extract(archive: &Path, dest: &Path) -> Result<(), Box<dyn Error>> {
+        extract_with_flags(archive, dest, &[])
+    }
+
+    fn extract_with_flags(archive: &Path, dest: &Path, flags: &[&str]) -> Result<(), Box<dyn Error>> {
+        let mut args = vec!["extract".as_ref(), "-d".as_ref(), dest.as_os_str()];
+        args.extend(flags.iter().map(OsStr::new));
+        args.push(archive.as_os_str());
+
+        Command::from_iter(&args).execute()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_redefined_by_later_symlink_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("links.tar");
+
+        // Counting components, `a` points to the destination. Once `b` links
+        // to the destination as well, `b/b/..` is the parent of it.
+        for entries in &[
+            [("a", Symlink, Some("b/b/../..")), ("b", Symlink, Some("."))],
+            [("b", Symlink, Some(".")), ("a", Symlink, Some("b/b/../.."))],
+        ] {
+            let dest = dir.path().join("o/dest");
+            write_tar(&archive, entries);
+
+            assert!(extract(&archive, &dest).is_err());
+            assert!(fs::symlink_metadata(dest.join("a")).is_err());
+            fs::remove_dir_all(dir.path().join("o")).unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_created_after_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("links.tar");
+        let dest = dir.path().join("dest");
+
+        // The link steps out of a directory that comes later in the archive.
+        write_tar(&archive, &[("link", Symlink, Some("sub/../target")), ("sub", Directory, None)]);
+        extract(&archive, &dest).unwrap();
+
+        assert_eq!(fs::read_link(dest.join("link")).unwrap(), Path::new("sub/../target"));
+        assert!(dest.join("sub").is_dir());
+    }
+
+    #[test]
+    fn hard_link_errors_name_the_link_and_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("links.tar");
+
+        write_tar(&archive, &[("link", Link, Some("missing"))]);
+        let error = extract(&archive, &dir.path().join("dest")).unwrap_err().to_string();
+
+        assert!(error.contains("link") && error.contains("missing"), "unexpected error: {}", error);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timestamps_and_permissions_are_restored() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("metadata.tar");
+        let mut builder = tar::Builder::new(fs::File::create(&archive).unwrap());
+
+        // The directory comes first, so its time must be restored after the
+        // file inside it is written.
+        for (name, entry_type, mode, data) in &[
+            ("dir", Directory, 0o750, &b""[..]),
+            ("dir/run", Regular, 0o4755, &b"#!/bin/sh\n"[..]),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(*entry_type);
+            header.set_mode(*mode);
+            header.set_mtime(1622548800);
+            header.set_size(data.len() as u64);
+            builder.append_data(&mut header, name, *data).unwrap();
+        }
+
+        builder.into_inner().unwrap();
+
+        let metadata = |dest: &Path, name| {
+            let metadata = fs::metadata(dest.join(name)).unwrap();
+            (metadata.permissions().mode() & 0o7777, FileTime::from_last_modification_time(&metadata).unix_seconds())
+        };
+
+        let dest = dir.path().join("default");
+        extract(&archive, &dest).unwrap();
+        assert_eq!(metadata(&dest, "dir"), (0o750, 1622548800));
+        assert_eq!(metadata(&dest, "dir/run"), (0o755, 1622548800));
+
+        let dest = dir.path().join("extra-bits");
+        extract_with_flags(&archive, &dest, &["--preserve-extra-bits"]).unwrap();
+        assert_eq!(metadata(&dest, "dir/run"), (0o4755, 1622548800));
+
+        let dest = dir.path().join("ignored");
+        extract_with_flags(&archive, &dest, &["--ignore-timestamp", "--ignore-permissions"]).unwrap();
+        let (mode, modified) = metadata(&dest, "dir/run");
+        assert_ne!(modified, 1622548800);
+        assert_eq!(mode & 0o4000, 0);
+    }
 }
@@ -1,4 +1,12 @@
-use std::fmt::Display;
+use crate::{
+    archive::{self, ArchiveFormat},
+    compress::{self, formats::CompressionFormat},
+};
+use std::{
+    fmt::Display,
+    io::{Error, ErrorKind, Result},
+};
+use structopt::StructOpt;
 
 /// A definition for a file format.
 pub trait Format: Display {
@@ -17,3 +25,52 @@ pub trait Format: Display {
     /// format's magic signatures.
     fn match_bytes(&self, bytes: &[u8]) -> bool;
 }
+
+// Command line options for choosing formats explicitly instead of detecting
+// them automatically.
+//
+// This is not a doc comment, since structopt would use it as the description
+// of every command that flattens these options in.
+#[derive(Debug, StructOpt)]
+pub struct FormatOptions {
+    /// Archive format to use instead of detecting it.
+    ///
+    /// Run `naru formats` to see the IDs of all supported formats.
+    #[structopt(long)]
+    format: Option<String>,
+
+    /// Compression formats to use instead of detecting them.
+    ///
+    /// Multiple formats can be given separated by commas, starting with the
+    /// outermost compression layer. Use "none" for no compression.
+    #[structopt(long, use_delimiter = true, number_of_values = 1)]
+    compression: Option<Vec<String>>,
+}
+
+impl FormatOptions {
+    /// Get the ID of the archive format given, without looking it up.
+    pub fn format_id(&self) -> Option<&str> {
+        self.format.as_deref()
+    }
+
+    /// Get the archive format chosen, if any.
+    pub fn archive_format(&self) -> Result<Option<&'static dyn ArchiveFormat>> {
+        self.format.as_deref()
+            .map(|id| archive::formats::for_id(id).ok_or_else(|| unknown_format_error("archive", id)))
+            .transpose()
+    }
+
+    /// Get the compression formats chosen, if any.
+    pub fn compression_formats(&self) -> Result<Option<Vec<&'static dyn CompressionFormat>>> {
+        self.compression.as_ref()
+            .map(|ids| ids.iter()
+                .filter(|id| !id.eq_ignore_ascii_case("none"))
+                .map(|id| compress::formats::for_id(id).ok_or_else(|| unknown_format_error("compression", id)))
+                .collect())
+            .transpose()
+    }
+}
+
+fn unknown_format_error(kind: &str, id: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("unknown {} format: {}", kind, id))
+}
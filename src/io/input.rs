@@ -9,16 +9,27 @@ use std::{
     path::{Path, PathBuf},
 };
 
+/// Size of the read buffer, which is also the number of bytes available to
+/// format detection. This is large enough to include the volume descriptors
+/// of ISO 9660 images, which begin 32 KiB into the image.
+const BUFFER_SIZE: usize = 64 * 1024;
+
 /// An input stream that might be seekable and might have a file path.
 ///
 /// This type is used to abstract over multiple kinds of file sources.
 pub struct Input<'r>(Inner<'r>);
 
 enum Inner<'r> {
-    File(BufReader<File>),
+    File(BufReader<File>, Option<PathBuf>),
     Other(BufReader<DiskCacheReader<Box<dyn Read + 'r>>>),
+    Borrowed(Box<dyn Stream + 'r>),
 }
 
+/// A buffered, seekable stream that an input can borrow from.
+trait Stream: BufRead + Seek {}
+
+impl<T: BufRead + Seek> Stream for T {}
+
 impl<'r> Input<'r> {
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
         let path = path.as_ref();
@@ -26,18 +37,18 @@ impl<'r> Input<'r> {
         if path.to_str() == Some("-") {
             Self::stdin()
         } else {
-            Ok(Self::from_file(File::open(path)?))
+            Ok(Self(Inner::File(BufReader::with_capacity(BUFFER_SIZE, File::open(path)?), Some(path.to_owned()))))
         }
     }
 
     pub fn from_file(file: File) -> Self {
-        Self(Inner::File(BufReader::new(file)))
+        Self(Inner::File(BufReader::with_capacity(BUFFER_SIZE, file), None))
     }
 
     pub fn from_reader(reader: impl Read + 'r) -> Result<Self> {
         let reader: Box<dyn Read + 'r> = Box::new(reader);
 
-        Ok(Self(Inner::Other(BufReader::new(DiskCacheReader::new(reader)?))))
+        Ok(Self(Inner::Other(BufReader::with_capacity(BUFFER_SIZE, DiskCacheReader::new(reader)?))))
     }
 
     pub fn stdin() -> Result<Self> {
@@ -54,13 +65,34 @@ impl<'r> Input<'r> {
         }
     }
 
+    /// Borrow this input as another input, so that whatever the borrowing
+    /// input leaves unread can still be read from this one afterwards.
+    pub fn by_ref(&mut self) -> Input<'_> {
+        Input(Inner::Borrowed(Box::new(self)))
+    }
+
+    /// Get the path of the file this input reads from, if it was opened from
+    /// one. Formats that span multiple files use this to locate the others.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.0 {
+            Inner::File(_, path) => path.as_deref(),
+            _ => None,
+        }
+    }
+
     /// Make this input available as a file on disk. This is used when a reader
     /// implementation either requires the ability to seek or that the input is
     /// on disk.
-    pub fn into_file(mut self) -> Result<File> {
+    pub fn into_file(self) -> Result<File> {
         match self.0 {
-            // Already a file
-            Inner::File(file) => Ok(file.into_inner()),
+            // Already a file, but discarding the read buffer might have moved
+            // the cursor ahead of where the reader was.
+            Inner::File(mut file, _) => {
+                let position = file.stream_position()?;
+                let mut file = file.into_inner();
+                file.seek(SeekFrom::Start(position))?;
+                Ok(file)
+            }
 
             // Allocate a temporary file and drain the reader into it
             Inner::Other(mut reader) => {
@@ -69,6 +101,12 @@ impl<'r> Input<'r> {
                 file.seek(SeekFrom::Start(0))?;
                 Ok(file)
             }
+            Inner::Borrowed(mut reader) => {
+                let mut file = tempfile::tempfile()?;
+                io::copy(&mut reader, &mut file)?;
+                file.seek(SeekFrom::Start(0))?;
+                Ok(file)
+            }
         }
     }
 }
@@ -84,15 +122,17 @@ impl TryFrom<Input<'_>> for File {
 impl BufRead for Input<'_> {
     fn fill_buf(&mut self) -> Result<&[u8]> {
         match &mut self.0 {
-            Inner::File(file) => file.fill_buf(),
+            Inner::File(file, _) => file.fill_buf(),
             Inner::Other(reader) => reader.fill_buf(),
+            Inner::Borrowed(reader) => reader.fill_buf(),
         }
     }
 
     fn consume(&mut self, amt: usize) {
         match &mut self.0 {
-            Inner::File(file) => file.consume(amt),
+            Inner::File(file, _) => file.consume(amt),
             Inner::Other(reader) => reader.consume(amt),
+            Inner::Borrowed(reader) => reader.consume(amt),
         }
     }
 }
@@ -100,8 +140,9 @@ impl BufRead for Input<'_> {
 impl Read for Input<'_> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         match &mut self.0 {
-            Inner::File(file) => file.read(buf),
+            Inner::File(file, _) => file.read(buf),
             Inner::Other(reader) => reader.read(buf),
+            Inner::Borrowed(reader) => reader.read(buf),
         }
     }
 }
@@ -109,8 +150,9 @@ impl Read for Input<'_> {
 impl Seek for Input<'_> {
     fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
         match &mut self.0 {
-            Inner::File(file) => file.seek(pos),
+            Inner::File(file, _) => file.seek(pos),
             Inner::Other(reader) => reader.seek(pos),
+            Inner::Borrowed(reader) => reader.seek(pos),
         }
     }
 }
@@ -1,6 +1,6 @@
 use std::{
     fs::File,
-    io::Result,
+    io::{ErrorKind, Read, Result},
     mem::ManuallyDrop,
 };
 
@@ -8,6 +8,23 @@ pub mod buffers;
 pub mod input;
 pub mod output;
 
+/// Read enough bytes to fill the buffer, stopping early only if the end of the
+/// stream is reached. Returns the number of bytes read.
+pub fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut len = 0;
+
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(len)
+}
+
 /// Helper trait for file-like handles that can be duplicated.
 trait Dup {
     /// Try to duplicate this file descriptor.
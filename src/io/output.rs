@@ -2,17 +2,22 @@ use super::{
     Dup,
     buffers::DiskCacheWriter,
 };
+use crate::compress::formats::Encoder;
 use std::{
     fs::File,
-    io::{self, BufWriter, Result, Seek, SeekFrom, Write},
+    io::{self, BufWriter, Error, Result, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
 };
 
+/// An output stream that might be seekable and might have a file path.
+///
+/// This type is used to abstract over multiple kinds of file sinks.
 pub struct Output(Inner);
 
 enum Inner {
     Direct(BufWriter<File>, Option<PathBuf>),
     Cached(BufWriter<DiskCacheWriter<BufWriter<File>>>),
+    Encoded(BufWriter<Box<dyn Encoder>>),
 }
 
 impl Output {
@@ -46,12 +51,32 @@ impl Output {
         }
     }
 
+    /// Create an output that writes into the given compression encoder.
+    ///
+    /// Outputs created this way are not seekable.
+    pub fn from_encoder(encoder: Box<dyn Encoder>) -> Self {
+        Self(Inner::Encoded(BufWriter::new(encoder)))
+    }
+
     pub fn path(&self) -> Option<&Path> {
         match &self.0 {
             Inner::Direct(_, path) => path.as_deref(),
             _ => None,
         }
     }
+
+    /// Finish writing to this output, flushing all buffered data and finishing
+    /// any compression streams the output is wrapped in.
+    pub fn finish(self) -> Result<()> {
+        match self.0 {
+            Inner::Direct(mut writer, _) => writer.flush(),
+            Inner::Cached(mut writer) => writer.flush(),
+            Inner::Encoded(writer) => writer.into_inner()
+                .map_err(|e| e.into_error())?
+                .finish()?
+                .finish(),
+        }
+    }
 }
 
 impl Write for Output {
@@ -59,6 +84,7 @@ impl Write for Output {
         match &mut self.0 {
             Inner::Direct(writer, _) => writer.write(buf),
             Inner::Cached(writer) => writer.write(buf),
+            Inner::Encoded(writer) => writer.write(buf),
         }
     }
 
@@ -66,6 +92,7 @@ impl Write for Output {
         match &mut self.0 {
             Inner::Direct(writer, _) => writer.flush(),
             Inner::Cached(writer) => writer.flush(),
+            Inner::Encoded(writer) => writer.flush(),
         }
     }
 }
@@ -75,6 +102,9 @@ impl Seek for Output {
         match &mut self.0 {
             Inner::Direct(writer, _) => writer.seek(pos),
             Inner::Cached(writer) => writer.seek(pos),
+            Inner::Encoded(_) => Err(Error::other(
+                "cannot seek within a compressed output stream",
+            )),
         }
     }
 }
@@ -1,64 +1,291 @@
 use crate::{
     archive,
+    archive::{Entry, EntryType, Metadata},
+    compress,
+    format::FormatOptions,
     io::input::Input,
 };
+use serde::Serialize;
 use std::{
     error::Error,
     fmt,
+    io,
     path::PathBuf,
+    str::FromStr,
 };
 use structopt::StructOpt;
 
 const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
+/// Column names used for CSV output.
+const CSV_HEADERS: &[&str] = &[
+    "path",
+    "entry_type",
+    "size",
+    "compressed_size",
+    "modified",
+    "created",
+    "accessed",
+    "unix_mode",
+    "read_only",
+    "hidden",
+    "link_target",
+    "device_major",
+    "device_minor",
+    "archive_format",
+    "compression",
+];
+
 /// List the contents of an archive.
 #[derive(Debug, StructOpt)]
 pub struct Command {
+    /// Output format to list entries in: table, json, jsonl, or csv.
+    ///
+    /// The json format prints a single document containing all entries, while
+    /// jsonl prints one JSON object per entry, per line. These names are also
+    /// accepted by --format, since no archive format uses them.
+    #[structopt(long)]
+    output_format: Option<OutputFormat>,
+
+    #[structopt(flatten)]
+    formats: FormatOptions,
+
     /// Input file ("-" for stdin).
     #[structopt(parse(from_os_str))]
     input: PathBuf,
 }
 
+/// Formats that archive listings can be printed in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum OutputFormat {
+    Table,
+    Json,
+    JsonLines,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            "jsonl" => Ok(Self::JsonLines),
+            "csv" => Ok(Self::Csv),
+            _ => Err(format!("unknown output format: {}", s)),
+        }
+    }
+}
+
+/// Machine-readable description of a single archive entry.
+#[derive(Debug, Serialize)]
+struct EntryRecord {
+    path: String,
+    entry_type: &'static str,
+    size: u64,
+    compressed_size: Option<u64>,
+    modified: Option<String>,
+    created: Option<String>,
+    accessed: Option<String>,
+    unix_mode: Option<u32>,
+    read_only: bool,
+    hidden: bool,
+    link_target: Option<String>,
+    device_major: Option<u32>,
+    device_minor: Option<u32>,
+}
+
+/// A single JSON Lines record, which includes the formats of the archive so
+/// that each line stands on its own.
+#[derive(Debug, Serialize)]
+struct JsonLinesRecord<'a> {
+    #[serde(flatten)]
+    entry: &'a EntryRecord,
+    archive_format: &'a str,
+    compression: &'a [&'a str],
+}
+
+/// JSON document describing an entire archive.
+#[derive(Debug, Serialize)]
+struct JsonDocument<'a> {
+    archive_format: &'a str,
+    compression: &'a [&'a str],
+    entries: Vec<EntryRecord>,
+}
+
 impl Command {
     pub(crate) fn execute(&self, flags: &super::Flags) -> Result<(), Box<dyn Error>> {
-        let input_file = Input::open(&self.input)?;
+        // `--format` chooses either the output format or the archive format.
+        let (output_format, archive_format) = match self.formats.format_id().map(OutputFormat::from_str) {
+            Some(Ok(output_format)) => (self.output_format.unwrap_or(output_format), None),
+            _ => (self.output_format.unwrap_or(OutputFormat::Table), self.formats.archive_format()?),
+        };
 
-        if let Some(mut reader) = archive::open(input_file)? {
-            let mut files = 0;
-            let mut dirs = 0;
-            let mut bytes = 0;
+        let input = Input::open(&self.input)?;
 
-            while let Some(entry) = reader.entry()? {
-                let metadata = entry.metadata();
+        let (mut input, compression) = match self.formats.compression_formats()? {
+            Some(compression) => (compress::decode(input, &compression)?, compression),
+            None => compress::detect_decode(input)?,
+        };
 
-                if metadata.is_dir() {
-                    dirs += 1;
-                } else {
-                    files += 1;
-                    bytes += metadata.size;
+        let format = match archive_format {
+            Some(format) => format,
+            None => match archive::detect(&mut input)? {
+                Some(format) => format,
+                None => {
+                    eprintln!("Unknown format: {}", self.input.display());
+                    return Ok(());
                 }
+            },
+        };
+
+        let mut reader = format.open(input)?;
+        let compression = compression.iter().map(|format| format.id()).collect::<Vec<_>>();
+
+        match output_format {
+            OutputFormat::Table => {
+                let mut files = 0;
+                let mut dirs = 0;
+                let mut bytes = 0;
 
-                println!(
-                    "{:>19}  {:>8}  {}",
-                    EmptyFormat(metadata.modified.clone().map(|dt| dt.format(DATE_FORMAT))),
-                    EmptyFormat(if metadata.is_dir() {
-                        None
+                while let Some(mut entry) = reader.entry()? {
+                    let metadata = entry.metadata();
+
+                    if metadata.is_dir() {
+                        dirs += 1;
                     } else {
-                        Some(size::Size::Bytes(metadata.size).to_string(flags.base(), size::Style::Abbreviated))
-                    }),
-                    entry.path().display(),
-                );
+                        files += 1;
+                        bytes += metadata.size;
+                    }
+
+                    print_table_row(&mut *entry, &metadata, flags)?;
+                }
+
+                println!("{} files, {} directories, totalling {}", files, dirs, size::Size::Bytes(bytes).to_string(flags.base(), size::Style::Smart));
             }
 
-            println!("{} files, {} directories, totalling {}", files, dirs, size::Size::Bytes(bytes).to_string(flags.base(), size::Style::Smart));
-        } else {
-            eprintln!("Unknown format: {}", self.input.display());
+            OutputFormat::Json => {
+                let mut entries = Vec::new();
+
+                while let Some(mut entry) = reader.entry()? {
+                    entries.push(EntryRecord::new(&mut *entry)?);
+                }
+
+                serde_json::to_writer_pretty(io::stdout(), &JsonDocument {
+                    archive_format: format.id(),
+                    compression: &compression,
+                    entries,
+                })?;
+                println!();
+            }
+
+            OutputFormat::JsonLines => {
+                while let Some(mut entry) = reader.entry()? {
+                    println!("{}", serde_json::to_string(&JsonLinesRecord {
+                        entry: &EntryRecord::new(&mut *entry)?,
+                        archive_format: format.id(),
+                        compression: &compression,
+                    })?);
+                }
+            }
+
+            OutputFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(io::stdout());
+                writer.write_record(CSV_HEADERS)?;
+
+                let compression = compression.join(",");
+
+                while let Some(mut entry) = reader.entry()? {
+                    let record = EntryRecord::new(&mut *entry)?;
+
+                    writer.write_record(&[
+                        record.path,
+                        record.entry_type.to_owned(),
+                        record.size.to_string(),
+                        csv_value(record.compressed_size),
+                        csv_value(record.modified),
+                        csv_value(record.created),
+                        csv_value(record.accessed),
+                        csv_value(record.unix_mode),
+                        record.read_only.to_string(),
+                        record.hidden.to_string(),
+                        csv_value(record.link_target),
+                        csv_value(record.device_major),
+                        csv_value(record.device_minor),
+                        format.id().to_owned(),
+                        compression.clone(),
+                    ])?;
+                }
+
+                writer.flush()?;
+            }
         }
 
         Ok(())
     }
 }
 
+fn print_table_row(entry: &mut dyn Entry, metadata: &Metadata, flags: &super::Flags) -> io::Result<()> {
+    let path = entry.path().into_owned();
+
+    // Show where links point to after the path, like `ls -l`.
+    let link = match metadata.entry_type {
+        EntryType::SymbolicLink => entry.read_link()?.map(|target| format!(" -> {}", target.display())),
+        EntryType::HardLink => entry.read_link()?.map(|target| format!(" link to {}", target.display())),
+        _ => None,
+    };
+
+    println!(
+        "{:>19}  {:>8}  {}{}",
+        EmptyFormat(metadata.modified.map(|dt| dt.format(DATE_FORMAT))),
+        EmptyFormat(match metadata.entry_type {
+            EntryType::Directory => None,
+            EntryType::CharacterDevice | EntryType::BlockDevice => metadata.device
+                .map(|(major, minor)| format!("{}, {}", major, minor)),
+            _ => Some(size::Size::Bytes(metadata.size).to_string(flags.base(), size::Style::Abbreviated)),
+        }),
+        path.display(),
+        link.unwrap_or_default(),
+    );
+
+    Ok(())
+}
+
+impl EntryRecord {
+    fn new(entry: &mut dyn Entry) -> io::Result<Self> {
+        let metadata = entry.metadata();
+        let path = entry.path().to_string_lossy().into_owned();
+
+        let link_target = match metadata.entry_type {
+            EntryType::SymbolicLink | EntryType::HardLink => entry.read_link()?
+                .map(|target| target.to_string_lossy().into_owned()),
+            _ => None,
+        };
+
+        Ok(Self {
+            path,
+            entry_type: metadata.entry_type.id(),
+            size: metadata.size,
+            compressed_size: metadata.compressed_size,
+            modified: metadata.modified.map(|dt| dt.to_rfc3339()),
+            created: metadata.created.map(|dt| dt.to_rfc3339()),
+            accessed: metadata.accessed.map(|dt| dt.to_rfc3339()),
+            unix_mode: metadata.unix_mode,
+            read_only: metadata.read_only,
+            hidden: metadata.hidden,
+            link_target,
+            device_major: metadata.device.map(|(major, _)| major),
+            device_minor: metadata.device.map(|(_, minor)| minor),
+        })
+    }
+}
+
+/// Format an optional value for CSV output, using an empty string for `None`.
+fn csv_value<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
 #[derive(Debug)]
 struct EmptyFormat<T>(Option<T>);
 
@@ -71,3 +298,15 @@ impl<T: fmt::Display> fmt::Display for EmptyFormat<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_formats_do_not_shadow_archive_formats() {
+        for format in archive::formats::all() {
+            assert!(OutputFormat::from_str(format.id()).is_err(), "{} is also an output format", format.id());
+        }
+    }
+}
@@ -14,8 +14,9 @@ mod extract;
 mod format;
 mod io;
 mod list;
+mod options;
 mod paths;
-mod util;
+mod test;
 
 pub use io::*;
 
@@ -62,6 +63,9 @@ enum Command {
 
     #[structopt(visible_alias = "x")]
     Extract(extract::Command),
+
+    #[structopt(visible_alias = "t")]
+    Test(test::Command),
 }
 
 impl Flags {
@@ -99,17 +103,18 @@ fn main() -> Result<(), Box<dyn Error>> {
         Command::Create(command) => command.execute(),
         Command::Extract(command) => command.execute(),
         Command::List(command) => command.execute(&options.flags),
+        Command::Test(command) => command.execute(),
         Command::Formats => {
             println!("Archive formats:");
 
             for format in archive::formats::all() {
-                println!("  {}", format);
+                println!("  {:<8} {}", format.id(), format);
             }
 
             println!("Compression stream formats:");
 
             for format in compress::formats::all() {
-                println!("  {}", format);
+                println!("  {:<8} {}", format.id(), format);
             }
 
             Ok(())
@@ -0,0 +1,54 @@
+//! Parsers for values given to command line options.
+
+use std::convert::TryFrom;
+
+/// Parse a size in bytes with an optional binary unit suffix.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => {
+            let multiplier = match s[index..].trim().to_ascii_lowercase().as_str() {
+                "k" | "kib" => 1 << 10,
+                "m" | "mib" => 1 << 20,
+                "g" | "gib" => 1 << 30,
+                unit => return Err(format!("unknown size unit: {}", unit)),
+            };
+
+            (&s[..index], multiplier)
+        }
+        None => (s, 1),
+    };
+
+    digits.parse::<u64>()
+        .ok()
+        .and_then(|size| size.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size: {}", s))
+}
+
+/// Parse a size in bytes that must fit in 32 bits.
+pub fn parse_size_u32(s: &str) -> Result<u32, String> {
+    u32::try_from(parse_size(s)?)
+        .map_err(|_| format!("size too large: {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1440"), Ok(1440));
+        assert_eq!(parse_size("64k"), Ok(64 << 10));
+        assert_eq!(parse_size("8MiB"), Ok(8 << 20));
+        assert_eq!(parse_size("2 GiB"), Ok(2 << 30));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("12q").is_err());
+        assert!(parse_size("k").is_err());
+        assert!(parse_size("99999999999999999999g").is_err());
+    }
+
+    #[test]
+    fn sizes_u32() {
+        assert_eq!(parse_size_u32("1536m"), Ok(1536 << 20));
+        assert!(parse_size_u32("4g").is_err());
+    }
+}
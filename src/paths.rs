@@ -1,7 +1,10 @@
+use glob::Pattern;
 use std::{
     borrow::Cow,
     ffi::{OsStr, OsString},
-    path::{Path, PathBuf},
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::{Component, Path, PathBuf},
 };
 
 #[cfg(unix)]
@@ -32,3 +35,258 @@ pub fn path_from_unix_path_bytes(bytes: Cow<'_, [u8]>) -> Cow<'_, Path> {
         Cow::Owned(bytes) => Cow::Owned(PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())),
     }
 }
+
+/// Get the bytes of a path as they would be stored by archive formats that
+/// represent paths as UNIX paths.
+///
+/// This is the inverse of [`path_from_unix_path_bytes`].
+pub fn path_to_unix_path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    #[cfg(unix)]
+    {
+        Cow::Borrowed(path.as_os_str().as_bytes())
+    }
+
+    // Use forward slashes as separators regardless of the platform.
+    #[cfg(not(unix))]
+    {
+        let path = path.components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        Cow::Owned(path.into_bytes())
+    }
+}
+
+/// Check whether an archive entry path matches the given glob pattern.
+///
+/// If a slash occurs anywhere other than the end of the pattern, the pattern is
+/// matched against the whole path. Otherwise it is matched against just the
+/// file name.
+pub fn matches_pattern(pattern: &Pattern, path: &Path) -> bool {
+    let first_slash = pattern.as_str().find('/');
+
+    if first_slash.is_none() || first_slash == Some(pattern.as_str().len() - 1) {
+        match path.file_name() {
+            Some(file_name) => pattern.matches_path(Path::new(file_name)),
+            None => {
+                log::debug!("couldn't match path against pattern, no filename: {}", path.display());
+                false
+            },
+        }
+    } else {
+        pattern.matches_path(path)
+    }
+}
+
+/// Resolve the path that an archive entry should be extracted to inside of the
+/// given destination directory.
+///
+/// Entry paths in an archive cannot be trusted, and a malicious archive could
+/// otherwise write files anywhere on the file system. Leading root directories
+/// and drive prefixes are stripped so that absolute paths are extracted relative
+/// to `dir`. Paths containing `..` components, or that would be written through
+/// a symbolic link that already exists inside of `dir`, are rejected.
+pub fn resolve_extract_path(dir: &Path, entry_path: &Path) -> Result<PathBuf> {
+    let mut resolved = dir.to_path_buf();
+
+    for component in entry_path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => {
+                log::warn!("removing leading `{}` from entry path {}", component.as_os_str().to_string_lossy(), entry_path.display());
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                return Err(unsafe_path_error(entry_path, "path contains `..`"));
+            }
+            Component::Normal(name) => {
+                // Don't write through a symbolic link that was previously
+                // extracted (or was already present), since it could point
+                // anywhere.
+                if resolved != dir && is_symlink(&resolved) {
+                    return Err(unsafe_path_error(entry_path, "path traverses a symbolic link"));
+                }
+
+                resolved.push(name);
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Check that the target of a symbolic link entry being extracted to `link`
+/// does not point outside of the destination directory `dir`.
+///
+/// Absolute targets are always rejected, as are relative targets with enough
+/// `..` components to climb above `dir`. A `..` component may only step back
+/// out of a directory that already exists as a real directory. Stepping out of
+/// a symbolic link goes to the parent of wherever that link points rather than
+/// to the parent of its location, and a path that does not exist yet could
+/// still be created as a symbolic link by a later entry.
+pub fn check_symlink_target(dir: &Path, link: &Path, target: &Path) -> Result<()> {
+    // Number of directories between the link and the destination directory.
+    let mut depth = link.strip_prefix(dir)
+        .map(|relative| relative.components().count().saturating_sub(1))
+        .unwrap_or(0);
+
+    // The path that the target resolves to so far, and how many of its
+    // components come from the target rather than the link's location, which
+    // has already been checked when the link's path was resolved.
+    let mut resolved = link.parent().unwrap_or(dir).to_path_buf();
+    let mut pushed = 0;
+
+    for component in target.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => {
+                return Err(unsafe_link_error(link, target));
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                depth = depth.checked_sub(1).ok_or_else(|| unsafe_link_error(link, target))?;
+
+                if pushed > 0 {
+                    if !is_real_dir(&resolved) {
+                        return Err(unsafe_link_error(link, target));
+                    }
+
+                    pushed -= 1;
+                }
+
+                resolved.pop();
+            }
+            Component::Normal(name) => {
+                depth += 1;
+                pushed += 1;
+                resolved.push(name);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that the target of a hard link entry being extracted to `link` is not
+/// a symbolic link.
+///
+/// Hard linking to a symbolic link links the symbolic link itself, so writing
+/// the entry's data into the hard link would write through the symbolic link.
+pub fn check_hard_link_target(link: &Path, target: &Path) -> Result<()> {
+    if is_symlink(target) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("refusing to create hard link {} to symbolic link {} (use --allow-unsafe-paths to override)", link.display(), target.display()),
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path)
+        .map(|metadata| metadata.file_type().is_symlink())
+        .unwrap_or(false)
+}
+
+/// Check whether a path is a directory without following symbolic links.
+fn is_real_dir(path: &Path) -> bool {
+    fs::symlink_metadata(path)
+        .map(|metadata| metadata.is_dir())
+        .unwrap_or(false)
+}
+
+fn unsafe_link_error(link: &Path, target: &Path) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("refusing to create symbolic link {} pointing outside of the destination: {} (use --allow-unsafe-paths to override)", link.display(), target.display()),
+    )
+}
+
+fn unsafe_path_error(entry_path: &Path, reason: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("refusing to extract entry {}: {} (use --allow-unsafe-paths to override)", entry_path.display(), reason),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_path_is_inside_destination() {
+        let dir = Path::new("dest");
+
+        assert_eq!(resolve_extract_path(dir, Path::new("a/b.txt")).unwrap(), Path::new("dest/a/b.txt"));
+        assert_eq!(resolve_extract_path(dir, Path::new("./a/./b.txt")).unwrap(), Path::new("dest/a/b.txt"));
+
+        // Absolute paths are extracted relative to the destination.
+        assert_eq!(resolve_extract_path(dir, Path::new("/etc/passwd")).unwrap(), Path::new("dest/etc/passwd"));
+        assert_eq!(resolve_extract_path(dir, Path::new("//a")).unwrap(), Path::new("dest/a"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn extract_path_drive_prefix_is_stripped() {
+        let dir = Path::new("dest");
+
+        assert_eq!(resolve_extract_path(dir, Path::new(r"C:\Windows\win.ini")).unwrap(), Path::new(r"dest\Windows\win.ini"));
+        assert_eq!(resolve_extract_path(dir, Path::new(r"\\server\share\a")).unwrap(), Path::new(r"dest\a"));
+    }
+
+    #[test]
+    fn extract_path_with_parent_is_rejected() {
+        let dir = Path::new("dest");
+
+        for path in &["..", "../a", "a/../../b", "a/../b", "/../a"] {
+            assert!(resolve_extract_path(dir, Path::new(path)).is_err(), "{} was accepted", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_path_through_existing_symlink_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        fs::create_dir(dir.join("a")).unwrap();
+        std::os::unix::fs::symlink("/tmp", dir.join("a/link")).unwrap();
+
+        assert!(resolve_extract_path(dir, Path::new("a/link/file")).is_err());
+        assert!(resolve_extract_path(dir, Path::new("a/link/b/file")).is_err());
+
+        // The link itself may still be replaced.
+        assert_eq!(resolve_extract_path(dir, Path::new("a/link")).unwrap(), dir.join("a/link"));
+
+        // The destination itself may be a link.
+        let link = dir.join("dest");
+        std::os::unix::fs::symlink(dir.join("a"), &link).unwrap();
+        assert_eq!(resolve_extract_path(&link, Path::new("file")).unwrap(), link.join("file"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_target_through_existing_symlink_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        // A link to the destination itself is harmless on its own.
+        check_symlink_target(dir, &dir.join("q"), Path::new(".")).unwrap();
+        std::os::unix::fs::symlink(".", dir.join("q")).unwrap();
+
+        // Counting components, `q/..` looks like it points to the destination,
+        // but since `q` is a link to the destination it points to its parent.
+        assert!(check_symlink_target(dir, &dir.join("p"), Path::new("q/..")).is_err());
+
+        // Links to the existing link are still allowed.
+        check_symlink_target(dir, &dir.join("r"), Path::new("q")).unwrap();
+    }
+
+    #[test]
+    fn symlink_target_outside_destination_is_rejected() {
+        let dir = Path::new("dest");
+
+        assert!(check_symlink_target(dir, &dir.join("a"), Path::new("..")).is_err());
+        assert!(check_symlink_target(dir, &dir.join("a"), Path::new("/etc/passwd")).is_err());
+        check_symlink_target(dir, &dir.join("a/b"), Path::new("../c")).unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{
+    archive,
+    archive::EntryType,
+    compress,
+    format::FormatOptions,
+    io::input::Input,
+};
+use std::{
+    error::Error,
+    io,
+    path::PathBuf,
+};
+use structopt::StructOpt;
+
+/// Test the integrity of an archive without extracting it.
+///
+/// Every entry in the archive is fully read in order to verify any checksums
+/// stored by the archive or compression formats used.
+#[derive(Debug, StructOpt)]
+pub struct Command {
+    #[structopt(flatten)]
+    formats: FormatOptions,
+
+    /// Input file ("-" for stdin)
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+}
+
+impl Command {
+    pub fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let format = self.formats.archive_format()?;
+        let compression = self.formats.compression_formats()?;
+        let input = Input::open(&self.input)?;
+
+        // Archive readers may stop reading before the end of the stream, in
+        // which case any trailing checksums of compression layers would never
+        // be verified. The readers therefore only borrow the decoded stream,
+        // so that whatever they leave unread can be decoded afterwards.
+        let input = match compression {
+            Some(compression) => compress::decode(input, &compression),
+            None => compress::detect_decode(input).map(|(input, _)| input),
+        }.map_err(|e| format!("compressed stream is corrupt: {}", e))?;
+
+        // Inputs still backed by the original file are passed on as-is so that
+        // readers can find any other files belonging to the archive.
+        let mut decoded = None;
+        let reader_input = match input.path() {
+            Some(_) => input,
+            None => decoded.insert(input).by_ref(),
+        };
+
+        let mut reader = match archive::open(reader_input, format, Some(&[]))? {
+            Some(reader) => reader,
+            None => return Err(format!("Unknown format: {}", self.input.display()).into()),
+        };
+
+        let mut tested = 0;
+        let mut failed = 0;
+
+        loop {
+            let mut entry = match reader.entry() {
+                Ok(Some(entry)) => entry,
+                Ok(None) => break,
+                Err(e) => {
+                    // The archive structure itself is damaged, so we can't
+                    // reliably continue reading.
+                    println!("FAILED  <archive>: {}", e);
+                    failed += 1;
+                    break;
+                }
+            };
+
+            let path = entry.path().into_owned();
+            let metadata = entry.metadata();
+            tested += 1;
+
+            match io::copy(&mut entry, &mut io::sink()) {
+                Ok(size) if metadata.entry_type == EntryType::File && size != metadata.size => {
+                    println!("FAILED  {}: expected {} bytes but read {}", path.display(), metadata.size, size);
+                    failed += 1;
+                }
+                Ok(_) => println!("OK      {}", path.display()),
+                Err(e) => {
+                    println!("FAILED  {}: {}", path.display(), e);
+                    failed += 1;
+                }
+            }
+        }
+
+        drop(reader);
+
+        if let Some(input) = &mut decoded {
+            if let Err(e) = io::copy(input, &mut io::sink()) {
+                println!("FAILED  <archive>: compressed stream is corrupt: {}", e);
+                failed += 1;
+            }
+        }
+
+        println!("{} entries tested, {} failed", tested, failed);
+
+        if failed > 0 {
+            Err(format!("{} failed integrity checks", self.input.display()).into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io::Write, path::Path};
+
+    fn test(path: &Path) -> Result<(), Box<dyn Error>> {
+        Command::from_iter(&["test".as_ref(), path.as_os_str()]).execute()
+    }
+
+    #[test]
+    fn zip_entry_checksums_are_verified() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sample.zip");
+
+        let mut writer = zip::ZipWriter::new(fs::File::create(&path).unwrap());
+        writer.start_file("hello.txt", zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored)).unwrap();
+        writer.write_all(b"hello from a test\n").unwrap();
+        writer.finish().unwrap();
+
+        test(&path).unwrap();
+
+        // Damage the stored data, which only the CRC can catch.
+        let mut bytes = fs::read(&path).unwrap();
+        let offset = bytes.windows(6).position(|window| window == b"a test").unwrap();
+        bytes[offset] = b'A';
+        fs::write(&path, bytes).unwrap();
+
+        assert!(test(&path).is_err());
+    }
+
+    #[test]
+    fn compression_trailers_are_verified() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sample.tar.gz");
+
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            fs::File::create(&path).unwrap(),
+            flate2::Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(6);
+        builder.append_data(&mut header, "hello.txt", &b"hello\n"[..]).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        test(&path).unwrap();
+
+        // The gzip CRC follows everything the tar reader reads, including the
+        // end of archive marker.
+        let mut bytes = fs::read(&path).unwrap();
+        let crc = bytes.len() - 8;
+        bytes[crc] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+
+        assert!(test(&path).is_err());
+    }
+}
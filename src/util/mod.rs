@@ -1,3 +0,0 @@
-mod maybe_boxed;
-
-pub(crate) use maybe_boxed::*;
@@ -0,0 +1,97 @@
+# Build the MPQ fixtures, since no tool for creating MPQ archives is readily
+# available. Run from this directory with `python3 make-mpq.py`.
+
+import struct, zlib
+
+table = [0] * 0x500
+seed = 0x00100001
+for i1 in range(256):
+    i2 = i1
+    for _ in range(5):
+        seed = (seed * 125 + 3) % 0x2AAAAB
+        t1 = (seed & 0xFFFF) << 16
+        seed = (seed * 125 + 3) % 0x2AAAAB
+        t2 = seed & 0xFFFF
+        table[i2] = t1 | t2
+        i2 += 256
+
+M = 0xFFFFFFFF
+
+def hash_string(key, off):
+    s1, s2 = 0x7FED7FED, 0xEEEEEEEE
+    for c in key.replace('/', '\\').upper():
+        ch = ord(c)
+        s1 = (table[off + ch] ^ (s1 + s2)) & M
+        s2 = (ch + s1 + s2 + (s2 << 5) + 3) & M
+    return s1
+
+assert hash_string("(listfile)", 0) == 0x5F3DE859
+
+def encrypt(data, seed):
+    out = b''
+    s2 = 0xEEEEEEEE
+    for (v,) in struct.iter_unpack('<I', data):
+        s2 = (s2 + table[0x400 + (seed & 0xff)]) & M
+        out += struct.pack('<I', v ^ ((seed + s2) & M))
+        seed = ((((~seed) << 21) & M) + 0x11111111 & M) | (seed >> 11)
+        s2 = (v + s2 + (s2 << 5) + 3) & M
+    return out
+
+EXISTS, SINGLE_UNIT, COMPRESS = 0x80000000, 0x01000000, 0x200
+SECTOR_SIZE = 512
+
+def single(data):
+    return data, len(data), EXISTS | SINGLE_UNIT
+
+def sectors(data):
+    chunks = [data[i:i + SECTOR_SIZE] for i in range(0, len(data), SECTOR_SIZE)]
+    packed = []
+    for chunk in chunks:
+        c = b'\x02' + zlib.compress(chunk, 9)
+        packed.append(c if len(c) < len(chunk) else chunk)
+    offsets = [(len(chunks) + 1) * 4]
+    for p in packed:
+        offsets.append(offsets[-1] + len(p))
+    body = struct.pack('<%dI' % len(offsets), *offsets) + b''.join(packed)
+    return body, len(data), EXISTS | COMPRESS
+
+def archive(files):
+    data = b''
+    blocks = []
+    for name, (body, size, flags) in files:
+        blocks.append((32 + len(data), len(body), size, flags))
+        data += body
+    count = 8
+    hashes = [(M, M, 0xFFFF, 0xFFFF, M)] * count
+    for index, (name, _) in enumerate(files):
+        i = hash_string(name, 0) & (count - 1)
+        while hashes[i][0] != M:
+            i = (i + 1) % count
+        hashes[i] = (hash_string(name, 0x100), hash_string(name, 0x200), 0, 0, index)
+    hash_table = encrypt(b''.join(struct.pack('<IIHHI', *h) for h in hashes), hash_string("(hash table)", 0x300))
+    block_table = encrypt(b''.join(struct.pack('<IIII', *b) for b in blocks), hash_string("(block table)", 0x300))
+    hash_offset = 32 + len(data)
+    block_offset = hash_offset + len(hash_table)
+    size = block_offset + len(block_table)
+    header = b'MPQ\x1a' + struct.pack('<IIHHIIII', 32, size, 0, 0, hash_offset, block_offset, count, len(blocks))
+    return header + data + hash_table + block_table
+
+lines = ''.join(f'line {i}: the quick brown fox jumps over {i*i%97} lazy dogs\n' for i in range(50)).encode()
+hello = b'hello from a fixture\n'
+files = [
+    ("(listfile)", single(b"hello.txt\r\ndir\\lines.txt\r\nmissing.txt\r\n")),
+    ("hello.txt", single(hello)),
+    ("dir\\lines.txt", sectors(lines)),
+]
+plain = archive(files)
+open('sample.mpq', 'wb').write(plain)
+
+user_data = b'user data before the archive'
+header = b'MPQ\x1b' + struct.pack('<III', len(user_data), 0x200, 16)
+open('sample-user-data.mpq', 'wb').write((header + user_data).ljust(0x200, b'\0') + plain)
+
+body, _, flags = single(hello)
+open('sample-oversized.mpq', 'wb').write(archive([
+    ("(listfile)", single(b"hello.txt\r\n")),
+    ("hello.txt", (body, 0xFFFFFFF0, flags)),
+]))
@@ -0,0 +1,104 @@
+# Build the RAR fixtures, since RAR archives can only be created with the
+# proprietary RAR tool. Entries are stored without compression, which UnRAR
+# reads the same way as compressed ones. Run from this directory with
+# `python3 make-rar.py`.
+
+import struct, zlib
+
+MTIME = 1622548800
+
+lines = ''.join(f'line {i}: the quick brown fox jumps over {i*i%97} lazy dogs\n' for i in range(50)).encode()
+
+# Path, Unix mode, and contents or link target.
+ENTRIES = [
+    ('hello.txt', 0o100640, b'hello from a fixture\n'),
+    ('dir/lines.txt', 0o100644, lines),
+    ('link', 0o120777, b'hello.txt'),
+    ('dir', 0o040755, b''),
+]
+
+
+def crc(data):
+    return zlib.crc32(data) & 0xffffffff
+
+
+def rar4(entries=ENTRIES, encrypted=False):
+    def block(head_type, flags, fields, data=b''):
+        size = 7 + len(fields)
+        header = struct.pack('<BHH', head_type, flags, size) + fields
+        return struct.pack('<H', crc(header) & 0xffff) + header + data
+
+    # 2021-06-01 12:00:00 as a DOS date and time in local time.
+    dos_time = (12 << 11) | (0 << 5) | 0 | ((2021 - 1980) << 25) | (6 << 21) | (1 << 16)
+
+    out = b'Rar!\x1a\x07\x00'
+    out += block(0x73, 0, struct.pack('<HI', 0, 0))
+
+    for name, mode, data in entries:
+        flags = 0x8000
+        if encrypted:
+            flags |= 0x0004
+        if mode & 0o170000 == 0o040000:
+            flags |= 0x00e0
+        name = name.encode()
+        fields = struct.pack('<IIBIIBBHI', len(data), len(data), 3, crc(data), dos_time, 29, 0x30, len(name), mode) + name
+        out += block(0x74, flags, fields, data)
+
+    return out + block(0x7b, 0x4000, b'')
+
+
+def vint(value):
+    out = b''
+    while True:
+        byte = value & 0x7f
+        value >>= 7
+        if value:
+            out += bytes([byte | 0x80])
+        else:
+            return out + bytes([byte])
+
+
+def rar5():
+    def block(head_type, fields, extra=b'', data=None):
+        flags = 0
+        optional = b''
+        if extra:
+            flags |= 0x0001
+            optional += vint(len(extra))
+        if data is not None:
+            flags |= 0x0002
+            optional += vint(len(data))
+        body = vint(head_type) + vint(flags) + optional + fields + extra
+        header = vint(len(body)) + body
+        return struct.pack('<I', crc(header)) + header + (data or b'')
+
+    out = b'Rar!\x1a\x07\x01\x00'
+    out += block(1, vint(0))
+
+    for name, mode, data in ENTRIES:
+        extra = b''
+        if mode & 0o170000 == 0o120000:
+            # File system redirection record for a Unix symbolic link, which
+            # holds the target instead of the data.
+            record = vint(5) + vint(1) + vint(0) + vint(len(data)) + data
+            extra = vint(len(record)) + record
+            data = b''
+
+        is_dir = mode & 0o170000 == 0o040000
+        file_flags = 0x0002 | (0x0001 if is_dir else 0x0004)
+        name = name.encode()
+        fields = vint(file_flags) + vint(len(data)) + vint(mode) + struct.pack('<I', MTIME)
+        if not is_dir:
+            fields += struct.pack('<I', crc(data))
+        fields += vint(0) + vint(1) + vint(len(name)) + name
+        out += block(2, fields, extra, data if data else None)
+
+    return out + block(5, vint(0))
+
+
+open('sample-rar4.rar', 'wb').write(rar4())
+open('sample-rar5.rar', 'wb').write(rar5())
+
+# The data is not actually encrypted, since the entry is only checked for
+# being encrypted.
+open('sample-encrypted.rar', 'wb').write(rar4(ENTRIES[:1], encrypted=True))